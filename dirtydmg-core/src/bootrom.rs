@@ -0,0 +1,177 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::bus::BusRW;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
+
+/// Overlays the boot ROM on top of the low bytes of the cartrige.
+///
+/// The device is mapped over the overlay range and the 0xFF50 register, with
+/// the cartrige mapped around it. While the overlay is active reads in the
+/// range come from the boot ROM, once 0xFF50 is written they fall through to
+/// the cartrige. Writes always go to the cartrige since they are mapper
+/// register writes.
+pub struct BootRom {
+    data: Vec<u8>,
+    target: Rc<RefCell<dyn BusRW>>,
+    active: bool,
+}
+
+impl BootRom {
+    /// Address of the register that unmaps the boot ROM.
+    pub const DISABLE_ADDRESS: usize = 0xFF50;
+    /// Size of the DMG boot ROM.
+    pub const DMG_SIZE: usize = 0x100;
+    const STATE_MAGIC: &'static [u8;4] = b"DBRM";
+    const STATE_VERSION: u16 = 1;
+
+    /// Creates an inactive overlay over `target`.
+    pub fn new(target: Rc<RefCell<dyn BusRW>>) -> BootRom {
+        BootRom {
+            data: Vec::new(),
+            target,
+            active: false,
+        }
+    }
+
+    /// Loads the boot ROM image and maps it in.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != BootRom::DMG_SIZE {
+            return Err(format!("Boot ROM must be {} bytes, got {}.", BootRom::DMG_SIZE, data.len()));
+        }
+        self.data = data.to_vec();
+        self.active = true;
+        Ok(())
+    }
+
+    /// True while the boot ROM is mapped in.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+impl BusRW for BootRom {
+    fn bus_read8(&mut self, addr: usize) -> u8 {
+        match addr {
+            BootRom::DISABLE_ADDRESS => {
+                if self.active {0xFE} else {0xFF}
+            }
+            _ if self.active && addr < self.data.len() => self.data[addr],
+            _ => self.target.as_ref().borrow_mut().bus_read8(addr),
+        }
+    }
+
+    fn bus_peek8(&self, addr: usize) -> u8 {
+        match addr {
+            BootRom::DISABLE_ADDRESS => {
+                if self.active {0xFE} else {0xFF}
+            }
+            _ if self.active && addr < self.data.len() => self.data[addr],
+            _ => self.target.as_ref().try_borrow().map_or(0xFF, |target| target.bus_peek8(addr)),
+        }
+    }
+
+    fn bus_write8(&mut self, addr: usize, value: u8) {
+        match addr {
+            // Any non-zero write unmaps the boot ROM until the next reset.
+            BootRom::DISABLE_ADDRESS => {
+                if value != 0 {
+                    self.active = false;
+                }
+            }
+            _ => self.target.as_ref().borrow_mut().bus_write8(addr, value),
+        }
+    }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+/// Only whether the overlay is mapped is saved, the image itself is loaded
+/// by the host like the cartrige ROM.
+impl Snapshotable for BootRom {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(BootRom::STATE_MAGIC, BootRom::STATE_VERSION);
+        w.write_bool(self.active);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, BootRom::STATE_MAGIC)?;
+        if version != BootRom::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let active = r.read_bool()?;
+        r.finish()?;
+        if active && self.data.is_empty() {
+            return Err(StateError::Invalid("the boot ROM was mapped but no image is loaded".to_owned()));
+        }
+        self.active = active;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, BusItem};
+    use crate::ram::Ram;
+
+    fn test_bus() -> (Bus, Rc<RefCell<BootRom>>, Rc<RefCell<Ram>>) {
+        let cart = Rc::new(RefCell::new(Ram::new(0x8000, 0)));
+        for addr in 0..0x200 {
+            cart.borrow_mut().bus_write8(addr, 0xC0 | (addr as u8 & 0xF));
+        }
+        let boot = Rc::new(RefCell::new(BootRom::new(cart.clone())));
+
+        let mut bus = Bus::new();
+        bus.add_item(BusItem::new(0x0000, 0x00FF, boot.clone())).unwrap();
+        bus.add_item(BusItem::new(0x0100, 0x7FFF, cart.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF50, 0xFF50, boot.clone())).unwrap();
+        (bus, boot, cart)
+    }
+
+    #[test]
+    fn test_boot_rom_overlay() {
+        let (mut bus, boot, _) = test_bus();
+        let image: Vec<u8> = (0..0x100).map(|x| x as u8).collect();
+        boot.borrow_mut().load(&image).unwrap();
+
+        assert_eq!(bus.bus_read8(0x0000), 0x00);
+        assert_eq!(bus.bus_read8(0x00FF), 0xFF);
+        // The cartrige header is visible while the boot ROM runs.
+        assert_eq!(bus.bus_read8(0x0104), 0xC4);
+        assert_eq!(bus.bus_peek8(0x00FF), 0xFF);
+        assert_eq!(bus.bus_peek8(0x0104), 0xC4);
+
+        bus.bus_write8(BootRom::DISABLE_ADDRESS, 1);
+        assert!(!boot.borrow().is_active());
+        assert_eq!(bus.bus_read8(0x0000), 0xC0);
+        assert_eq!(bus.bus_read8(0x00FF), 0xCF);
+
+        assert_eq!(bus.bus_peek8(0x00FF), 0xCF);
+
+        // Writing zero afterwards doesn't map it back in.
+        bus.bus_write8(BootRom::DISABLE_ADDRESS, 0);
+        assert_eq!(bus.bus_read8(0x0000), 0xC0);
+    }
+
+    #[test]
+    fn test_writes_reach_cartrige() {
+        let (mut bus, boot, cart) = test_bus();
+        boot.borrow_mut().load(&[0u8; 0x100]).unwrap();
+
+        bus.bus_write8(0x0000, 0x0A);
+        assert_eq!(cart.borrow_mut().bus_read8(0x0000), 0x0A);
+        assert_eq!(bus.bus_read8(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_inactive_without_image() {
+        let (mut bus, boot, _) = test_bus();
+        assert!(!boot.borrow().is_active());
+        assert_eq!(bus.bus_read8(0x0010), 0xC0);
+        assert!(boot.borrow_mut().load(&[0u8; 0x80]).is_err());
+        assert!(!boot.borrow().is_active());
+    }
+}
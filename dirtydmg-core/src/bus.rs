@@ -1,11 +1,21 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 
 // A trait that lets data be written and read from an address.
 pub trait BusRW{
     fn bus_write8(&mut self, addr:usize, value:u8);
     fn bus_read8(&mut self, addr:usize) -> u8;
 
+    /// Reads a byte for inspection, without any side effects.
+    /// 
+    /// Unlike bus_read8 this ignores access restrictions, such as VRAM 
+    /// being locked while the PPU draws, and never changes device state. 
+    /// Devices that can't be inspected read as 0xFF.
+    fn bus_peek8(&self, _addr: usize) -> u8 {
+        0xFF
+    }
+
     /// Default 16bit read implementation made of 2 8 bit reads.
     fn bus_read16(&mut self, addr: usize) -> u16 {
         let high_byte = self.bus_read8(addr + 1) as u16;
@@ -17,6 +27,39 @@ pub trait BusRW{
         self.bus_write8(addr, value as u8);
         self.bus_write8(addr + 1, (value >> 8) as u8);
     }
+
+    /// Advances the device by a number of clock ticks.
+    /// 
+    /// Devices that only respond to accesses don't need to implement this.
+    fn tick(&mut self, _ticks: u32) {
+    }
+
+    /// Called when the CPU increments or decrements a 16 bit register 
+    /// holding `addr`.
+    /// 
+    /// Nothing is read or written, but the register is put on the address 
+    /// bus, which is enough to trigger the DMG OAM bug. Only the CPU's view
+    /// of the bus needs to implement this.
+    fn bus_idu_access(&mut self, _addr: usize) {
+    }
+
+    /// Gets the device's save state support.
+    /// 
+    /// Devices without any state of their own, such as mirrors, return None
+    /// and are left out of Bus::save_state.
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        None
+    }
+
+    /// Takes the first access that failed inside the device since the last
+    /// call.
+    /// 
+    /// Devices that pass accesses on to another device, such as mirrors, 
+    /// report a busy target here. The Bus checks it after every access and
+    /// keeps it for Bus::take_fault.
+    fn take_fault(&mut self) -> Option<BusError> {
+        None
+    }
 }
 
 // An item to encapsulate a device attached to a bus.
@@ -42,45 +85,528 @@ impl BusItem {
         self.start_addr <= addr && self.end_addr >= addr
     }
 
+    /// Checks if any address is shared with another item.
+    pub fn overlaps(&self, other: &BusItem) -> bool
+    {
+        self.start_addr <= other.end_addr && other.start_addr <= self.end_addr
+    }
+
+    /// Borrows the device for an access.
+    /// 
+    /// Fails if the device is already borrowed, which happens when an access
+    /// re-enters a device that is in the middle of its own update.
+    fn device(&self, addr: usize) -> Result<std::cell::RefMut<'_, dyn BusRW + 'static>, BusError>
+    {
+        self.item.as_ref().try_borrow_mut().map_err(|_| BusError::Reentrant{addr})
+    }
+
+    pub fn try_bus_write8(&self, addr: usize, value: u8) -> Result<(), BusError>
+    {
+        let mut device = self.device(addr)?;
+        device.bus_write8(addr, value);
+        device.take_fault().map_or(Ok(()), Err)
+    }
+
+    pub fn try_bus_read8(&self, addr: usize) -> Result<u8, BusError>
+    {
+        let mut device = self.device(addr)?;
+        let value = device.bus_read8(addr);
+        device.take_fault().map_or(Ok(value), Err)
+    }
+
+    /// Writes to the device, dropping the write if it is already borrowed.
     pub fn bus_write8(&self, addr: usize, value: u8)
     {
-        self.item.as_ref().borrow_mut().bus_write8(addr, value);
+        let _ = self.try_bus_write8(addr, value);
     }
 
     pub fn bus_write16(&self, addr: usize, value: u16)
     {
-        self.item.as_ref().borrow_mut().bus_write16(addr, value);
+        if let Ok(mut device) = self.device(addr) {
+            device.bus_write16(addr, value);
+        }
+    }
+
+    /// Peeks at the device, reading 0xFF if it is already mutably borrowed.
+    pub fn bus_peek8(&self, addr: usize) -> u8
+    {
+        self.item.as_ref().try_borrow().map_or(0xFF, |device| device.bus_peek8(addr))
     }
 
+    /// Reads from the device, reading 0xFF if it is already borrowed.
     pub fn bus_read8(&self, addr: usize) -> u8
     {
-        return self.item.as_ref().borrow_mut().bus_read8(addr);
+        self.try_bus_read8(addr).unwrap_or(0xFF)
     }
 
     pub fn bus_read16(&self, addr:usize) -> u16
     {
-        return self.item.as_ref().borrow_mut().bus_read16(addr);
+        self.device(addr).map_or(0xFFFF, |mut device| device.bus_read16(addr))
     }
 }
 
+/// A device that redirects accesses to another device at a fixed offset.
+/// 
+/// Used for mirrored regions like echo RAM, where an access to `addr` is 
+/// passed to the target as `addr - offset`. If the target is already 
+/// borrowed the read returns 0xFF or the write is dropped, and the fault is
+/// reported through take_fault.
+pub struct MirrorDevice {
+    target: Rc<RefCell<dyn BusRW>>,
+    offset: usize,
+    /// The first access that found the target busy since the last take.
+    fault: Option<BusError>,
+}
+
+impl MirrorDevice {
+    pub fn new(target: Rc<RefCell<dyn BusRW>>, offset: usize) -> MirrorDevice
+    {
+        MirrorDevice {
+            target,
+            offset,
+            fault: None,
+        }
+    }
+}
+
+impl BusRW for MirrorDevice {
+    fn bus_write8(&mut self, addr:usize, value:u8)
+    {
+        match self.target.as_ref().try_borrow_mut() {
+            Ok(mut target) => target.bus_write8(addr - self.offset, value),
+            Err(_) => {
+                self.fault.get_or_insert(BusError::Reentrant{addr});
+            }
+        }
+    }
+
+    fn bus_read8(&mut self, addr:usize) -> u8
+    {
+        match self.target.as_ref().try_borrow_mut() {
+            Ok(mut target) => target.bus_read8(addr - self.offset),
+            Err(_) => {
+                self.fault.get_or_insert(BusError::Reentrant{addr});
+                0xFF
+            }
+        }
+    }
+
+    fn bus_peek8(&self, addr:usize) -> u8
+    {
+        self.target.as_ref().try_borrow().map_or(0xFF, |target| target.bus_peek8(addr - self.offset))
+    }
+
+    fn take_fault(&mut self) -> Option<BusError> {
+        self.fault.take()
+    }
+}
+
+/// A device for deliberately unused ranges, such as unused I/O registers.
+/// 
+/// Reads always return the same value, and writes are ignored.
+pub struct OpenBus {
+    value: u8,
+}
+
+impl OpenBus {
+    /// Creates a device that reads as `value`, normally 0xFF.
+    pub fn new(value: u8) -> OpenBus
+    {
+        OpenBus {
+            value
+        }
+    }
+}
+
+impl BusRW for OpenBus {
+    fn bus_write8(&mut self, _addr:usize, _value:u8)
+    {
+    }
+
+    fn bus_read8(&mut self, _addr:usize) -> u8
+    {
+        self.value
+    }
+
+    fn bus_peek8(&self, _addr:usize) -> u8
+    {
+        self.value
+    }
+}
+
+/// Errors from changing the devices attached to a bus.
+#[derive(Debug, PartialEq)]
+pub enum BusError {
+    /// The new item's range shares addresses with an existing member.
+    Overlap { start_addr: usize, end_addr: usize },
+    /// An access reached a device that was already borrowed.
+    Reentrant { addr: usize },
+}
+
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BusError::Overlap{start_addr, end_addr} => write!(f, 
+                "bus range 0x{:04X}-0x{:04X} overlaps an existing member", start_addr, end_addr),
+            BusError::Reentrant{addr} => write!(f,
+                "access to 0x{:04X} re-entered a device already in use", addr),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+/// The direction of a bus access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A single byte access made through a bus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusAccess {
+    pub addr: usize,
+    /// The value read or written.
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+/// The accesses a watchpoint triggers on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, kind: AccessKind) -> bool {
+        match self {
+            WatchKind::Read => kind == AccessKind::Read,
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// Called with every access made through a bus.
+pub type TraceHook = Box<dyn FnMut(BusAccess)>;
+
+/// Provides the value read from an address with no device attached.
+pub type OpenBusRead = Box<dyn Fn(usize) -> u8>;
+
+/// Routes accesses to the devices mapped on it.
+/// 
+/// Addresses are masked before routing, to 16 bits by default. A masked
+/// address with no device behind it is unmapped: reads resolve through the
+/// open bus policy, 0xFF unless one is set, and writes are dropped. Neither
+/// is a fault. This covers the gaps in the I/O page at 0xFF00-0xFF7F, which
+/// read 0xFF on hardware unless a device is mapped over a register.
 pub struct Bus {
-    members: Vec<BusItem>
+    members: Vec<BusItem>,
+    /// Applied to every address before it is routed.
+    addr_mask: usize,
+    /// Unmapped read policy, reads are 0xFF if not set.
+    open_bus: Option<OpenBusRead>,
+    /// Debug hook for watching accesses.
+    trace: Option<TraceHook>,
+    watchpoints: Vec<(usize, WatchKind)>,
+    /// Accesses that hit a watchpoint since they were last taken.
+    triggered: Vec<BusAccess>,
+    /// The first access that failed since faults were last taken.
+    fault: Option<BusError>,
 }
 
 impl Bus {
+    /// Wraps addresses to the 16 bit address space.
+    pub const DEFAULT_ADDR_MASK: usize = 0xFFFF;
+    /// Magic bytes at the start of a saved bus state.
+    const STATE_MAGIC: &'static [u8;4] = b"DBUS";
+    /// The current binary format version.
+    const STATE_VERSION: u16 = 1;
+
     pub fn new()->Bus {
         Bus {
-            members: Vec::new()
+            members: Vec::new(),
+            addr_mask: Bus::DEFAULT_ADDR_MASK,
+            open_bus: None,
+            trace: None,
+            watchpoints: Vec::new(),
+            triggered: Vec::new(),
+            fault: None,
         }
     }
 
+    /// Watches an address for the given kind of access.
+    /// 
+    /// Matching accesses are collected until taken with 
+    /// take_triggered_watchpoints.
+    pub fn add_watchpoint(&mut self, addr: usize, kind: WatchKind)
+    {
+        self.watchpoints.push((addr, kind));
+    }
+
+    /// Removes all watchpoints on an address.
+    pub fn remove_watchpoint(&mut self, addr: usize)
+    {
+        self.watchpoints.retain(|(x, _)| *x != addr);
+    }
+
+    /// Takes the accesses that have triggered watchpoints, in order.
+    pub fn take_triggered_watchpoints(&mut self) -> Vec<BusAccess>
+    {
+        std::mem::take(&mut self.triggered)
+    }
+
+    /// Takes the first fault since the last call, if any.
+    /// 
+    /// An access that re-enters a device that is already borrowed, such as
+    /// an OAM DMA reading from the PPU while it is updating, doesn't panic.
+    /// The read returns 0xFF or the write is dropped, and the fault is kept
+    /// here to be reported.
+    pub fn take_fault(&mut self) -> Option<BusError>
+    {
+        self.fault.take()
+    }
+
+    /// Records a failed access, keeping the first until taken.
+    fn record_fault(&mut self, fault: BusError)
+    {
+        if self.fault.is_none() {
+            self.fault = Some(fault);
+        }
+    }
+
+    /// Passes an access to the trace hook and watchpoints.
+    fn observe(&mut self, access: BusAccess)
+    {
+        if let Some(trace) = self.trace.as_mut() {
+            trace(access);
+        }
+        if !self.watchpoints.is_empty() && 
+            self.watchpoints.iter().any(|(addr, kind)| *addr == access.addr && kind.matches(access.kind)) {
+            self.triggered.push(access);
+        }
+    }
+
+    /// Sets a hook called with every 8 bit read and write, None removes it.
+    /// 
+    /// 16 bit accesses are reported one byte at a time.
+    pub fn set_trace(&mut self, trace: Option<TraceHook>)
+    {
+        self.trace = trace;
+    }
+
+    /// Sets the mask applied to addresses before they are routed.
+    /// 
+    /// By default addresses wrap at 16 bits, so a stray access past 0xFFFF
+    /// aliases the bottom of the address space instead of missing.
+    pub fn set_addr_mask(&mut self, mask: usize)
+    {
+        self.addr_mask = mask;
+    }
+
+    /// Sets how reads from unmapped addresses are resolved.
+    /// 
+    /// The function is given the address being read. Passing None restores
+    /// the default of reading 0xFF.
+    pub fn set_open_bus(&mut self, open_bus: Option<OpenBusRead>)
+    {
+        self.open_bus = open_bus;
+    }
+
+    /// Finds the member mapped to an address.
+    /// 
+    /// Members are kept sorted by start address, so the only candidate is the
+    /// last member starting at or before the address.
     fn get_item(&self, addr:usize)->Option<&BusItem>{
-        self.members.iter().find(|&x|x.in_range(addr))
+        let index = self.members.partition_point(|x| x.start_addr <= addr);
+        if index == 0 {
+            return None;
+        }
+        Some(&self.members[index - 1]).filter(|x| x.in_range(addr))
     }
 
-    pub fn add_item(&mut self, item:BusItem)
+    /// Checks if an access to an address would reach a device, without 
+    /// making the access.
+    /// 
+    /// The address is masked the same way as a read or write.
+    pub fn is_mapped(&self, addr: usize) -> bool
     {
-        self.members.push(item);
+        self.get_item(addr & self.addr_mask).is_some()
+    }
+
+    /// Gets the handle of the device mapped to an address.
+    /// 
+    /// Lets a device be reached after wiring without keeping a clone of its 
+    /// handle. Returns None if the address is unmapped. The address is 
+    /// masked the same way as a read or write.
+    pub fn device_at(&self, addr: usize) -> Option<Rc<RefCell<dyn BusRW>>>
+    {
+        self.get_item(addr & self.addr_mask).map(|x| x.item.clone())
+    }
+
+    /// Peeks at every address from `start` to `end` inclusive.
+    /// 
+    /// Nothing is triggered by the reads, so this is safe for a debugger to
+    /// dump any part of the address space, registers included.
+    pub fn dump_range(&self, start: usize, end: usize) -> Vec<u8>
+    {
+        (start..=end).map(|addr| self.bus_peek8(addr)).collect()
+    }
+
+    /// Gets the number of members attached to the bus.
+    pub fn len(&self) -> usize
+    {
+        self.members.len()
+    }
+
+    /// True if no members are attached to the bus.
+    pub fn is_empty(&self) -> bool
+    {
+        self.members.is_empty()
+    }
+
+    /// Iterates over the inclusive (start, end) address range of each 
+    /// member, in address order.
+    pub fn ranges(&self) -> impl Iterator<Item = (usize, usize)> + '_
+    {
+        self.members.iter().map(|x| (x.start_addr, x.end_addr))
+    }
+
+    /// Iterates over the members, skipping any whose device was already 
+    /// seen at a lower address.
+    fn unique_members(&self) -> impl Iterator<Item = &BusItem>
+    {
+        self.members.iter().enumerate()
+            .filter(move |(index, member)| 
+                !self.members[..*index].iter().any(|x| Rc::ptr_eq(&x.item, &member.item)))
+            .map(|(_, member)| member)
+    }
+
+    /// Saves the state of every device on the bus that supports it.
+    /// 
+    /// Each device is saved once, tagged with the lowest address it is 
+    /// mapped at. Fails if a device is borrowed elsewhere.
+    pub fn save_state(&self) -> Result<Vec<u8>, BusError>
+    {
+        let mut devices = Vec::new();
+        for member in self.unique_members() {
+            if let Some(device) = member.device(member.start_addr)?.snapshot() {
+                devices.push((member.start_addr, device.save_state()));
+            }
+        }
+
+        let mut w = StateWriter::new(Bus::STATE_MAGIC, Bus::STATE_VERSION);
+        w.write_u16(devices.len() as u16);
+        for (start_addr, state) in devices.iter() {
+            w.write_u32(*start_addr as u32);
+            w.write_u32(state.len() as u32);
+            w.write_bytes(state);
+        }
+        Ok(w.finish())
+    }
+
+    /// Restores a state saved by `save_state`.
+    /// 
+    /// The bus must have the same devices mapped at the same addresses. 
+    /// Nothing is changed if the blob is malformed or doesn't match the 
+    /// bus, but a device rejecting its own state leaves the devices before
+    /// it restored.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError>
+    {
+        let (mut r, version) = StateReader::new(data, Bus::STATE_MAGIC)?;
+        if version != Bus::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut states = Vec::new();
+        for _ in 0..r.read_u16()? {
+            let start_addr = r.read_u32()? as usize;
+            let len = r.read_u32()? as usize;
+            states.push((start_addr, r.read_bytes(len)?));
+        }
+        r.finish()?;
+
+        let mut devices = Vec::new();
+        for member in self.unique_members() {
+            let mut device = member.device(member.start_addr).map_err(|_| StateError::Invalid(
+                format!("device at {:#X} is busy", member.start_addr)))?;
+            if device.snapshot().is_some() {
+                devices.push((member.start_addr, member.item.clone()));
+            }
+        }
+        if devices.len() != states.len() || 
+            devices.iter().zip(states.iter()).any(|((x, _), (y, _))| x != y) {
+            return Err(StateError::Invalid("saved devices don't match the bus".to_string()));
+        }
+
+        for ((_, device), (_, state)) in devices.iter().zip(states.iter()) {
+            if let Some(device) = device.borrow_mut().snapshot() {
+                device.load_state(state)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ticks every device on the bus.
+    /// 
+    /// A device mapped to several ranges is only ticked once. Devices that
+    /// are already borrowed are skipped, and the fault is recorded.
+    pub fn tick_all(&mut self, ticks: u32)
+    {
+        let mut fault = None;
+        for member in self.unique_members() {
+            match member.device(member.start_addr) {
+                Ok(mut device) => device.tick(ticks),
+                Err(error) => {
+                    fault.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(fault) = fault {
+            self.record_fault(fault);
+        }
+    }
+
+    /// Attaches a member to the bus.
+    /// 
+    /// Fails if the member's range overlaps any existing member.
+    pub fn add_item(&mut self, item:BusItem) -> Result<(), BusError>
+    {
+        if self.members.iter().any(|x| x.overlaps(&item)) {
+            return Err(BusError::Overlap{start_addr: item.start_addr, end_addr: item.end_addr});
+        }
+        let index = self.members.partition_point(|x| x.start_addr <= item.start_addr);
+        self.members.insert(index, item);
+        Ok(())
+    }
+
+    /// Detaches the member starting at the given address.
+    /// 
+    /// Returns the removed member, or None if no member starts there.
+    pub fn remove_item(&mut self, start_addr: usize) -> Option<BusItem>
+    {
+        let index = self.members.iter().position(|x| x.start_addr == start_addr)?;
+        Some(self.members.remove(index))
+    }
+
+    /// Swaps in a new member in place of the one it overlaps.
+    /// 
+    /// Returns the replaced member. If nothing overlaps the new item, it is
+    /// simply added and None is returned. Fails without changing the bus if
+    /// the new item overlaps more than one member.
+    pub fn replace_item(&mut self, item: BusItem) -> Result<Option<BusItem>, BusError>
+    {
+        let mut overlapping = self.members.iter().enumerate().filter(|(_, x)| x.overlaps(&item));
+        let index = overlapping.next().map(|(index, _)| index);
+        if overlapping.next().is_some() {
+            return Err(BusError::Overlap{start_addr: item.start_addr, end_addr: item.end_addr});
+        }
+
+        let replaced = index.map(|index| self.members.remove(index));
+        self.add_item(item)?;
+        Ok(replaced)
     }
 }
 
@@ -94,16 +620,596 @@ impl BusRW for Bus {
 
     fn bus_write8(&mut self, addr:usize, value:u8)
     {
-        if let Some(item) = self.get_item(addr) {
-            item.bus_write8(addr, value);
+        let addr = addr & self.addr_mask;
+        self.observe(BusAccess{addr, value, kind: AccessKind::Write});
+        let result = match self.get_item(addr) {
+            Some(item) => item.try_bus_write8(addr, value),
+            None => Ok(()),
+        };
+        if let Err(fault) = result {
+            self.record_fault(fault);
         }
     }
 
     fn bus_read8(&mut self, addr:usize) -> u8
     {
-        match self.get_item(addr){
-            Some(x) => x.bus_read8(addr),
-            None => 0xff
+        let addr = addr & self.addr_mask;
+        let value = match self.get_item(addr).map(|x| x.try_bus_read8(addr)){
+            Some(Ok(value)) => value,
+            Some(Err(fault)) => {
+                self.record_fault(fault);
+                0xff
+            }
+            None => match &self.open_bus {
+                Some(open_bus) => open_bus(addr),
+                None => 0xff
+            }
+        };
+        self.observe(BusAccess{addr, value, kind: AccessKind::Read});
+        value
+    }
+
+    /// Peeks at the device mapped to an address.
+    /// 
+    /// Watchpoints and the trace hook don't see peeks. Unmapped addresses
+    /// resolve through the open bus policy.
+    fn bus_peek8(&self, addr: usize) -> u8
+    {
+        let addr = addr & self.addr_mask;
+        match self.get_item(addr) {
+            Some(item) => item.bus_peek8(addr),
+            None => match &self.open_bus {
+                Some(open_bus) => open_bus(addr),
+                None => 0xff
+            }
+        }
+    }
+
+    /// Reads a little endian 16 bit value.
+    /// 
+    /// Each byte is routed on its own, so a read that straddles two members
+    /// takes the low byte from the first and the high byte from the second.
+    /// The low byte is read first.
+    fn bus_read16(&mut self, addr: usize) -> u16
+    {
+        let low_byte = self.bus_read8(addr) as u16;
+        let high_byte = self.bus_read8(addr + 1) as u16;
+        (high_byte << 8) | low_byte
+    }
+
+    /// Writes a little endian 16 bit value.
+    /// 
+    /// Each byte is routed on its own, the same as bus_read16. The low byte
+    /// is written first.
+    fn bus_write16(&mut self, addr: usize, value: u16)
+    {
+        self.bus_write8(addr, value as u8);
+        self.bus_write8(addr + 1, (value >> 8) as u8);
+    }
+
+    fn tick(&mut self, ticks: u32)
+    {
+        self.tick_all(ticks);
+    }
+
+    /// Passes faults up when the bus is itself mapped on another bus.
+    fn take_fault(&mut self) -> Option<BusError>
+    {
+        self.fault.take()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ram::Ram;
+
+    fn ram_item(start_addr: usize, end_addr: usize) -> BusItem {
+        let ram = Rc::new(RefCell::new(Ram::new(end_addr - start_addr + 1, start_addr)));
+        BusItem::new(start_addr, end_addr, ram)
+    }
+
+    #[test]
+    fn test_device_at() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        bus.add_item(ram_item(0xD000, 0xD0FF)).unwrap();
+
+        // The handle reaches the device directly, without the bus.
+        let device = bus.device_at(0xC080).unwrap();
+        device.borrow_mut().bus_write8(0xC010, 0x12);
+        assert_eq!(bus.bus_read8(0xC010), 0x12);
+        assert_eq!(bus.bus_read8(0xD010), 0);
+
+        assert!(Rc::ptr_eq(&device, &bus.device_at(0xC0FF).unwrap()));
+        assert!(!Rc::ptr_eq(&device, &bus.device_at(0xD000).unwrap()));
+        assert!(bus.device_at(0xC100).is_none());
+    }
+
+    #[test]
+    fn test_reentrant_access_is_a_fault() {
+        let mut bus = Bus::new();
+        let ram = Rc::new(RefCell::new(Ram::new(0x100, 0xC000)));
+        bus.add_item(BusItem::new(0xC000, 0xC0FF, ram.clone())).unwrap();
+        bus.bus_write8(0xC010, 0x12);
+        assert_eq!(bus.take_fault(), None);
+
+        // Accesses while the device is borrowed, as when a device's own 
+        // update accesses the bus, fail without panicking.
+        {
+            let _held = ram.borrow_mut();
+            assert_eq!(bus.bus_read8(0xC010), 0xFF);
+            bus.bus_write8(0xC011, 0x34);
+            assert_eq!(bus.bus_read16(0xC010), 0xFFFF);
+        }
+        assert_eq!(bus.take_fault(), Some(BusError::Reentrant{addr: 0xC010}));
+        assert_eq!(bus.take_fault(), None);
+
+        // The dropped write never reached the device.
+        assert_eq!(bus.bus_read8(0xC010), 0x12);
+        assert_eq!(bus.bus_read8(0xC011), 0x00);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_is_mapped() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0x0000, 0x00FF)).unwrap();
+        bus.add_item(ram_item(0xC000, 0xDFFF)).unwrap();
+
+        assert!(bus.is_mapped(0x0000));
+        assert!(bus.is_mapped(0x00FF));
+        assert!(!bus.is_mapped(0x0100));
+        assert!(!bus.is_mapped(0xBFFF));
+        assert!(bus.is_mapped(0xC000));
+        assert!(bus.is_mapped(0xDFFF));
+        assert!(!bus.is_mapped(0xE000));
+        // Addresses past 16 bits wrap like any access.
+        assert!(bus.is_mapped(0x10000));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        bus.bus_write8(0xC010, 0x12);
+        bus.add_watchpoint(0xC010, WatchKind::Read);
+
+        assert_eq!(bus.bus_peek8(0xC010), 0x12);
+        assert_eq!(bus.bus_peek8(0x1C010), 0x12);
+        assert_eq!(bus.bus_peek8(0x0000), 0xFF);
+        // Peeks aren't accesses.
+        assert!(bus.take_triggered_watchpoints().is_empty());
+
+        bus.set_open_bus(Some(Box::new(|addr| (addr >> 8) as u8)));
+        assert_eq!(bus.bus_peek8(0x1234), 0x12);
+    }
+
+    #[test]
+    fn test_dump_range() {
+        use crate::ppu::PPU;
+
+        let mut bus = Bus::new();
+        let wram = Rc::new(RefCell::new(Ram::new(0x2000, 0xC000)));
+        let echo = Rc::new(RefCell::new(MirrorDevice::new(wram.clone(), 0x2000)));
+        let ppu = Rc::new(RefCell::new(PPU::new()));
+        bus.add_item(BusItem::new(0xC000, 0xDFFF, wram)).unwrap();
+        bus.add_item(BusItem::new(0xE000, 0xFDFF, echo)).unwrap();
+        bus.add_item(BusItem::new(0xFF40, 0xFF4B, ppu.clone())).unwrap();
+        bus.bus_write8(0xC000, 0x12);
+        bus.bus_write8(0xC001, 0x34);
+        bus.bus_write8(0xFF42, 0x56);
+
+        assert_eq!(bus.dump_range(0xBFFF, 0xC001), vec![0xFF, 0x12, 0x34]);
+        assert_eq!(bus.dump_range(0xE000, 0xE001), vec![0x12, 0x34]);
+
+        // Dumping the PPU registers doesn't start an OAM DMA.
+        let registers = bus.dump_range(0xFF40, 0xFF4B);
+        assert_eq!(registers.len(), 12);
+        assert_eq!(registers[2], 0x56);
+        assert!(!ppu.borrow().dma_active());
+    }
+
+    #[test]
+    fn test_remove_item() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        bus.add_item(ram_item(0xD000, 0xD0FF)).unwrap();
+        bus.bus_write8(0xC010, 0x12);
+        bus.bus_write8(0xD010, 0x34);
+        assert_eq!(bus.bus_read8(0xC010), 0x12);
+
+        // Removing a device leaves its addresses unmapped.
+        let removed = bus.remove_item(0xC000);
+        assert!(removed.is_some());
+        assert_eq!(removed.unwrap().bus_read8(0xC010), 0x12);
+        assert_eq!(bus.bus_read8(0xC010), 0xFF);
+        assert_eq!(bus.bus_read8(0xD010), 0x34);
+
+        // Only an exact start address matches.
+        assert!(bus.remove_item(0xC000).is_none());
+        assert!(bus.remove_item(0xD001).is_none());
+        assert_eq!(bus.bus_read8(0xD010), 0x34);
+    }
+
+    #[test]
+    fn test_open_bus_policy() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        assert_eq!(bus.bus_read8(0x1234), 0xFF);
+
+        // Unmapped reads return the upper address byte.
+        bus.set_open_bus(Some(Box::new(|addr| (addr >> 8) as u8)));
+        assert_eq!(bus.bus_read8(0x1234), 0x12);
+        assert_eq!(bus.bus_read8(0xC100), 0xC1);
+        assert_eq!(bus.bus_read8(0xC000), 0);
+
+        bus.set_open_bus(None);
+        assert_eq!(bus.bus_read8(0x1234), 0xFF);
+    }
+
+    #[test]
+    fn test_many_members_route_correctly() {
+        // 256 byte devices over the whole address space, added out of order.
+        let mut bus = Bus::new();
+        for page in (0..0x100).rev().step_by(2).chain((0..0x100).step_by(2)) {
+            bus.add_item(ram_item(page << 8, (page << 8) | 0xFF)).unwrap();
         }
+        for page in 0..0x100 {
+            bus.bus_write8(page << 8, page as u8);
+        }
+
+        // Every address reaches the device that owns it.
+        for _ in 0..10 {
+            for addr in 0..0x10000 {
+                let expected = if addr & 0xFF == 0 {(addr >> 8) as u8} else {0};
+                assert_eq!(bus.bus_read8(addr), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_addresses_wrap_at_16_bits() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0x0000, 0x00FF)).unwrap();
+        bus.add_item(ram_item(0xFF00, 0xFFFF)).unwrap();
+        bus.bus_write8(0x0000, 0x12);
+
+        assert_eq!(bus.bus_read8(0x10000), 0x12);
+        bus.bus_write8(0x10001, 0x34);
+        assert_eq!(bus.bus_read8(0x0001), 0x34);
+
+        // A 16 bit access at the top of memory wraps its high byte.
+        bus.bus_write8(0xFFFF, 0x56);
+        assert_eq!(bus.bus_read16(0xFFFF), 0x1256);
+
+        // Lookups wrap the same way.
+        let low = bus.device_at(0x0000).unwrap();
+        assert!(Rc::ptr_eq(&low, &bus.device_at(0x10000).unwrap()));
+        assert!(bus.device_at(0x1C000).is_none());
+
+        // Widening the mask leaves high addresses unmapped.
+        bus.set_addr_mask(usize::MAX);
+        assert_eq!(bus.bus_read8(0x10000), 0xFF);
+        assert!(bus.device_at(0x10000).is_none());
+    }
+
+    #[test]
+    fn test_empty_bus_unmapped_access() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.bus_read8(0x0000), 0xFF);
+        assert_eq!(bus.bus_read8(0xFF4C), 0xFF);
+        assert_eq!(bus.bus_read8(0xFFFF), 0xFF);
+        assert_eq!(bus.bus_read16(0xFF7F), 0xFFFF);
+
+        // Writes are silently dropped and don't change later reads.
+        bus.bus_write8(0xFF4C, 0x12);
+        bus.bus_write16(0xC000, 0x3456);
+        assert_eq!(bus.bus_read8(0xFF4C), 0xFF);
+        assert_eq!(bus.bus_read16(0xC000), 0xFFFF);
+        assert_eq!(bus.bus_peek8(0xFF4C), 0xFF);
+
+        // Neither is reported as a fault.
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_gaps_are_unmapped() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0x2000, 0x20FF)).unwrap();
+        bus.add_item(ram_item(0x1000, 0x10FF)).unwrap();
+        bus.bus_write8(0x1000, 1);
+        bus.bus_write8(0x2000, 2);
+
+        assert_eq!(bus.bus_read8(0x0000), 0xFF);
+        assert_eq!(bus.bus_read8(0x1000), 1);
+        assert_eq!(bus.bus_read8(0x1100), 0xFF);
+        assert_eq!(bus.bus_read8(0x2000), 2);
+        assert_eq!(bus.bus_read8(0x2100), 0xFF);
+    }
+
+    #[test]
+    fn test_16bit_access_across_members() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0x1000, 0x1000)).unwrap();
+        bus.add_item(ram_item(0x1001, 0x1001)).unwrap();
+
+        bus.bus_write8(0x1000, 0x34);
+        bus.bus_write8(0x1001, 0x12);
+        assert_eq!(bus.bus_read16(0x1000), 0x1234);
+
+        bus.bus_write16(0x1000, 0xABCD);
+        assert_eq!(bus.bus_read8(0x1000), 0xCD);
+        assert_eq!(bus.bus_read8(0x1001), 0xAB);
+
+        // A half that falls off the mapped range reads open bus.
+        assert_eq!(bus.bus_read16(0x1001), 0xFFAB);
+        assert_eq!(bus.bus_read16(0x0FFF), 0xCDFF);
+    }
+
+    #[test]
+    fn test_mirror_device() {
+        let mut bus = Bus::new();
+        let wram = Rc::new(RefCell::new(Ram::new(0x2000, 0xC000)));
+        let echo = Rc::new(RefCell::new(MirrorDevice::new(wram.clone(), 0x2000)));
+        bus.add_item(BusItem::new(0xC000, 0xDFFF, wram.clone())).unwrap();
+        bus.add_item(BusItem::new(0xE000, 0xFDFF, echo)).unwrap();
+
+        bus.bus_write8(0xC000, 0x12);
+        assert_eq!(bus.bus_read8(0xE000), 0x12);
+        bus.bus_write8(0xFDFF, 0x34);
+        assert_eq!(bus.bus_read8(0xDDFF), 0x34);
+        bus.bus_write16(0xE100, 0x5678);
+        assert_eq!(bus.bus_read16(0xC100), 0x5678);
+        assert_eq!(bus.take_fault(), None);
+
+        // A busy target is a fault through the mirror too.
+        {
+            let _held = wram.borrow_mut();
+            assert_eq!(bus.bus_read8(0xE000), 0xFF);
+            bus.bus_write8(0xE001, 0x9A);
+        }
+        assert_eq!(bus.take_fault(), Some(BusError::Reentrant{addr: 0xE000}));
+        assert_eq!(bus.bus_read8(0xC001), 0x00);
+        bus.bus_write8(0xE001, 0x9A);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        use crate::ppu::PPU;
+        use crate::interrupt::InterruptStatus;
+
+        let build = || {
+            let mut bus = Bus::new();
+            let wram = Rc::new(RefCell::new(Ram::new(0x2000, 0xC000)));
+            let echo = Rc::new(RefCell::new(MirrorDevice::new(wram.clone(), 0x2000)));
+            let ppu = Rc::new(RefCell::new(PPU::new()));
+            let isr = Rc::new(RefCell::new(InterruptStatus::new()));
+            bus.add_item(BusItem::new(0x8000, 0x9FFF, ppu.clone())).unwrap();
+            bus.add_item(BusItem::new(0xC000, 0xDFFF, wram)).unwrap();
+            bus.add_item(BusItem::new(0xE000, 0xFDFF, echo)).unwrap();
+            bus.add_item(BusItem::new(0xFF0F, 0xFF0F, isr.clone())).unwrap();
+            bus.add_item(BusItem::new(0xFF40, 0xFF4B, ppu)).unwrap();
+            bus.add_item(BusItem::new(0xFFFF, 0xFFFF, isr)).unwrap();
+            bus
+        };
+
+        let mut bus = build();
+        bus.bus_write8(0xFF40, 0);
+        bus.bus_write8(0x8000, 0x12);
+        bus.bus_write8(0xC000, 0x34);
+        bus.bus_write8(0xDFFF, 0x56);
+        bus.bus_write8(0xFF42, 0x78);
+        bus.bus_write8(0xFFFF, 0x1F);
+        let state = bus.save_state().unwrap();
+
+        // Restoring into a freshly built bus brings every device back.
+        let mut restored = build();
+        assert_eq!(restored.load_state(&state), Ok(()));
+        for addr in [0x8000, 0xC000, 0xDFFF, 0xFDFF, 0xFF40, 0xFF42, 0xFFFF].iter() {
+            assert_eq!(restored.bus_read8(*addr), bus.bus_read8(*addr), "{:#X}", addr);
+        }
+        assert_eq!(restored.bus_read8(0xC000), 0x34);
+
+        // Changes made since are undone.
+        bus.bus_write8(0xC000, 0x00);
+        bus.bus_write8(0xFF42, 0x00);
+        bus.load_state(&state).unwrap();
+        assert_eq!(bus.bus_read8(0xC000), 0x34);
+        assert_eq!(bus.bus_read8(0xFF42), 0x78);
+
+        // A bus with different devices is rejected without changes.
+        let mut other = Bus::new();
+        other.add_item(ram_item(0xC000, 0xDFFF)).unwrap();
+        other.bus_write8(0xC000, 0x99);
+        assert!(other.load_state(&state).is_err());
+        assert_eq!(other.bus_read8(0xC000), 0x99);
+        assert_eq!(bus.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+    }
+
+    /// Counts the ticks it is given.
+    struct TickCounter {
+        ticks: u32,
+    }
+
+    impl BusRW for TickCounter {
+        fn bus_write8(&mut self, _addr: usize, _value: u8) {}
+        fn bus_read8(&mut self, _addr: usize) -> u8 { 0 }
+        fn tick(&mut self, ticks: u32) {
+            self.ticks += ticks;
+        }
+    }
+
+    #[test]
+    fn test_tick_all() {
+        let mut bus = Bus::new();
+        let first = Rc::new(RefCell::new(TickCounter{ticks: 0}));
+        let second = Rc::new(RefCell::new(TickCounter{ticks: 0}));
+        bus.add_item(BusItem::new(0x0000, 0x00FF, first.clone())).unwrap();
+        bus.add_item(BusItem::new(0x1000, 0x10FF, second.clone())).unwrap();
+        // Mapped twice, but still ticked once.
+        bus.add_item(BusItem::new(0xFF00, 0xFF00, first.clone())).unwrap();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+
+        bus.tick_all(4);
+        bus.tick_all(8);
+        assert_eq!(first.borrow().ticks, 12);
+        assert_eq!(second.borrow().ticks, 12);
+
+        // A device that is busy misses the tick.
+        {
+            let _busy = second.borrow_mut();
+            bus.tick(4);
+        }
+        assert_eq!(first.borrow().ticks, 16);
+        assert_eq!(second.borrow().ticks, 12);
+        assert_eq!(bus.take_fault(), Some(BusError::Reentrant{addr: 0x1000}));
+    }
+
+    #[test]
+    fn test_open_bus_device() {
+        let mut bus = Bus::new();
+        bus.add_item(BusItem::new(0xFF4C, 0xFF4F, Rc::new(RefCell::new(OpenBus::new(0xFF))))).unwrap();
+        bus.add_item(BusItem::new(0xFF03, 0xFF03, Rc::new(RefCell::new(OpenBus::new(0x5A))))).unwrap();
+        bus.set_open_bus(Some(Box::new(|_| 0x00)));
+
+        // The range is mapped, so the bus open bus policy doesn't apply.
+        assert!(bus.is_mapped(0xFF4C));
+        assert_eq!(bus.bus_read8(0xFF4C), 0xFF);
+        assert_eq!(bus.bus_read8(0xFF4F), 0xFF);
+        assert_eq!(bus.bus_read8(0xFF50), 0x00);
+
+        bus.bus_write8(0xFF03, 0x12);
+        assert_eq!(bus.bus_read8(0xFF03), 0x5A);
+        assert_eq!(bus.bus_peek8(0xFF03), 0x5A);
+    }
+
+    #[test]
+    fn test_trace_hook() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let log = accesses.clone();
+        bus.set_trace(Some(Box::new(move |access| log.borrow_mut().push(access))));
+
+        bus.bus_write8(0xC000, 0x12);
+        bus.bus_read8(0xC000);
+        bus.bus_read8(0x0000);
+        bus.bus_write16(0xC010, 0x3456);
+
+        let expected = [
+            BusAccess{addr: 0xC000, value: 0x12, kind: AccessKind::Write},
+            BusAccess{addr: 0xC000, value: 0x12, kind: AccessKind::Read},
+            BusAccess{addr: 0x0000, value: 0xFF, kind: AccessKind::Read},
+            BusAccess{addr: 0xC010, value: 0x56, kind: AccessKind::Write},
+            BusAccess{addr: 0xC011, value: 0x34, kind: AccessKind::Write},
+        ];
+        assert_eq!(accesses.borrow()[..], expected[..]);
+
+        // Nothing more is recorded once the hook is removed.
+        bus.set_trace(None);
+        bus.bus_read8(0xC000);
+        assert_eq!(accesses.borrow().len(), expected.len());
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        bus.add_watchpoint(0xC010, WatchKind::Write);
+
+        // Reads and other addresses don't trigger.
+        bus.bus_read8(0xC010);
+        bus.bus_write8(0xC011, 1);
+        assert!(bus.take_triggered_watchpoints().is_empty());
+
+        bus.bus_write8(0xC010, 0x12);
+        bus.bus_write16(0xC00F, 0x3456);
+        assert_eq!(bus.take_triggered_watchpoints(), vec![
+            BusAccess{addr: 0xC010, value: 0x12, kind: AccessKind::Write},
+            BusAccess{addr: 0xC010, value: 0x34, kind: AccessKind::Write},
+        ]);
+        assert!(bus.take_triggered_watchpoints().is_empty());
+
+        bus.remove_watchpoint(0xC010);
+        bus.bus_write8(0xC010, 0x12);
+        assert!(bus.take_triggered_watchpoints().is_empty());
+    }
+
+    #[test]
+    fn test_read_watchpoints() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0xC000, 0xC0FF)).unwrap();
+        bus.add_watchpoint(0xC000, WatchKind::Read);
+        bus.add_watchpoint(0xC001, WatchKind::ReadWrite);
+
+        bus.bus_write8(0xC000, 1);
+        bus.bus_read8(0xC000);
+        bus.bus_write8(0xC001, 2);
+        bus.bus_read8(0xC001);
+        assert_eq!(bus.take_triggered_watchpoints(), vec![
+            BusAccess{addr: 0xC000, value: 1, kind: AccessKind::Read},
+            BusAccess{addr: 0xC001, value: 2, kind: AccessKind::Write},
+            BusAccess{addr: 0xC001, value: 2, kind: AccessKind::Read},
+        ]);
+    }
+
+    #[test]
+    fn test_replace_item() {
+        let mut bus = Bus::new();
+        bus.add_item(ram_item(0x0000, 0x00FF)).unwrap();
+        bus.bus_write8(0x0010, 0x12);
+
+        // An overlapping item takes the old one's place.
+        let replaced = bus.replace_item(ram_item(0x0000, 0x007F)).unwrap();
+        assert!(replaced.is_some());
+        assert_eq!(bus.bus_read8(0x0010), 0);
+        assert_eq!(bus.bus_read8(0x0080), 0xFF);
+
+        // Without an overlap the item is just added.
+        assert!(bus.replace_item(ram_item(0x0080, 0x00FF)).unwrap().is_none());
+        bus.bus_write8(0x0080, 0x34);
+        assert_eq!(bus.bus_read8(0x0080), 0x34);
+
+        // An item spanning two members can't replace either.
+        assert_eq!(bus.replace_item(ram_item(0x0070, 0x0090)).err(),
+            Some(BusError::Overlap{start_addr: 0x0070, end_addr: 0x0090}));
+        assert_eq!(bus.bus_read8(0x0080), 0x34);
+    }
+
+    #[test]
+    fn test_add_item_rejects_overlaps() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.add_item(ram_item(0x1000, 0x1FFF)), Ok(()));
+        assert_eq!(bus.add_item(ram_item(0x2000, 0x2FFF)), Ok(()));
+        assert_eq!(bus.add_item(ram_item(0x0000, 0x0FFF)), Ok(()));
+
+        // Any shared address is rejected, and the bus is left unchanged.
+        let overlaps = [(0x1FFF, 0x1FFF), (0x0F00, 0x1000), (0x1800, 0x2800), (0x0000, 0xFFFF)];
+        for (start_addr, end_addr) in overlaps.iter() {
+            assert_eq!(bus.add_item(ram_item(*start_addr, *end_addr)),
+                Err(BusError::Overlap{start_addr: *start_addr, end_addr: *end_addr}));
+        }
+        bus.bus_write8(0x1FFF, 0x12);
+        assert_eq!(bus.bus_read8(0x1FFF), 0x12);
+        assert_eq!(bus.members.len(), 3);
+    }
+
+    #[test]
+    fn test_member_ranges() {
+        let mut bus = Bus::new();
+        assert!(bus.is_empty());
+        assert_eq!(bus.ranges().count(), 0);
+
+        bus.add_item(ram_item(0xC000, 0xDFFF)).unwrap();
+        bus.add_item(ram_item(0x0000, 0x7FFF)).unwrap();
+        bus.add_item(ram_item(0xFF80, 0xFFFE)).unwrap();
+
+        assert!(!bus.is_empty());
+        assert_eq!(bus.len(), 3);
+        let ranges: Vec<(usize, usize)> = bus.ranges().collect();
+        assert_eq!(ranges, vec![(0x0000, 0x7FFF), (0xC000, 0xDFFF), (0xFF80, 0xFFFE)]);
     }
 }
@@ -1,4 +1,5 @@
 use super::MapperRW;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 enum BankMode {
     Mode16KRom,
     Mode4KRom,
@@ -6,8 +7,9 @@ enum BankMode {
 
 pub struct Mbc1Cart {
     mode: BankMode,
+    rom0_offset: usize,
     rom_offset: usize,
-    ram_offset: usize, 
+    ram_offset: usize,
     ram_enabled: bool,
     is_ram_mode: bool,
     low_bank_bits: u8,
@@ -34,9 +36,13 @@ impl Mbc1Cart {
     const MODE_SEL_START_ADDR: usize = 0x6000;
     const MODE_SEL_END_ADDR: usize = 0x7FFF;
 
+    const STATE_MAGIC: &'static [u8;4] = b"DMB1";
+    const STATE_VERSION: u16 = 1;
+
     pub fn new() -> Mbc1Cart {
         let mut mapper = Mbc1Cart {
             mode:BankMode::Mode16KRom,
+            rom0_offset:0,
             rom_offset:0,
             ram_offset:0,
             ram_enabled: false,
@@ -51,11 +57,14 @@ impl Mbc1Cart {
     }
 
     fn update_rom_bank_offset(&mut self) {
-        let mut bank = self.low_bank_bits;
-        if self.is_ram_mode == false {
-            bank |= self.high_bank_bits << 5;
-        }
+        // The switchable area always sees the upper bank bits.
+        let bank = self.low_bank_bits | (self.high_bank_bits << 5);
         self.rom_offset = (bank as usize) * Mbc1Cart::ROM_BANK_SIZE;
+
+        // In ram mode the upper bits also select the bank at 0x0000.
+        let bank0 = if self.is_ram_mode {self.high_bank_bits << 5}
+                    else {0};
+        self.rom0_offset = (bank0 as usize) * Mbc1Cart::ROM_BANK_SIZE;
     }
 
     fn update_ram_bank_offset(&mut self) {
@@ -63,24 +72,35 @@ impl Mbc1Cart {
                         else {0};
         self.ram_offset = (bank as usize) * Mbc1Cart::RAM_BANK_SIZE;
     }
+
+    /// Index into the rom, wrapping banks past the end of the rom.
+    fn rom_index(rom: &[u8], offset: usize) -> usize {
+        offset % rom.len()
+    }
 }
 
 impl MapperRW for Mbc1Cart {
 
-    fn read(&mut self, ram: &mut [u8], rom: &mut [u8], addr:u16) -> u8{
+    fn peek(&self, ram: &[u8], rom: &[u8], addr:u16) -> u8{
         let addr = addr as usize;
         match addr {
             // Bank zero area
             Mbc1Cart::ROM_BANK_0_START_ADDR..=Mbc1Cart::ROM_BANK_0_END_ADDR => {
-                rom[addr]
+                rom[Mbc1Cart::rom_index(rom, self.rom0_offset + addr)]
             }
             // Switchable bank area
             Mbc1Cart::ROM_BANK_SWITCH_START_ADDR..=Mbc1Cart::ROM_BANK_SWITCH_END_ADDR => {
-                rom[self.rom_offset + addr - Mbc1Cart::ROM_BANK_SWITCH_START_ADDR]
+                let offset = self.rom_offset + addr - Mbc1Cart::ROM_BANK_SWITCH_START_ADDR;
+                rom[Mbc1Cart::rom_index(rom, offset)]
             }
-            // Ram area
+            // Ram area, reads as 0xFF while disabled.
             Mbc1Cart::RAM_START_ADDR..=Mbc1Cart::RAM_END_ADDR => {
-                ram[self.ram_offset + addr - Mbc1Cart::RAM_START_ADDR]
+                let index = self.ram_offset + addr - Mbc1Cart::RAM_START_ADDR;
+                if self.ram_enabled && index < ram.len() {
+                    ram[index]
+                } else {
+                    0xFF
+                }
             }
             // For any other address, return 0xFF.
             _ => {
@@ -96,7 +116,7 @@ impl MapperRW for Mbc1Cart {
             Mbc1Cart::RAM_ENABLE_START_ADDR..=Mbc1Cart::RAM_ENABLE_END_ADDR => {
                 // Only the magic value of 0xA enables ram.
                 // Any other value disables ram
-                self.ram_enabled = value & 0xF == 0xA; 
+                self.ram_enabled = value & 0xF == 0xA;
             }
             // Bank cfg 0 (lower 5 rom bank bits)
             Mbc1Cart::BANK_CFG_0_START_ADDR..=Mbc1Cart::BANK_CFG_0_END_ADDR => {
@@ -115,11 +135,13 @@ impl MapperRW for Mbc1Cart {
             // Set the RAM or ROM banking mode.
             Mbc1Cart::MODE_SEL_START_ADDR..=Mbc1Cart::MODE_SEL_END_ADDR => {
                 self.is_ram_mode = value & 0x1 != 0;
+                self.update_ram_bank_offset();
+                self.update_rom_bank_offset();
             }
             // Ram region
             Mbc1Cart::RAM_START_ADDR..=Mbc1Cart::RAM_END_ADDR => {
                 let index = addr + self.ram_offset - Mbc1Cart::RAM_START_ADDR;
-                if index < ram.len(){
+                if self.ram_enabled && index < ram.len(){
                     ram[index] = value;
                 }
             }
@@ -131,12 +153,152 @@ impl MapperRW for Mbc1Cart {
     }
 }
 
+impl Snapshotable for Mbc1Cart {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(Mbc1Cart::STATE_MAGIC, Mbc1Cart::STATE_VERSION);
+        w.write_bool(self.ram_enabled);
+        w.write_bool(self.is_ram_mode);
+        w.write_u8(self.low_bank_bits);
+        w.write_u8(self.high_bank_bits);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Mbc1Cart::STATE_MAGIC)?;
+        if version != Mbc1Cart::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut mapper = Mbc1Cart::new();
+        mapper.ram_enabled = r.read_bool()?;
+        mapper.is_ram_mode = r.read_bool()?;
+        mapper.low_bank_bits = r.read_u8()?;
+        mapper.high_bank_bits = r.read_u8()?;
+        r.finish()?;
+        if mapper.low_bank_bits == 0 || mapper.low_bank_bits > 0x1F || mapper.high_bank_bits > 0b11 {
+            return Err(StateError::Invalid("MBC1 bank registers out of range".to_owned()));
+        }
+        mapper.update_ram_bank_offset();
+        mapper.update_rom_bank_offset();
+        *self = mapper;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
-    //TODO write unit tests for this module.
+    use super::*;
+
+    /// Builds a rom where the first byte of every bank holds the bank number.
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; banks * Mbc1Cart::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * Mbc1Cart::ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_rom_bank_switching() {
+        let mut rom = banked_rom(128);
+        let mut ram = vec![];
+        let mut mbc = Mbc1Cart::new();
+
+        // Bank 1 is selected at startup.
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 1);
+
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x05);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 5);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x0000), 0);
+
+        // Upper bank bits.
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x02);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 0x45);
+    }
+
+    #[test]
+    fn test_bank_zero_maps_to_one() {
+        let mut rom = banked_rom(128);
+        let mut ram = vec![];
+        let mut mbc = Mbc1Cart::new();
+
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x00);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 1);
+
+        // Only the low 5 bits are checked, so 0x20 becomes 0x21.
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x01);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 0x21);
+
+        // Bits above the low 5 are ignored.
+        mbc.write(&mut ram, &mut rom, 0x2000, 0xE0);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 0x21);
+    }
+
+    #[test]
+    fn test_bank_wraps_rom_size() {
+        let mut rom = banked_rom(4);
+        let mut ram = vec![];
+        let mut mbc = Mbc1Cart::new();
+
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x06);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 2);
+    }
+
+    #[test]
+    fn test_ram_enable() {
+        let mut rom = banked_rom(4);
+        let mut ram = vec![0u8; 0x2000];
+        let mut mbc = Mbc1Cart::new();
+
+        // Disabled ram ignores writes and reads open bus.
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x12);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0xFF);
+        assert_eq!(ram[0], 0);
+
+        mbc.write(&mut ram, &mut rom, 0x0000, 0x0A);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x12);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0x12);
+
+        mbc.write(&mut ram, &mut rom, 0x1FFF, 0x00);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0xFF);
+    }
+
     #[test]
-    #[ignore]
-    fn mmc1_needs_to_be_tested(){
-        assert!(false);
+    fn test_mode1_large_ram() {
+        let mut rom = banked_rom(4);
+        let mut ram = vec![0u8; 0x8000];
+        let mut mbc = Mbc1Cart::new();
+        mbc.write(&mut ram, &mut rom, 0x0000, 0x0A);
+
+        // In mode 0 the ram bank is fixed to 0.
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x02);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x11);
+        assert_eq!(ram[0], 0x11);
+
+        // Mode 1 lets the upper bits select the ram bank.
+        mbc.write(&mut ram, &mut rom, 0x6000, 0x01);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x22);
+        assert_eq!(ram[2 * Mbc1Cart::RAM_BANK_SIZE], 0x22);
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x03);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x33);
+        assert_eq!(ram[3 * Mbc1Cart::RAM_BANK_SIZE], 0x33);
+
+        // Back to mode 0 sees bank 0 again.
+        mbc.write(&mut ram, &mut rom, 0x6000, 0x00);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0x11);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_mode1_large_rom_bank_zero() {
+        let mut rom = banked_rom(128);
+        let mut ram = vec![];
+        let mut mbc = Mbc1Cart::new();
+
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x01);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x0000), 0);
+
+        // Mode 1 maps bank 0x20 into the 0x0000 area.
+        mbc.write(&mut ram, &mut rom, 0x6000, 0x01);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x0000), 0x20);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 0x21);
+    }
+}
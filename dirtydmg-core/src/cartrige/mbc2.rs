@@ -27,6 +27,9 @@ impl Mbc2Cart {
     const CFG_0_REG_END_ADDR:   usize = 0x3FFF;
     const CFG_0_REG_ROM_BANK_SELECT_BIT: usize = 0x100;
 
+    const STATE_MAGIC: &'static [u8;4] = b"DMB2";
+    const STATE_VERSION: u16 = 1;
+
     pub fn new() -> Mbc2Cart {
         let mut mapper = Mbc2Cart {
             mode:BankMode::Mode16KRom,
@@ -53,7 +56,7 @@ impl Mbc2Cart {
 
 impl MapperRW for Mbc2Cart {
 
-    fn read(&mut self, ram: &mut [u8], rom: &mut [u8], addr:u16) -> u8{
+    fn peek(&self, ram: &[u8], rom: &[u8], addr:u16) -> u8{
         let addr = addr as usize;
         match addr {
             // Bank zero area
@@ -111,6 +114,28 @@ impl MapperRW for Mbc2Cart {
     }
 }
 
+impl Snapshotable for Mbc2Cart {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(Mbc2Cart::STATE_MAGIC, Mbc2Cart::STATE_VERSION);
+        w.write_bool(self.ram_enabled);
+        w.write_u8((self.rom_offset / Mbc2Cart::ROM_BANK_SIZE) as u8);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Mbc2Cart::STATE_MAGIC)?;
+        if version != Mbc2Cart::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut mapper = Mbc2Cart::new();
+        mapper.ram_enabled = r.read_bool()?;
+        mapper.update_rom_bank_offset(r.read_u8()?);
+        r.finish()?;
+        *self = mapper;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     //TODO write unit tests for this module.
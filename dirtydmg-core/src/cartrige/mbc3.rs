@@ -5,14 +5,177 @@ enum BankMode {
     Mode4KRom,
 }
 
+/// The MBC3 real time clock.
+///
+/// The clock doesn't read the host time itself, instead the current time is
+/// injected with `update` and the counters advance by the time elapsed since
+/// the previous update.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rtc {
+    regs: RtcRegisters,
+    latched: RtcRegisters,
+    /// Host time (seconds since the unix epoch) of the last update.
+    timestamp: Option<u64>,
+    latch_armed: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+}
+
+impl RtcRegisters {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            Rtc::SECONDS_REG => self.seconds,
+            Rtc::MINUTES_REG => self.minutes,
+            Rtc::HOURS_REG => self.hours,
+            Rtc::DAY_LOW_REG => self.days as u8,
+            Rtc::DAY_HIGH_REG => {
+                ((self.days >> 8) as u8 & Rtc::DAY_HIGH_BIT_MASK)
+                | if self.halt {Rtc::HALT_MASK} else {0}
+                | if self.carry {Rtc::CARRY_MASK} else {0}
+            }
+            _ => 0xFF
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            Rtc::SECONDS_REG => self.seconds = value & 0x3F,
+            Rtc::MINUTES_REG => self.minutes = value & 0x3F,
+            Rtc::HOURS_REG => self.hours = value & 0x1F,
+            Rtc::DAY_LOW_REG => self.days = (self.days & 0x100) | value as u16,
+            Rtc::DAY_HIGH_REG => {
+                self.days = (self.days & 0xFF) | (((value & Rtc::DAY_HIGH_BIT_MASK) as u16) << 8);
+                self.halt = value & Rtc::HALT_MASK != 0;
+                self.carry = value & Rtc::CARRY_MASK != 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Rtc {
+    pub const SECONDS_REG: u8 = 0x08;
+    pub const MINUTES_REG: u8 = 0x09;
+    pub const HOURS_REG: u8 = 0x0A;
+    pub const DAY_LOW_REG: u8 = 0x0B;
+    pub const DAY_HIGH_REG: u8 = 0x0C;
+
+    const DAY_HIGH_BIT_MASK: u8 = 0b0000_0001;
+    const HALT_MASK: u8 = 0b0100_0000;
+    const CARRY_MASK: u8 = 0b1000_0000;
+    const DAYS_MAX: u64 = 512;
+
+    /// Length of the serialized clock state.
+    pub const STATE_LENGTH: usize = 48;
+
+    pub fn new() -> Rtc {
+        Rtc::default()
+    }
+
+    /// Advances the clock to `now`, in seconds since the unix epoch.
+    ///
+    /// The first update only records the time, later updates advance the
+    /// counters by the time elapsed since the previous one.
+    pub fn update(&mut self, now: u64) {
+        if let Some(last) = self.timestamp {
+            if now > last {
+                self.advance(now - last);
+            }
+        }
+        self.timestamp = Some(now);
+    }
+
+    /// Advances the clock by a number of seconds, unless it is halted.
+    pub fn advance(&mut self, seconds: u64) {
+        if self.regs.halt {
+            return;
+        }
+
+        let total = self.regs.seconds as u64 + seconds;
+        self.regs.seconds = (total % 60) as u8;
+        let total = self.regs.minutes as u64 + total / 60;
+        self.regs.minutes = (total % 60) as u8;
+        let total = self.regs.hours as u64 + total / 60;
+        self.regs.hours = (total % 24) as u8;
+        let total = self.regs.days as u64 + total / 24;
+        if total >= Rtc::DAYS_MAX {
+            self.regs.carry = true;
+        }
+        self.regs.days = (total % Rtc::DAYS_MAX) as u16;
+    }
+
+    /// Handles a write to the latch register, latching the clock on a 0 then
+    /// 1 write sequence.
+    fn latch_write(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.latched = self.regs;
+        }
+        self.latch_armed = value == 0;
+    }
+
+    /// Reads a latched clock register.
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    /// Writes a live clock register.
+    pub fn write(&mut self, reg: u8, value: u8) {
+        self.regs.write(reg, value);
+    }
+
+    /// Serializes the clock in the common 48 byte save file format: the live
+    /// then latched registers as little endian u32s, then the u64 timestamp.
+    pub fn to_bytes(&self) -> [u8; Rtc::STATE_LENGTH] {
+        let mut data = [0u8; Rtc::STATE_LENGTH];
+        for (i, regs) in [self.regs, self.latched].iter().enumerate() {
+            for reg in 0..5 {
+                let offset = (i * 5 + reg) * 4;
+                data[offset] = regs.read(Rtc::SECONDS_REG + reg as u8);
+            }
+        }
+        data[40..].copy_from_slice(&self.timestamp.unwrap_or(0).to_le_bytes());
+        data
+    }
+
+    /// Restores a clock serialized by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> Result<Rtc, String> {
+        if data.len() < Rtc::STATE_LENGTH {
+            return Err("RTC data was too short.".to_owned());
+        }
+
+        let mut rtc = Rtc::new();
+        for (i, regs) in [&mut rtc.regs, &mut rtc.latched].iter_mut().enumerate() {
+            for reg in 0..5 {
+                let offset = (i * 5 + reg) * 4;
+                regs.write(Rtc::SECONDS_REG + reg as u8, data[offset]);
+            }
+        }
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        let timestamp = u64::from_le_bytes(timestamp);
+        rtc.timestamp = if timestamp == 0 {None} else {Some(timestamp)};
+        Ok(rtc)
+    }
+}
+
 pub struct Mbc3Cart {
     mode: BankMode,
     rom_offset: usize,
-    ram_offset: usize, 
+    ram_offset: usize,
     ram_enabled: bool,
     is_ram_mode: bool,
     rom_bank: u8,
     ram_bank: u8,
+    rtc_select: Option<u8>,
+    rtc: Rtc,
 }
 
 impl Mbc3Cart {
@@ -35,6 +198,9 @@ impl Mbc3Cart {
     const MODE_SEL_START_ADDR: usize = 0x6000;
     const MODE_SEL_END_ADDR: usize = 0x7FFF;
 
+    const STATE_MAGIC: &'static [u8;4] = b"DMB3";
+    const STATE_VERSION: u16 = 1;
+
     pub fn new() -> Mbc3Cart {
         let mut mapper = Mbc3Cart {
             mode:BankMode::Mode16KRom,
@@ -42,8 +208,10 @@ impl Mbc3Cart {
             ram_offset:0,
             ram_enabled: false,
             is_ram_mode: false,
-            rom_bank: 0,
+            rom_bank: 1,
             ram_bank: 0,
+            rtc_select: None,
+            rtc: Rtc::new(),
         };
         mapper.update_ram_bank_offset();
         mapper.update_rom_bank_offset();
@@ -56,15 +224,13 @@ impl Mbc3Cart {
     }
 
     fn update_ram_bank_offset(&mut self) {
-        let bank = if self.is_ram_mode {self.ram_bank}
-                    else {0};
-        self.ram_offset = (bank as usize) * Mbc3Cart::RAM_BANK_SIZE;
+        self.ram_offset = (self.ram_bank as usize) * Mbc3Cart::RAM_BANK_SIZE;
     }
 }
 
 impl MapperRW for Mbc3Cart {
 
-    fn read(&mut self, ram: &mut [u8], rom: &mut [u8], addr:u16) -> u8{
+    fn peek(&self, ram: &[u8], rom: &[u8], addr:u16) -> u8{
         let addr = addr as usize;
         match addr {
             // Bank zero area
@@ -73,11 +239,19 @@ impl MapperRW for Mbc3Cart {
             }
             // Switchable bank area
             Mbc3Cart::ROM_BANK_SWITCH_START_ADDR..=Mbc3Cart::ROM_BANK_SWITCH_END_ADDR => {
-                rom[self.rom_offset + addr - Mbc3Cart::ROM_BANK_SWITCH_START_ADDR]
+                let offset = self.rom_offset + addr - Mbc3Cart::ROM_BANK_SWITCH_START_ADDR;
+                rom[offset % rom.len()]
             }
-            // Ram area
+            // Ram or RTC register area, reads as 0xFF while disabled.
             Mbc3Cart::RAM_START_ADDR..=Mbc3Cart::RAM_END_ADDR => {
-                ram[self.ram_offset + addr - Mbc3Cart::RAM_START_ADDR]
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                if let Some(reg) = self.rtc_select {
+                    return self.rtc.read(reg);
+                }
+                let index = self.ram_offset + addr - Mbc3Cart::RAM_START_ADDR;
+                *ram.get(index).unwrap_or(&0xFF)
             }
             // For any other address, return 0xFF.
             _ => {
@@ -103,17 +277,18 @@ impl MapperRW for Mbc3Cart {
                 self.rom_bank = bits;
                 self.update_rom_bank_offset();
             }
-            // Bank cfg 1 (upper 2 rom bank bits, or 2 ram bank bits)
+            // Bank cfg 1 (ram bank or RTC register select)
             Mbc3Cart::BANK_CFG_1_START_ADDR..=Mbc3Cart::BANK_CFG_1_END_ADDR => {
                 match value {
                     // Ram bank select
-                    0..=4 => {
+                    0..=7 => {
                         self.ram_bank = value;
+                        self.rtc_select = None;
                         self.update_ram_bank_offset();
                     }
                     // RTC register select
-                    0x8..=0xC => {
-                        // TODO 
+                    Rtc::SECONDS_REG..=Rtc::DAY_HIGH_REG => {
+                        self.rtc_select = Some(value);
                     }
                     // Unmapped
                     _ => {}
@@ -121,12 +296,19 @@ impl MapperRW for Mbc3Cart {
             }
             // Latch clock data
             Mbc3Cart::MODE_SEL_START_ADDR..=Mbc3Cart::MODE_SEL_END_ADDR => {
-                // TODO
+                self.rtc.latch_write(value);
             }
-            // Ram region
+            // Ram or RTC register region
             Mbc3Cart::RAM_START_ADDR..=Mbc3Cart::RAM_END_ADDR => {
+                if !self.ram_enabled {
+                    return;
+                }
+                if let Some(reg) = self.rtc_select {
+                    self.rtc.write(reg, value);
+                    return;
+                }
                 let index = addr + self.ram_offset - Mbc3Cart::RAM_START_ADDR;
-                if self.ram_enabled && index < ram.len(){
+                if index < ram.len(){
                     ram[index] = value;
                 }
             }
@@ -136,14 +318,232 @@ impl MapperRW for Mbc3Cart {
             }
         }
     }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        Some(&self.rtc)
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        Some(&mut self.rtc)
+    }
+}
+
+/// The clock is saved in the `Rtc::to_bytes` format, followed by the latch
+/// sequence that is partway through.
+impl Snapshotable for Mbc3Cart {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(Mbc3Cart::STATE_MAGIC, Mbc3Cart::STATE_VERSION);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        // Zero is never a clock register, so it stands for no selection.
+        w.write_u8(self.rtc_select.unwrap_or(0));
+        w.write_bytes(&self.rtc.to_bytes());
+        w.write_bool(self.rtc.latch_armed);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Mbc3Cart::STATE_MAGIC)?;
+        if version != Mbc3Cart::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut mapper = Mbc3Cart::new();
+        mapper.ram_enabled = r.read_bool()?;
+        mapper.rom_bank = r.read_u8()?;
+        mapper.ram_bank = r.read_u8()?;
+        let rtc_select = r.read_u8()?;
+        mapper.rtc = Rtc::from_bytes(r.read_bytes(Rtc::STATE_LENGTH)?)
+            .map_err(StateError::Invalid)?;
+        mapper.rtc.latch_armed = r.read_bool()?;
+        r.finish()?;
+        if mapper.rom_bank == 0 || mapper.rom_bank > 0x7F || mapper.ram_bank > 7 {
+            return Err(StateError::Invalid("MBC3 bank registers out of range".to_owned()));
+        }
+        mapper.rtc_select = match rtc_select {
+            0 => None,
+            Rtc::SECONDS_REG..=Rtc::DAY_HIGH_REG => Some(rtc_select),
+            _ => return Err(StateError::Invalid(format!("{:#X} is not a clock register", rtc_select))),
+        };
+        mapper.update_ram_bank_offset();
+        mapper.update_rom_bank_offset();
+        *self = mapper;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    //TODO write unit tests for this module.
+    use super::*;
+
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; banks * Mbc3Cart::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * Mbc3Cart::ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    fn enabled_mbc(ram: &mut [u8], rom: &mut [u8]) -> Mbc3Cart {
+        let mut mbc = Mbc3Cart::new();
+        mbc.write(ram, rom, 0x0000, 0x0A);
+        mbc
+    }
+
+    fn latch(mbc: &mut Mbc3Cart, ram: &mut [u8], rom: &mut [u8]) {
+        mbc.write(ram, rom, 0x6000, 0x00);
+        mbc.write(ram, rom, 0x6000, 0x01);
+    }
+
+    fn read_rtc(mbc: &mut Mbc3Cart, ram: &mut [u8], rom: &mut [u8], reg: u8) -> u8 {
+        mbc.write(ram, rom, 0x4000, reg);
+        mbc.read(ram, rom, 0xA000)
+    }
+
+    #[test]
+    fn test_rom_bank_switching() {
+        let mut rom = banked_rom(128);
+        let mut ram = vec![];
+        let mut mbc = Mbc3Cart::new();
+
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 1);
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x7F);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 0x7F);
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x00);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x4000), 1);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0x0000), 0);
+    }
+
+    #[test]
+    fn test_ram_bank_switching() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![0u8; 4 * Mbc3Cart::RAM_BANK_SIZE];
+        let mut mbc = enabled_mbc(&mut ram, &mut rom);
+
+        for bank in 0..4u8 {
+            mbc.write(&mut ram, &mut rom, 0x4000, bank);
+            mbc.write(&mut ram, &mut rom, 0xA000, bank + 0x10);
+        }
+        for bank in 0..4u8 {
+            mbc.write(&mut ram, &mut rom, 0x4000, bank);
+            assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), bank + 0x10);
+        }
+
+        // Disabled ram reads open bus.
+        mbc.write(&mut ram, &mut rom, 0x0000, 0x00);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0xFF);
+    }
+
     #[test]
-    #[ignore]
-    fn mmc1_needs_to_be_tested(){
-        assert!(false);
+    fn test_rtc_latch() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![0u8; Mbc3Cart::RAM_BANK_SIZE];
+        let mut mbc = enabled_mbc(&mut ram, &mut rom);
+
+        mbc.rtc_mut().unwrap().update(1000);
+        mbc.rtc_mut().unwrap().update(1000 + 3661);
+
+        // Nothing is visible until the clock is latched.
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 0);
+
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 1);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::MINUTES_REG), 1);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::HOURS_REG), 1);
+
+        // The latched values hold while the clock keeps running.
+        mbc.rtc_mut().unwrap().advance(10);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 1);
+
+        // Writing 1 without a preceding 0 doesn't latch.
+        mbc.write(&mut ram, &mut rom, 0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 1);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 11);
+
+        // Selecting a ram bank again maps the ram back in.
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x00);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x42);
+        assert_eq!(ram[0], 0x42);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rtc_day_carry() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![];
+        let mut mbc = enabled_mbc(&mut ram, &mut rom);
+
+        // Day 511, one second before midnight.
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::DAY_HIGH_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x01);
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::DAY_LOW_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0xFF);
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::HOURS_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 23);
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::MINUTES_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 59);
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::SECONDS_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 59);
+
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_LOW_REG), 0xFF);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_HIGH_REG), 0x01);
+
+        mbc.rtc_mut().unwrap().advance(1);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 0);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_LOW_REG), 0);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_HIGH_REG), 0x80);
+
+        // The carry is sticky until cleared by a write.
+        mbc.rtc_mut().unwrap().advance(86400);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_LOW_REG), 1);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_HIGH_REG), 0x80);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x00);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_HIGH_REG), 0x00);
+    }
+
+    #[test]
+    fn test_rtc_halt() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![];
+        let mut mbc = enabled_mbc(&mut ram, &mut rom);
+
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::DAY_HIGH_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x40);
+        mbc.rtc_mut().unwrap().advance(100);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 0);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::DAY_HIGH_REG), 0x40);
+
+        mbc.write(&mut ram, &mut rom, 0x4000, Rtc::DAY_HIGH_REG);
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x00);
+        mbc.rtc_mut().unwrap().advance(100);
+        latch(&mut mbc, &mut ram, &mut rom);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::SECONDS_REG), 40);
+        assert_eq!(read_rtc(&mut mbc, &mut ram, &mut rom, Rtc::MINUTES_REG), 1);
+    }
+
+    #[test]
+    fn test_rtc_serialize() {
+        let mut rtc = Rtc::new();
+        rtc.update(1_600_000_000);
+        rtc.advance(90061);
+        rtc.latch_write(0);
+        rtc.latch_write(1);
+        rtc.advance(5);
+
+        let data = rtc.to_bytes();
+        assert_eq!(data[0], 6);
+        assert_eq!(data[20], 1);
+        let restored = Rtc::from_bytes(&data).unwrap();
+        assert_eq!(restored.to_bytes()[..], data[..]);
+        assert_eq!(restored.regs, rtc.regs);
+        assert_eq!(restored.latched, rtc.latched);
+        assert_eq!(restored.timestamp, Some(1_600_000_000));
+
+        assert!(Rtc::from_bytes(&data[..40]).is_err());
+    }
+}
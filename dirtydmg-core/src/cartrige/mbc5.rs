@@ -13,6 +13,8 @@ pub struct Mbc5Cart {
     low_bank_bits: u8,
     high_bank_bits: u8,
     ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
 }
 
 impl Mbc5Cart {
@@ -37,6 +39,12 @@ impl Mbc5Cart {
     const MODE_SEL_START_ADDR: usize = 0x6000;
     const MODE_SEL_END_ADDR: usize = 0x7FFF;
 
+    /// On rumble carts bit 3 of the ram bank register drives the motor.
+    const RUMBLE_MASK: u8 = 0b1000;
+
+    const STATE_MAGIC: &'static [u8;4] = b"DMB5";
+    const STATE_VERSION: u16 = 1;
+
     pub fn new() -> Mbc5Cart {
         let mut mapper = Mbc5Cart {
             mode:BankMode::Mode16KRom,
@@ -46,6 +54,8 @@ impl Mbc5Cart {
             low_bank_bits: 1,
             high_bank_bits: 0,
             ram_bank: 0,
+            has_rumble: false,
+            rumble: false,
         };
         mapper.update_ram_bank_offset();
         mapper.update_rom_bank_offset();
@@ -54,6 +64,13 @@ impl Mbc5Cart {
         mapper
     }
 
+    /// Creates a mapper for a cart with a rumble motor.
+    pub fn with_rumble() -> Mbc5Cart {
+        let mut mapper = Mbc5Cart::new();
+        mapper.has_rumble = true;
+        mapper
+    }
+
     fn update_rom_bank_offset(&mut self) {
         let bank = 
             ((self.high_bank_bits as u16) << 8) 
@@ -68,7 +85,7 @@ impl Mbc5Cart {
 
 impl MapperRW for Mbc5Cart {
 
-    fn read(&mut self, ram: &mut [u8], rom: &mut [u8], addr:u16) -> u8{
+    fn peek(&self, ram: &[u8], rom: &[u8], addr:u16) -> u8{
         let addr = addr as usize;
         match addr {
             // Bank zero area
@@ -77,11 +94,17 @@ impl MapperRW for Mbc5Cart {
             }
             // Switchable bank area
             Mbc5Cart::ROM_BANK_SWITCH_START_ADDR..=Mbc5Cart::ROM_BANK_SWITCH_END_ADDR => {
-                rom[self.rom_offset + addr - Mbc5Cart::ROM_BANK_SWITCH_START_ADDR]
+                let offset = self.rom_offset + addr - Mbc5Cart::ROM_BANK_SWITCH_START_ADDR;
+                rom[offset % rom.len()]
             }
-            // Ram area
+            // Ram area, reads as 0xFF while disabled.
             Mbc5Cart::RAM_START_ADDR..=Mbc5Cart::RAM_END_ADDR => {
-                ram[self.ram_offset + addr - Mbc5Cart::RAM_START_ADDR]
+                let index = self.ram_offset + addr - Mbc5Cart::RAM_START_ADDR;
+                if self.ram_enabled && index < ram.len() {
+                    ram[index]
+                } else {
+                    0xFF
+                }
             }
             // For any other address, return 0xFF.
             _ => {
@@ -112,9 +135,16 @@ impl MapperRW for Mbc5Cart {
             }
             // Ram bank number
             Mbc5Cart::BANK_CFG_2_START_ADDR..=Mbc5Cart::BANK_CFG_2_END_ADDR => {
-                self.ram_bank = value & 0xF;
+                if self.has_rumble {
+                    self.rumble = value & Mbc5Cart::RUMBLE_MASK != 0;
+                    self.ram_bank = value & 0x7;
+                } else {
+                    self.ram_bank = value & 0xF;
+                }
                 self.update_ram_bank_offset();
             }
+            // Unused on MBC5
+            Mbc5Cart::MODE_SEL_START_ADDR..=Mbc5Cart::MODE_SEL_END_ADDR => {}
             // Ram region
             Mbc5Cart::RAM_START_ADDR..=Mbc5Cart::RAM_END_ADDR => {
                 let index = addr + self.ram_offset - Mbc5Cart::RAM_START_ADDR;
@@ -128,14 +158,135 @@ impl MapperRW for Mbc5Cart {
             }
         }
     }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+impl Snapshotable for Mbc5Cart {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(Mbc5Cart::STATE_MAGIC, Mbc5Cart::STATE_VERSION);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.low_bank_bits);
+        w.write_u8(self.high_bank_bits);
+        w.write_u8(self.ram_bank);
+        w.write_bool(self.rumble);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Mbc5Cart::STATE_MAGIC)?;
+        if version != Mbc5Cart::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut mapper = Mbc5Cart::new();
+        mapper.has_rumble = self.has_rumble;
+        mapper.ram_enabled = r.read_bool()?;
+        mapper.low_bank_bits = r.read_u8()?;
+        mapper.high_bank_bits = r.read_u8()?;
+        mapper.ram_bank = r.read_u8()?;
+        mapper.rumble = r.read_bool()?;
+        r.finish()?;
+        if mapper.high_bank_bits > 1 || mapper.ram_bank > 0xF {
+            return Err(StateError::Invalid("MBC5 bank registers out of range".to_owned()));
+        }
+        mapper.update_ram_bank_offset();
+        mapper.update_rom_bank_offset();
+        *self = mapper;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    //TODO write unit tests for this module.
+    use super::*;
+
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; banks * Mbc5Cart::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * Mbc5Cart::ROM_BANK_SIZE] = bank as u8;
+            rom[bank * Mbc5Cart::ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn read_bank(mbc: &mut Mbc5Cart, ram: &mut [u8], rom: &mut [u8]) -> usize {
+        mbc.read(ram, rom, 0x4000) as usize | (mbc.read(ram, rom, 0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn test_high_rom_banks() {
+        let mut rom = banked_rom(512);
+        let mut ram = vec![];
+        let mut mbc = Mbc5Cart::new();
+
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 1);
+
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x23);
+        mbc.write(&mut ram, &mut rom, 0x3000, 0x01);
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 0x123);
+
+        mbc.write(&mut ram, &mut rom, 0x2FFF, 0xFF);
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 0x1FF);
+
+        // Only bit 0 of the high register is used.
+        mbc.write(&mut ram, &mut rom, 0x3FFF, 0xFE);
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 0xFF);
+    }
+
     #[test]
-    #[ignore]
-    fn mmc1_needs_to_be_tested(){
-        assert!(false);
+    fn test_bank_zero_addressable() {
+        let mut rom = banked_rom(512);
+        let mut ram = vec![];
+        let mut mbc = Mbc5Cart::new();
+
+        mbc.write(&mut ram, &mut rom, 0x2000, 0x00);
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 0);
+
+        mbc.write(&mut ram, &mut rom, 0x3000, 0x01);
+        assert_eq!(read_bank(&mut mbc, &mut ram, &mut rom), 0x100);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![0u8; 16 * Mbc5Cart::RAM_BANK_SIZE];
+        let mut mbc = Mbc5Cart::new();
+
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x55);
+        assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), 0xFF);
+
+        mbc.write(&mut ram, &mut rom, 0x0000, 0x0A);
+        for bank in 0..16u8 {
+            mbc.write(&mut ram, &mut rom, 0x4000, bank);
+            mbc.write(&mut ram, &mut rom, 0xA000, bank);
+        }
+        for bank in 0..16u8 {
+            mbc.write(&mut ram, &mut rom, 0x4000, bank);
+            assert_eq!(mbc.read(&mut ram, &mut rom, 0xA000), bank);
+        }
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut rom = banked_rom(2);
+        let mut ram = vec![0u8; 8 * Mbc5Cart::RAM_BANK_SIZE];
+        let mut mbc = Mbc5Cart::with_rumble();
+        mbc.write(&mut ram, &mut rom, 0x0000, 0x0A);
+
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x0B);
+        assert!(mbc.rumble());
+        // The rumble bit doesn't select a ram bank.
+        mbc.write(&mut ram, &mut rom, 0xA000, 0x33);
+        assert_eq!(ram[3 * Mbc5Cart::RAM_BANK_SIZE], 0x33);
+
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x03);
+        assert!(!mbc.rumble());
+
+        // Without a motor bit 3 is a ram bank bit.
+        let mut mbc = Mbc5Cart::new();
+        mbc.write(&mut ram, &mut rom, 0x4000, 0x08);
+        assert!(!mbc.rumble());
     }
 }
\ No newline at end of file
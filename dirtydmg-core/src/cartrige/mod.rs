@@ -4,17 +4,44 @@ mod mbc3;
 mod mbc5;
 mod no_mapper;
 use crate::bus::BusRW;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
+pub use mbc3::Rtc;
 
-trait MapperRW{
+/// A cartrige memory mapper.
+///
+/// The saved state of a mapper holds its bank registers, the ROM and RAM
+/// they point into are saved by the cartrige.
+trait MapperRW: Snapshotable {
     /// Reads a single byte from the mapper.
-    fn read(&mut self, rom:&mut [u8], ram:&mut [u8], addr:u16) -> u8;
+    fn read(&mut self, ram:&mut [u8], rom:&mut [u8], addr:u16) -> u8 {
+        self.peek(ram, rom, addr)
+    }
+
+    /// Reads a single byte from the mapper without changing its state.
+    fn peek(&self, ram:&[u8], rom:&[u8], addr:u16) -> u8;
 
     /// Writes a single byte to the mapper.
-    fn write(&mut self, rom:&mut [u8], ram:&mut [u8], addr:u16, value:u8);
+    fn write(&mut self, ram:&mut [u8], rom:&mut [u8], addr:u16, value:u8);
+
+    /// The real time clock of the mapper, if it has one.
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    /// Mutable access to the real time clock of the mapper, if it has one.
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    /// True while the mapper's rumble motor is switched on.
+    fn rumble(&self) -> bool {
+        false
+    }
 }
 
 
-enum MapperType{
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapperType{
     Rom,
     Mbc1,
     Mbc2,
@@ -25,22 +52,32 @@ enum MapperType{
     Mbc7,
 }
 
-struct CartInfo{
+/// Information parsed from a cartrige's ROM header.
+#[derive(Debug, Clone)]
+pub struct CartInfo{
+    /// The game title.
+    pub title: String,
+    /// The raw cartrige type byte.
+    pub cart_type: u8,
     /// The memory mapper used by the cart.
-    mapper:MapperType,
+    pub mapper:MapperType,
     /// The rom size on the cart.
-    rom_size: usize,
+    pub rom_size: usize,
     /// The ram size on the cart (zero if none).
-    ram_size: usize,
+    pub ram_size: usize,
     /// True if a battery is present.
-    battery: bool,
+    pub battery: bool,
     /// True if a timer is present.
-    timer: bool,
+    pub timer: bool,
     /// True if rumble is present.
-    rumble: bool,
+    pub rumble: bool,
 }
 
 impl CartInfo{
+    /// Offset of the title in the ROM header.
+    pub const HEADER_TITLE_OFFSET: usize = 0x134;
+    /// Maximum length of the title in the ROM header.
+    pub const HEADER_TITLE_LENGTH: usize = 16;
     /// Offset of the cart type byte in the ROM header
     pub const HEADER_TYPE_OFFSET: usize = 0x147;
     /// Offset of the ROM size byte in the ROM header
@@ -49,7 +86,8 @@ impl CartInfo{
     pub const HEADER_RAM_SIZE_OFFSET: usize = 0x149;
     pub const HEADER_LENGTH:usize = 0x150;
 
-    fn from_header(header: &[u8]) -> Result<CartInfo, String> {
+    /// Parses the cartrige header from the start of a ROM image.
+    pub fn from_header(header: &[u8]) -> Result<CartInfo, String> {
 
         // error out early if the header data is too short.
         if header.len() < CartInfo::HEADER_LENGTH {
@@ -57,7 +95,16 @@ impl CartInfo{
         }
 
         // Construct a mutable info object to populate.
+        let title_end = CartInfo::HEADER_TITLE_OFFSET + CartInfo::HEADER_TITLE_LENGTH;
+        let title = header[CartInfo::HEADER_TITLE_OFFSET..title_end].iter()
+            .take_while(|&&c| c != 0)
+            .filter(|c| c.is_ascii_graphic() || **c == b' ')
+            .map(|&c| c as char)
+            .collect();
+
         let mut info = CartInfo {
+            title,
+            cart_type: header[CartInfo::HEADER_TYPE_OFFSET],
             mapper:MapperType::Rom,
             rom_size:0,
             ram_size:0,
@@ -69,6 +116,8 @@ impl CartInfo{
         // Obtain info from the cartrige type byte
         match header[CartInfo::HEADER_TYPE_OFFSET] {
             0x00 => { }
+            0x08 => { }
+            0x09 => {info.battery = true},
             0x01 => {info.mapper = MapperType::Mbc1;},
             0x02 => {info.mapper = MapperType::Mbc1;},
             0x03 => {info.mapper = MapperType::Mbc1;
@@ -76,10 +125,27 @@ impl CartInfo{
             0x05 => {info.mapper = MapperType::Mbc2;},
             0x06 => {info.mapper = MapperType::Mbc2;
                      info.battery = true},
+            0x0F => {info.mapper = MapperType::Mbc3;
+                     info.battery = true;
+                     info.timer = true},
+            0x10 => {info.mapper = MapperType::Mbc3;
+                     info.battery = true;
+                     info.timer = true},
+            0x11 => {info.mapper = MapperType::Mbc3;},
+            0x12 => {info.mapper = MapperType::Mbc3;},
             0x13 => {info.mapper = MapperType::Mbc3;
                      info.battery = true},
+            0x19 => {info.mapper = MapperType::Mbc5;},
+            0x1A => {info.mapper = MapperType::Mbc5;},
             0x1B => {info.mapper = MapperType::Mbc5;
                      info.battery = true},
+            0x1C => {info.mapper = MapperType::Mbc5;
+                     info.rumble = true},
+            0x1D => {info.mapper = MapperType::Mbc5;
+                     info.rumble = true},
+            0x1E => {info.mapper = MapperType::Mbc5;
+                     info.battery = true;
+                     info.rumble = true},
             // TODO Complete cartrige type parsing.
             value => {
                 return Err(format!("Unsupported cartrige type: {:#02X}", value))
@@ -128,51 +194,37 @@ pub struct Cartrige
     rom: Vec<u8>,
     ram: Vec<u8>,
     mapper: Box<dyn MapperRW>,
+    info: Option<CartInfo>,
 }
 
 impl Cartrige {
+    /// Magic bytes at the start of a saved cartrige state.
+    const STATE_MAGIC: &'static [u8;4] = b"DCRT";
+    /// The current binary format version.
+    const STATE_VERSION: u16 = 1;
+
     pub fn new() -> Cartrige{
         Cartrige {
             ram: vec![0u8, 0],
             rom: vec![0u8, 0],
             mapper: Box::new(no_mapper::NoMapper{}),
+            info: None,
         }
     }
 
     pub fn from_data(rom_data: &[u8]) -> Result<Cartrige, String>
     {
-        if rom_data.len() > 0x8000{
-            return Err("Rom data was too long.".to_owned());
-        }
-
-        let info = CartInfo::from_header(rom_data)?;
-
-        let ram: Vec<u8> = vec![0;info.ram_size];
-        let rom: Vec<u8> = Vec::from(&rom_data[0..info.rom_size]);
-
-        let mapper:Box<dyn MapperRW> = 
-            match info.mapper {
-                MapperType::Rom => {
-                    Box::new(no_mapper::NoMapper{})
-                },
-                MapperType::Mbc1 => {
-                    Box::new(mbc1::Mbc1Cart::new())
-                },
-                _ => panic!("Unsupported mapper type")
-            };
-
-        let cart = Cartrige {
-            rom,
-            ram,
-            mapper,
-        };
-
+        let mut cart = Cartrige::new();
+        cart.load_rom(rom_data)?;
         Ok(cart)
     }
 
     pub fn load_rom(&mut self, rom_data: &[u8]) -> Result<(), String>
     {
         let info = CartInfo::from_header(rom_data)?;
+        if rom_data.len() < info.rom_size {
+            return Err("Rom data was too short.".to_owned());
+        }
 
         let ram: Vec<u8> = vec![0;info.ram_size];
         let rom: Vec<u8> = Vec::from(&rom_data[0..info.rom_size]);
@@ -191,6 +243,9 @@ impl Cartrige {
                 MapperType::Mbc3 => {
                     Box::new(mbc3::Mbc3Cart::new())
                 }
+                MapperType::Mbc5 if info.rumble => {
+                    Box::new(mbc5::Mbc5Cart::with_rumble())
+                }
                 MapperType::Mbc5 => {
                     Box::new(mbc5::Mbc5Cart::new())
                 }
@@ -200,10 +255,32 @@ impl Cartrige {
         self.rom = rom;
         self.ram = ram;
         self.mapper = mapper;
+        self.info = Some(info);
 
         Ok(())
     }
 
+    /// The header info of the loaded ROM, if one has been loaded.
+    pub fn info(&self) -> Option<&CartInfo> {
+        self.info.as_ref()
+    }
+
+    /// The cartrige real time clock, if the mapper has one.
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mapper.rtc()
+    }
+
+    /// Mutable access to the cartrige real time clock, used to inject the
+    /// current time and to restore saved clock state.
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.mapper.rtc_mut()
+    }
+
+    /// True while the cartrige rumble motor is switched on.
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+
     pub fn load_ram(&mut self, sram: &[u8]) {
         if sram.len() == self.ram.len(){
             self.ram.clone_from_slice(sram)
@@ -222,10 +299,52 @@ impl BusRW for Cartrige {
         self.mapper.as_mut().read(&mut self.ram[..], &mut self.rom[..], addr as u16)
     }
 
+    fn bus_peek8(&self, addr:usize) -> u8
+    {
+        self.mapper.peek(&self.ram[..], &self.rom[..], addr as u16)
+    }
+
     fn bus_write8(&mut self, addr:usize, value:u8)
     {
         self.mapper.as_mut().write(&mut self.ram[..], &mut self.rom[..], addr as u16, value);
     }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+/// The saved state holds the cartrige RAM and the mapper registers, but not
+/// the ROM. It can only be restored onto a cartrige with the same ROM loaded.
+impl Snapshotable for Cartrige {
+    fn save_state(&self) -> Vec<u8> {
+        let mapper = self.mapper.save_state();
+        let mut w = StateWriter::new(Cartrige::STATE_MAGIC, Cartrige::STATE_VERSION);
+        w.write_u32(self.ram.len() as u32);
+        w.write_bytes(&self.ram);
+        w.write_u32(mapper.len() as u32);
+        w.write_bytes(&mapper);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Cartrige::STATE_MAGIC)?;
+        if version != Cartrige::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let ram_len = r.read_u32()? as usize;
+        if ram_len != self.ram.len() {
+            return Err(StateError::Invalid(format!(
+                "saved {} bytes of cartrige RAM, but the cartrige has {}", ram_len, self.ram.len())));
+        }
+        let ram = r.read_bytes(ram_len)?;
+        let mapper_len = r.read_u32()? as usize;
+        let mapper = r.read_bytes(mapper_len)?;
+        r.finish()?;
+        self.mapper.load_state(mapper)?;
+        self.ram.copy_from_slice(ram);
+        Ok(())
+    }
 }
 
 impl Default for Cartrige {
@@ -233,3 +352,112 @@ impl Default for Cartrige {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_rom(title: &[u8], cart_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[CartInfo::HEADER_TITLE_OFFSET..CartInfo::HEADER_TITLE_OFFSET + title.len()]
+            .copy_from_slice(title);
+        rom[CartInfo::HEADER_TYPE_OFFSET] = cart_type;
+        rom[CartInfo::HEADER_ROM_SIZE_OFFSET] = 0x00;
+        rom[CartInfo::HEADER_RAM_SIZE_OFFSET] = ram_size;
+        rom
+    }
+
+    #[test]
+    fn test_header_info() {
+        let cart = Cartrige::from_data(&make_rom(b"TETRIS", 0x00, 0x00)).unwrap();
+        let info = cart.info().unwrap();
+
+        assert_eq!(info.title, "TETRIS");
+        assert_eq!(info.cart_type, 0x00);
+        assert_eq!(info.mapper, MapperType::Rom);
+        assert_eq!(info.rom_size, 0x8000);
+        assert_eq!(info.ram_size, 0);
+        assert!(!info.battery);
+    }
+
+    #[test]
+    fn test_header_too_short() {
+        assert!(CartInfo::from_header(&[0u8; 0x100]).is_err());
+        assert!(Cartrige::from_data(&make_rom(b"", 0x00, 0x00)[..0x4000]).is_err());
+    }
+
+    #[test]
+    fn test_rom_only_reads_and_ignores_writes() {
+        let mut rom = make_rom(b"ROM", 0x00, 0x00);
+        rom[0x0100] = 0x12;
+        rom[0x7FFF] = 0x34;
+        let mut cart = Cartrige::from_data(&rom).unwrap();
+
+        assert_eq!(cart.bus_read8(0x0100), 0x12);
+        assert_eq!(cart.bus_read8(0x7FFF), 0x34);
+
+        cart.bus_write8(0x0100, 0xFF);
+        assert_eq!(cart.bus_read8(0x0100), 0x12);
+
+        // No RAM on the cart.
+        cart.bus_write8(0xA000, 0x55);
+        assert_eq!(cart.bus_read8(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rom_only_with_ram() {
+        let mut cart = Cartrige::from_data(&make_rom(b"RAM", 0x09, 0x02)).unwrap();
+        assert!(cart.info().unwrap().battery);
+        assert_eq!(cart.info().unwrap().ram_size, 0x2000);
+
+        cart.bus_write8(0xA000, 0x55);
+        cart.bus_write8(0xBFFF, 0xAA);
+        assert_eq!(cart.bus_read8(0xA000), 0x55);
+        assert_eq!(cart.bus_read8(0xBFFF), 0xAA);
+    }
+
+    #[test]
+    fn test_mbc3_timer_cart_has_rtc() {
+        let mut cart = Cartrige::from_data(&make_rom(b"CLOCK", 0x10, 0x03)).unwrap();
+        assert!(cart.info().unwrap().timer);
+        assert_eq!(cart.info().unwrap().mapper, MapperType::Mbc3);
+
+        cart.rtc_mut().unwrap().update(100);
+        cart.rtc_mut().unwrap().update(130);
+        cart.bus_write8(0x0000, 0x0A);
+        cart.bus_write8(0x6000, 0x00);
+        cart.bus_write8(0x6000, 0x01);
+        cart.bus_write8(0x4000, Rtc::SECONDS_REG);
+        assert_eq!(cart.bus_read8(0xA000), 30);
+
+        let rom_only = Cartrige::from_data(&make_rom(b"ROM", 0x00, 0x00)).unwrap();
+        assert!(rom_only.rtc().is_none());
+    }
+
+    #[test]
+    fn test_save_state_keeps_mbc3_clock() {
+        let rom = make_rom(b"CLOCK", 0x10, 0x03);
+        let mut cart = Cartrige::from_data(&rom).unwrap();
+        cart.rtc_mut().unwrap().update(100);
+        cart.rtc_mut().unwrap().update(145);
+        cart.bus_write8(0x0000, 0x0A);
+        cart.bus_write8(0x4000, 0x02);
+        cart.bus_write8(0xA000, 0x5A);
+        cart.bus_write8(0x6000, 0x00);
+        cart.bus_write8(0x6000, 0x01);
+        cart.bus_write8(0x4000, Rtc::SECONDS_REG);
+        let state = cart.save_state();
+
+        let mut restored = Cartrige::from_data(&rom).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.rtc(), cart.rtc());
+        assert_eq!(restored.bus_read8(0xA000), 45);
+        restored.bus_write8(0x4000, 0x02);
+        assert_eq!(restored.bus_read8(0xA000), 0x5A);
+
+        let mut rom_only = Cartrige::from_data(&make_rom(b"ROM", 0x00, 0x00)).unwrap();
+        assert!(rom_only.load_state(&state).is_err());
+        assert!(restored.load_state(&state[..state.len() - 1]).is_err());
+    }
+}
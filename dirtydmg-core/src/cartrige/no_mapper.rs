@@ -1,15 +1,25 @@
 use super::MapperRW;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 
 pub struct NoMapper { }
 
+impl NoMapper {
+    const STATE_MAGIC: &'static [u8;4] = b"DNOM";
+    const STATE_VERSION: u16 = 1;
+}
+
 impl MapperRW for NoMapper {
 
-    fn read(&mut self, _ram: &mut [u8], rom: &mut [u8], addr:u16) -> u8{
+    fn peek(&self, ram: &[u8], rom: &[u8], addr:u16) -> u8{
         match addr {
             // Return values strait up if they are in the rom area.
             0x0000..=0x7FFF => {
                 rom[addr as usize]
             }
+            // Optional ram, unmapped addresses read as 0xFF.
+            0xA000..=0xBFFF => {
+                *ram.get((addr - 0xA000) as usize).unwrap_or(&0xFF)
+            }
             // For any other address, return 0xFF.
             _ => {
                 0xFF
@@ -17,7 +27,26 @@ impl MapperRW for NoMapper {
         }
     }
 
-    fn write(&mut self, _:&mut [u8], _:&mut [u8], _:u16, _:u8) {
-        // Nothing to do, ROM only.
+    fn write(&mut self, ram:&mut [u8], _:&mut [u8], addr:u16, value:u8) {
+        // ROM writes are ignored, only the optional ram is writable.
+        if let 0xA000..=0xBFFF = addr {
+            if let Some(byte) = ram.get_mut((addr - 0xA000) as usize) {
+                *byte = value;
+            }
+        }
+    }
+}
+/// There are no registers, the state only identifies the mapper.
+impl Snapshotable for NoMapper {
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new(NoMapper::STATE_MAGIC, NoMapper::STATE_VERSION).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (r, version) = StateReader::new(data, NoMapper::STATE_MAGIC)?;
+        if version != NoMapper::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        r.finish()
     }
-}
\ No newline at end of file
+}
@@ -141,11 +141,6 @@ pub struct Cpu {
 #[allow(dead_code)]
 impl Cpu {
     const ISR_OVERHEAD_CYCLES:u8 = 5;
-    const VBLANK_ISR_ADDR:u16 = 0x40;
-    const LCD_ISR_ADDR:u16    = 0x48;
-    const TIMER_ISR_ADDR:u16  = 0x50;
-    const SERIAL_ISR_ADDR:u16 = 0x58;
-    const JOYPAD_ISR_ADDR:u16 = 0x60;
 
     pub fn new()->Cpu {
         Cpu {
@@ -221,45 +216,16 @@ impl Cpu {
     /// Checks for and handles pending interrupts
     /// 
     /// Returns the number of busy cycles if an ISR is started, or zero if no interrupt started.
-    #[allow(clippy::collapsible_if)]
     fn update_interrupts(&mut self, bus: &mut impl BusRW, is: &mut InterruptStatus) -> bool
     {
         let mut started:bool = false;
 
-        // Check for interrupts if enabled.
-        if is.is_vblank_active()
+        // Service the highest priority interrupt if enabled.
+        if let Some(interrupt) = is.next_pending()
         {
-            if self.start_isr(bus, Cpu::VBLANK_ISR_ADDR){
+            if self.start_isr(bus, interrupt.vector()){
                 started = true;
-                is.clear_vblank();
-            }
-        }
-        else if is.is_lcdstat_active()
-        {
-            if self.start_isr(bus, Cpu::LCD_ISR_ADDR){
-                started = true;
-                is.clear_lcdstat();
-            }
-        }
-        else if is.is_timer_active()
-        {
-            if self.start_isr(bus, Cpu::TIMER_ISR_ADDR){
-                started = true;
-                is.clear_timer();
-            }
-        }
-        else if is.is_serial_active()
-        {
-            if self.start_isr(bus, Cpu::SERIAL_ISR_ADDR){
-                started = true;
-                is.clear_serial();
-            }
-        }
-        else if is.is_joypad_active()
-        {
-            if self.start_isr(bus, Cpu::JOYPAD_ISR_ADDR){
-                started = true;
-                is.clear_joypad();
+                is.clear(interrupt);
             }
         }
 
@@ -538,6 +504,7 @@ impl Cpu {
 
             // Increment a 16 bit register.
             IncR16{dst} => {
+                bus.bus_idu_access(self.reg.read16(*dst) as usize);
                 let i = self.reg.read16(*dst).wrapping_add(1);
                 self.reg.write16(*dst, i);
             },
@@ -632,6 +599,7 @@ impl Cpu {
 
             // Decrement a 16 bit register
             DecR16{dst} => {
+                bus.bus_idu_access(self.reg.read16(*dst) as usize);
                 let i = self.reg.read16(*dst).wrapping_sub(1);
                 self.reg.write16(*dst, i);
             },
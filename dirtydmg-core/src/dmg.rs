@@ -1,62 +1,73 @@
 use std::rc::Rc;
 use std::cell::RefCell;
 use crate::cpu::{Cpu};
-use crate::ram::Ram;
-use crate::bus::{Bus, BusItem};
+use crate::ram::{Ram, HighRam};
+use crate::bus::{Bus, BusItem, BusRW, MirrorDevice};
+use crate::bootrom::BootRom;
 use crate::interrupt::InterruptStatus;
 use crate::cartrige::{Cartrige};
-use crate::ppu::{PPU};
-use crate::serial::{SerialUnit};
+use crate::ppu::{PPU, OamBugAccess, FRAMEBUFFER_SIZE};
+use crate::serial::{SerialUnit, SerialCallback};
 use crate::timer::TimerUnit;
 use crate::input::{Gamepad, Button};
 use crate::sound::Apu;
 
-//DEBUG! should not stay!
-use std::io::stdout;
-use std::io::Write;
-
 pub struct Dmg{
     pub cpu:Cpu,
     ram: Rc<RefCell<Ram>>,
     bus: Bus,
     isr: Rc<RefCell<InterruptStatus>>,
     cart: Rc<RefCell<Cartrige>>,
+    boot_rom: Rc<RefCell<BootRom>>,
     pub ppu: Rc<RefCell<PPU>>,
     stu: Rc<RefCell<SerialUnit>>,
     tu: Rc<RefCell<TimerUnit>>,
     gamepad: Rc<RefCell<Gamepad>>,
     pub apu: Rc<RefCell<Apu>>,
+    /// Ticks run past the end of the last frame by the final instruction.
+    frame_overrun: u32,
+    /// Copy of the last completed frame, returned by run_frame.
+    frame: [u8;FRAMEBUFFER_SIZE],
 }
 
 impl Dmg {
+    /// Number of clock ticks in one frame, 154 lines of 456 ticks.
+    pub const TICKS_PER_FRAME: u32 = 70224;
 
     pub fn new() -> Dmg {
         // components that will live on the bus
         let ram = Rc::new(RefCell::new(Ram::new(0x2000, 0xC000)));
-        let zero_page = Rc::new(RefCell::new(Ram::new(127, 0xFF80)));
+        let high_ram = Rc::new(RefCell::new(HighRam::new()));
         let isr = Rc::new(RefCell::new(InterruptStatus::new()));
         let cart = Rc::new(RefCell::new(Cartrige::new()));
-        let ppu = Rc::new(RefCell::new(PPU::new()));
+        let ppu = Rc::new(RefCell::new(PPU::new_post_boot()));
         let stu = Rc::new(RefCell::new(SerialUnit::new()));
         let tu = Rc::new(RefCell::new(TimerUnit::new()));
         let gamepad =  Rc::new(RefCell::new(Gamepad::new()));
         let apu = Rc::new(RefCell::new(Apu::new()));
-
+        let echo_ram = Rc::new(RefCell::new(MirrorDevice::new(ram.clone(), 0x2000)));
+        let boot_rom = Rc::new(RefCell::new(BootRom::new(cart.clone())));
+        stu.borrow_mut().set_interrupts(Some(isr.clone()));
+        tu.borrow_mut().set_interrupts(Some(isr.clone()));
 
         // Map components to the bus.
         let mut bus = Bus::new();
-        bus.add_item(BusItem::new(0x0000, 0x7FFF, cart.clone()));
-        bus.add_item(BusItem::new(0x8000, 0x9FFF, ppu.clone()));
-        bus.add_item(BusItem::new(0xA000, 0xBFFF, cart.clone()));
-        bus.add_item(BusItem::new(0xC000, 0xDFFF, ram.clone()));
-        bus.add_item(BusItem::new(0xFF00, 0xFF00, gamepad.clone()));
-        //bus.add_item(BusItem::new(0xFF01, 0xFF02, stu.clone()));
-        bus.add_item(BusItem::new(0xFF04, 0xFF07, tu.clone()));
-        bus.add_item(BusItem::new(0xFF0F, 0xFF0F, isr.clone()));
-        bus.add_item(BusItem::new(0xFF10, 0xFF3F, apu.clone()));
-        bus.add_item(BusItem::new(0xFF40, 0xFF4B, ppu.clone()));
-        bus.add_item(BusItem::new(0xFF80, 0xFFFE, zero_page));
-        bus.add_item(BusItem::new(0xFFFF, 0xFFFF, isr.clone()));
+        bus.add_item(BusItem::new(0x0000, 0x00FF, boot_rom.clone())).unwrap();
+        bus.add_item(BusItem::new(0x0100, 0x7FFF, cart.clone())).unwrap();
+        bus.add_item(BusItem::new(0x8000, 0x9FFF, ppu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xA000, 0xBFFF, cart.clone())).unwrap();
+        bus.add_item(BusItem::new(0xC000, 0xDFFF, ram.clone())).unwrap();
+        bus.add_item(BusItem::new(0xE000, 0xFDFF, echo_ram)).unwrap();
+        bus.add_item(BusItem::new(0xFE00, 0xFE9F, ppu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF00, 0xFF00, gamepad.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF01, 0xFF02, stu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF04, 0xFF07, tu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF0F, 0xFF0F, isr.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF10, 0xFF3F, apu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF40, 0xFF4B, ppu.clone())).unwrap();
+        bus.add_item(BusItem::new(0xFF50, 0xFF50, boot_rom.clone())).unwrap();
+        bus.add_item(BusItem::new(HighRam::START_ADDRESS, HighRam::END_ADDRESS, high_ram)).unwrap();
+        bus.add_item(BusItem::new(0xFFFF, 0xFFFF, isr.clone())).unwrap();
 
         let mut cpu = Cpu::new();
         cpu.reg.a = 0x01;
@@ -73,20 +84,36 @@ impl Dmg {
             bus,
             isr,
             cart,
+            boot_rom,
             ppu,
             stu,
             tu,
             gamepad,
             apu,
+            frame_overrun: 0,
+            frame: [0;FRAMEBUFFER_SIZE],
         }
     }
 
     // Attempts to load the specified rom file into the system.
     pub fn load_rom(&mut self, data: &[u8]) -> Result<(), String>{
-        self.cpu.reg.pc = 0x100;
+        if !self.boot_rom.as_ref().borrow().is_active() {
+            self.cpu.reg.pc = 0x100;
+        }
         self.cart.as_ref().borrow_mut().load_rom(data)
     }
 
+    /// Maps in a boot ROM and starts execution from it.
+    /// 
+    /// The PPU is put back in its power on state for the boot ROM to set up,
+    /// so this should be called before anything is configured on the PPU.
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> Result<(), String>{
+        self.boot_rom.as_ref().borrow_mut().load(data)?;
+        *self.ppu.as_ref().borrow_mut() = PPU::new();
+        self.cpu.reg.pc = 0;
+        Ok(())
+    }
+
     pub fn load_sram(&mut self, data: &[u8]){
         self.cart.as_ref().borrow_mut().load_ram(data);
     }
@@ -95,34 +122,119 @@ impl Dmg {
         self.cart.as_ref().borrow_mut().get_ram(buffer);
     }
 
-    pub fn update(&mut self) {
-
-        self.cpu.handle_interrupts(&mut self.bus, &mut self.isr.as_ref().borrow_mut());
-        let cycles = self.cpu.update(&mut self.bus);
+    /// Executes a single instruction, or interrupt dispatch, and advances
+    /// the rest of the system to match.
+    /// 
+    /// Returns the number of clock ticks that elapsed.
+    pub fn step(&mut self) -> u32 {
+        let mut cpu_bus = DmaGuard {bus: &mut self.bus, ppu: &self.ppu};
+        self.cpu.handle_interrupts(&mut cpu_bus, &mut self.isr.as_ref().borrow_mut());
+        let cycles = self.cpu.update(&mut cpu_bus);
         self.ppu.as_ref().borrow_mut().execute_ticks(cycles as u16 * 4, &mut self.bus, &mut self.isr.as_ref().borrow_mut());
-        {
-            let mut stu = self.stu.as_ref().borrow_mut();
-            stu.update(cycles as u32, &mut self.isr.as_ref().borrow_mut());
-            if let Some(x) = stu.get_output() {
-                print!("{} ", x as char);
-                stdout().flush().unwrap();
-            };
-        }
-        // Update the timer unit with cpu ticks (not machine cycles)
-        self.tu.as_ref().borrow_mut().update(
-            cycles as u16 * 4, 
-            &mut self.isr.as_ref().borrow_mut());
+        // The serial port and timer request their interrupts through the
+        // handle they were given in new.
+        self.bus.tick_all(cycles as u32 * 4);
         self.apu.as_ref().borrow_mut().tick(cycles as u16 * 4);
+        cycles as u32 * 4
+    }
+
+    /// Same as step, kept for existing callers.
+    pub fn update(&mut self) -> u32 {
+        self.step()
+    }
+
+    /// Runs the system for one frame worth of ticks and returns the last
+    /// completed frame.
+    /// 
+    /// Instructions can't be split, so a frame can run a few ticks long. 
+    /// The overrun is taken out of the next frame to keep frames in step
+    /// with the PPU. The PPU passes through exactly one VBlank per frame, so
+    /// the returned frame is always the one finished during this call. It
+    /// uses the same pixel format as `PPU::framebuffer`.
+    pub fn run_frame(&mut self) -> &[u8] {
+        let mut ticks = self.frame_overrun;
+        while ticks < Dmg::TICKS_PER_FRAME {
+            ticks += self.step();
+        }
+        self.frame_overrun = ticks - Dmg::TICKS_PER_FRAME;
+
+        self.frame.copy_from_slice(self.ppu.as_ref().borrow().framebuffer());
+        &self.frame
+    }
+
+    /// Sets a hook that is called with every byte sent over serial, None 
+    /// removes it.
+    /// 
+    /// Test ROMs commonly print their results this way.
+    pub fn set_serial_output_hook(&mut self, hook: Option<SerialCallback>) {
+        self.stu.as_ref().borrow_mut().set_output_hook(hook);
     }
 
     pub fn input(&mut self, btn:Button, pressed:bool) {
-        if pressed {
-            self.isr.as_ref().borrow_mut().request_joypad();
-            self.gamepad.as_ref().borrow_mut().press_btn(btn);
+        self.gamepad.as_ref().borrow_mut().set_button(
+            btn,
+            pressed,
+            &mut self.isr.as_ref().borrow_mut());
+    }
+}
+
+/// The CPU's view of the bus, which loses everything but high RAM while an
+/// OAM DMA transfer runs.
+/// 
+/// The DMA itself reads through the bus directly, so only CPU accesses are 
+/// blocked. CPU accesses to 0xFE00-0xFEFF are also reported to the PPU for
+/// the OAM bug.
+struct DmaGuard<'a> {
+    bus: &'a mut Bus,
+    ppu: &'a RefCell<PPU>,
+}
+
+impl DmaGuard<'_> {
+    fn oam_bug_access(&self, addr: usize, kind: OamBugAccess) {
+        if let 0xFE00..=0xFEFF = addr {
+            self.ppu.borrow_mut().oam_bug_access(addr, kind);
+        }
+    }
+}
+
+impl BusRW for DmaGuard<'_> {
+    fn bus_read8(&mut self, addr: usize) -> u8 {
+        self.oam_bug_access(addr, OamBugAccess::Read);
+        if self.ppu.borrow().dma_blocks_address(addr) {
+            0xFF
         } else {
-            self.gamepad.as_ref().borrow_mut().release_btn(btn);
+            self.bus.bus_read8(addr)
+        }
+    }
+
+    fn bus_write8(&mut self, addr: usize, value: u8) {
+        self.oam_bug_access(addr, OamBugAccess::Write);
+        if !self.ppu.borrow().dma_blocks_address(addr) {
+            self.bus.bus_write8(addr, value);
         }
     }
+
+    /// Register increments and decrements corrupt OAM like writes do.
+    fn bus_idu_access(&mut self, addr: usize) {
+        self.oam_bug_access(addr, OamBugAccess::Write);
+    }
+
+    fn bus_peek8(&self, addr: usize) -> u8 {
+        self.bus.bus_peek8(addr)
+    }
+
+    /// Checks each byte on its own, low byte first like the Bus.
+    fn bus_read16(&mut self, addr: usize) -> u16 {
+        let low_byte = self.bus_read8(addr) as u16;
+        let high_byte = self.bus_read8(addr + 1) as u16;
+        (high_byte << 8) | low_byte
+    }
+
+    /// Checks each byte on its own, low byte first like the Bus.
+    fn bus_write16(&mut self, addr: usize, value: u16) {
+        self.bus_write8(addr, value as u8);
+        self.bus_write8(addr + 1, (value >> 8) as u8);
+    }
 }
 
 impl Default for Dmg {
@@ -130,3 +242,276 @@ impl Default for Dmg {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a ROM only cartrige that loops forever at 0x100.
+    fn idle_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        // jr -2
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        rom
+    }
+
+    #[test]
+    fn test_run_frame_draws_background() {
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&idle_rom()).unwrap();
+
+        // Tile 0 is solid color 1, and the palette maps it straight through.
+        // The post boot LCDC uses unsigned tile addressing, so tile 0 is at 0x8000.
+        for addr in (0x8000..0x8010).step_by(2) {
+            dmg.bus.bus_write8(addr, 0xFF);
+        }
+        dmg.bus.bus_write8(0xFF47, 0xE4);
+
+        dmg.run_frame();
+        let frame = dmg.run_frame();
+        assert_eq!(frame.len(), FRAMEBUFFER_SIZE);
+        assert!(frame.iter().all(|&pixel| pixel == 1));
+    }
+
+    /// Sets up a checkered background of two tiles.
+    fn checker_dmg() -> Dmg {
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&idle_rom()).unwrap();
+        for row in 0..8 {
+            dmg.bus.bus_write8(0x8010 + row * 2, 0xF0);
+            dmg.bus.bus_write8(0x8011 + row * 2, 0x3C);
+        }
+        for entry in 0..0x400 {
+            dmg.bus.bus_write8(0x9800 + entry, ((entry + entry / 32) & 1) as u8);
+        }
+        dmg.bus.bus_write8(0xFF47, 0xE4);
+        dmg
+    }
+
+    #[test]
+    fn test_run_frame_is_deterministic() {
+        let mut dmg = checker_dmg();
+        dmg.run_frame();
+        let first = dmg.run_frame().to_vec();
+        let second = dmg.run_frame().to_vec();
+        assert!(first.iter().any(|&pixel| pixel != 0));
+        assert_eq!(first, second);
+
+        // A separate system given the same inputs draws the same frames.
+        let mut other = checker_dmg();
+        other.run_frame();
+        assert_eq!(other.run_frame(), &first[..]);
+    }
+
+    #[test]
+    fn test_run_frame_stays_in_step_with_ppu() {
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&idle_rom()).unwrap();
+
+        // The 12 tick loop divides a frame evenly, so every frame ends at
+        // the same point in the PPU's frame.
+        dmg.run_frame();
+        let ly = dmg.bus.bus_read8(0xFF44);
+        for _ in 0..3 {
+            dmg.run_frame();
+            assert_eq!(dmg.frame_overrun, 0);
+            assert_eq!(dmg.bus.bus_read8(0xFF44), ly);
+        }
+    }
+
+    #[test]
+    fn test_boot_rom_starts_from_power_on_ppu() {
+        let mut dmg = Dmg::new();
+        assert_eq!(dmg.bus.bus_read8(0xFF40), 0x91);
+        assert_eq!(dmg.ppu.borrow().line(), 153);
+
+        dmg.load_boot_rom(&[0u8; 0x100]).unwrap();
+        assert_eq!(dmg.cpu.reg.pc, 0);
+        assert_eq!(dmg.bus.bus_read8(0xFF40), PPU::new().lcdc());
+        assert_eq!(dmg.ppu.borrow().line(), 0);
+
+        // A rejected image leaves the post boot state alone.
+        let mut dmg = Dmg::new();
+        assert!(dmg.load_boot_rom(&[0u8; 0x10]).is_err());
+        assert_eq!(dmg.bus.bus_read8(0xFF40), 0x91);
+    }
+
+    #[test]
+    fn test_serial_output_hook() {
+        let mut rom = idle_rom();
+        // ld a, 'H'; ldh (SB), a; ld a, 0x81; ldh (SC), a; jr -2
+        let program = [0x3E, b'H', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&rom).unwrap();
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let hook_sent = sent.clone();
+        dmg.set_serial_output_hook(Some(Box::new(move |b| hook_sent.borrow_mut().push(b))));
+
+        dmg.run_frame();
+        assert_eq!(*sent.borrow(), vec![b'H']);
+        assert_ne!(dmg.bus.bus_read8(0xFF0F) & 0x08, 0);
+        assert_eq!(dmg.bus.bus_read8(0xFF02) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_dma_leaves_only_high_ram() {
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&idle_rom()).unwrap();
+        dmg.bus.bus_write8(0xC000, 0x12);
+        dmg.bus.bus_write8(0xFF90, 0x34);
+        dmg.bus.bus_write8(0xFF46, 0xC0);
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let log = accesses.clone();
+        dmg.bus.set_trace(Some(Box::new(move |access| log.borrow_mut().push(access.addr))));
+
+        let mut cpu_bus = DmaGuard {bus: &mut dmg.bus, ppu: &dmg.ppu};
+        assert_eq!(cpu_bus.bus_read8(0xFF90), 0x34);
+        assert_eq!(cpu_bus.bus_read8(0xC000), 0xFF);
+        assert_eq!(cpu_bus.bus_read8(0x0100), 0xFF);
+        assert_eq!(cpu_bus.bus_read8(0xFF44), 0xFF);
+        cpu_bus.bus_write8(0xFF91, 0x56);
+        cpu_bus.bus_write8(0xC001, 0x78);
+        assert_eq!(cpu_bus.bus_read8(0xFF91), 0x56);
+
+        // 16 bit accesses are checked a byte at a time, so one straddling 
+        // the end of high RAM only reaches its low byte.
+        cpu_bus.bus_write16(0xFF92, 0x9ABC);
+        accesses.borrow_mut().clear();
+        assert_eq!(cpu_bus.bus_read16(0xFF92), 0x9ABC);
+        assert_eq!(*accesses.borrow(), [0xFF92, 0xFF93]);
+        assert_eq!(cpu_bus.bus_read16(0xC000), 0xFFFF);
+        cpu_bus.bus_write16(0xFFFE, 0xDEF0);
+        assert_eq!(cpu_bus.bus_read16(0xFFFE), 0xFFF0);
+        cpu_bus.bus_write16(0xC002, 0x1234);
+
+        // Once the transfer ends the rest of the bus is back.
+        while dmg.ppu.borrow().dma_active() {
+            dmg.step();
+        }
+        let mut cpu_bus = DmaGuard {bus: &mut dmg.bus, ppu: &dmg.ppu};
+        assert_eq!(cpu_bus.bus_read8(0xC000), 0x12);
+        assert_eq!(cpu_bus.bus_read8(0xC001), 0x00);
+        assert_eq!(cpu_bus.bus_read16(0xC002), 0x0000);
+        assert_eq!(cpu_bus.bus_read8(0x0100), 0x18);
+    }
+
+    #[test]
+    fn test_save_state_keeps_cartrige_banks() {
+        // A 64KB MBC1 cart with battery backed RAM, each bank starting
+        // with its own number.
+        let mut rom = idle_rom();
+        rom.resize(0x10000, 0);
+        rom[0x147] = 0x03;
+        rom[0x148] = 0x01;
+        rom[0x149] = 0x02;
+        for bank in 1..4 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&rom).unwrap();
+        dmg.bus.bus_write8(0x0000, 0x0A);
+        dmg.bus.bus_write8(0x2000, 3);
+        dmg.bus.bus_write8(0xA000, 0x77);
+        dmg.bus.bus_write8(0xFF01, 0x42);
+        let state = dmg.bus.save_state().unwrap();
+
+        dmg.bus.bus_write8(0x2000, 2);
+        dmg.bus.bus_write8(0xA000, 0x11);
+        dmg.bus.bus_write8(0x0000, 0x00);
+        dmg.bus.bus_write8(0xFF01, 0x00);
+        assert_eq!(dmg.bus.bus_read8(0x4000), 2);
+
+        let mut restored = Dmg::new();
+        restored.load_rom(&rom).unwrap();
+        for dmg in [&mut dmg, &mut restored].iter_mut() {
+            dmg.bus.load_state(&state).unwrap();
+            assert_eq!(dmg.bus.bus_read8(0x4000), 3);
+            assert_eq!(dmg.bus.bus_read8(0xA000), 0x77);
+            assert_eq!(dmg.bus.bus_read8(0xFF01), 0x42);
+        }
+
+        // The state can't be put onto a cart with a different mapper.
+        let mut other = Dmg::new();
+        other.load_rom(&idle_rom()).unwrap();
+        assert!(other.bus.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_step_ticks_timer() {
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&idle_rom()).unwrap();
+        dmg.bus.bus_write8(0xFF0F, 0x00);
+        dmg.bus.bus_write8(0xFF05, 0xFF);
+        dmg.bus.bus_write8(0xFF06, 0x80);
+        dmg.bus.bus_write8(0xFF07, 0b101);
+
+        let mut ticks = 0;
+        while ticks < 16 {
+            ticks += dmg.step();
+        }
+        assert_ne!(dmg.bus.bus_read8(0xFF0F) & 0x04, 0);
+        assert_eq!(dmg.bus.bus_read8(0xFF05), 0x80);
+    }
+
+    /// Runs a frame of `program` with each OAM byte holding its own offset,
+    /// returning the OAM afterwards.
+    fn run_oam_program(program: &[u8], oam_bug: bool) -> Vec<u8> {
+        let mut rom = idle_rom();
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&rom).unwrap();
+        dmg.ppu.borrow_mut().set_oam_bug(oam_bug);
+
+        // OAM can only be filled with the LCD off.
+        dmg.bus.bus_write8(0xFF40, 0x11);
+        for offset in 0..0xA0 {
+            dmg.bus.bus_write8(0xFE00 + offset, offset as u8);
+        }
+        dmg.bus.bus_write8(0xFF40, 0x91);
+        dmg.run_frame();
+        dmg.bus.dump_range(0xFE00, 0xFE9F)
+    }
+
+    #[test]
+    fn test_oam_bug_from_cpu() {
+        let untouched: Vec<u8> = (0..0xA0).collect();
+        // ld hl, 0xFE00; inc hl; dec hl; jr -4
+        let idu = [0x21, 0x00, 0xFE, 0x23, 0x2B, 0x18, 0xFC];
+        // ld hl, 0xFE00; ld a, (hl); jr -3
+        let read = [0x21, 0x00, 0xFE, 0x7E, 0x18, 0xFD];
+
+        assert_ne!(run_oam_program(&idu, true), untouched);
+        assert_ne!(run_oam_program(&read, true), untouched);
+        assert_eq!(run_oam_program(&idu, false), untouched);
+        assert_eq!(run_oam_program(&read, false), untouched);
+    }
+
+    #[test]
+    fn test_dump_range_reaches_every_device() {
+        let mut rom = idle_rom();
+        rom[0x7FFF] = 0x5A;
+        let mut dmg = Dmg::new();
+        dmg.load_rom(&rom).unwrap();
+
+        assert_eq!(dmg.bus.dump_range(0x0100, 0x7FFF), &rom[0x100..]);
+
+        dmg.bus.bus_write8(0xC000, 0x12);
+        dmg.bus.bus_write8(0xFF00, 0x20);
+        dmg.bus.bus_write8(0xFF01, 0x34);
+        dmg.bus.bus_write8(0xFF06, 0x56);
+        dmg.bus.bus_write8(0xFFFF, 0x1F);
+        dmg.bus.bus_write8(0xFF24, 0x77);
+
+        assert_eq!(dmg.bus.dump_range(0xC000, 0xC000), vec![0x12]);
+        let io = dmg.bus.dump_range(0xFF00, 0xFF0F);
+        assert_eq!(io[0x00], 0xEF);
+        assert_eq!(io[0x01], 0x34);
+        assert_eq!(io[0x06], 0x56);
+        assert_eq!(io[0x0F] & 0xE0, 0xE0);
+        assert_eq!(dmg.bus.dump_range(0xFF24, 0xFF24), vec![0x77]);
+        assert_eq!(dmg.bus.dump_range(0xFFFF, 0xFFFF), vec![0x1F]);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::bus::BusRW;
+use crate::interrupt::InterruptStatus;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 
 pub enum Button {
     A,
@@ -14,8 +16,7 @@ pub enum Button {
 pub struct Gamepad{
     buttons_raw: u8,
     dpad_raw: u8,
-    dpad_active: bool,
-    isr_req: bool,
+    select: u8,
 }
 
 impl Gamepad{
@@ -26,13 +27,16 @@ impl Gamepad{
     const BTN_B_LEFT_MASK:u8      = 0b10;
     const BTN_SELECT_UP_MASK:u8   = 0b100;
     const BTN_START_DOWN_MASK:u8 = 0b1000;
+    const SELECT_MASK:u8 = Gamepad::DPAD_ENABLE_MASK | Gamepad::BTN_ENABLE_MASK;
+    const UNUSED_MASK:u8 = 0b1100_0000;
+    const STATE_MAGIC: &'static [u8;4] = b"DPAD";
+    const STATE_VERSION: u16 = 1;
 
     pub fn new()->Gamepad{
         Gamepad{
             buttons_raw: 0xF,
             dpad_raw: 0xF,
-            dpad_active: false,
-            isr_req: false,
+            select: Gamepad::SELECT_MASK,
         }
     }
 
@@ -60,8 +64,36 @@ impl Gamepad{
         }
     }
 
+    /// Returns true if the group containing `button` is currently selected
+    /// by the select bits in P1 (active low).
+    fn is_selected(&self, button:&Button) -> bool {
+        let enable_mask = if Gamepad::is_dpad(button) {
+            Gamepad::DPAD_ENABLE_MASK
+        } else {
+            Gamepad::BTN_ENABLE_MASK
+        };
+        self.select & enable_mask == 0
+    }
+
+    fn is_pressed(&self, button:&Button) -> bool {
+        let raw = if Gamepad::is_dpad(button) { self.dpad_raw } else { self.buttons_raw };
+        raw & Gamepad::get_mask(button) == 0
+    }
+
+    /// Sets the state of a button, requesting the joypad interrupt when a
+    /// button in the currently selected group goes from released to pressed.
+    pub fn set_button(&mut self, btn:Button, pressed:bool, is:&mut InterruptStatus) {
+        if pressed {
+            if !self.is_pressed(&btn) && self.is_selected(&btn) {
+                is.request_joypad();
+            }
+            self.press_btn(btn);
+        } else {
+            self.release_btn(btn);
+        }
+    }
+
     pub fn press_btn(&mut self, btn:Button) {
-        self.isr_req = true;
         let mask = Gamepad::get_mask(&btn);
         if Gamepad::is_dpad(&btn) {
             self.dpad_raw &= !mask;
@@ -82,22 +114,54 @@ impl Gamepad{
 
 impl BusRW for Gamepad{
 
-    fn bus_read8(&mut self, _addr: usize) -> u8{
-        if self.dpad_active{
-            self.dpad_raw
+    fn bus_read8(&mut self, addr: usize) -> u8{
+        self.bus_peek8(addr)
+    }
+
+    fn bus_peek8(&self, _addr: usize) -> u8{
+        // Selected groups pull their lines low; with both selected the
+        // nibbles are combined, with neither selected all lines read high.
+        let mut lines = 0xF;
+        if self.select & Gamepad::DPAD_ENABLE_MASK == 0 {
+            lines &= self.dpad_raw;
         }
-        else {
-            self.buttons_raw
+        if self.select & Gamepad::BTN_ENABLE_MASK == 0 {
+            lines &= self.buttons_raw;
         }
+        Gamepad::UNUSED_MASK | self.select | lines
     }
 
     fn bus_write8(&mut self, _addr: usize, value: u8) {
-        if value & Gamepad::DPAD_ENABLE_MASK == 0{
-            self.dpad_active = true;
-        }
-        else if value & Gamepad::BTN_ENABLE_MASK == 0{
-            self.dpad_active = false;
+        self.select = value & Gamepad::SELECT_MASK;
+    }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+impl Snapshotable for Gamepad {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(Gamepad::STATE_MAGIC, Gamepad::STATE_VERSION);
+        w.write_u8(self.buttons_raw);
+        w.write_u8(self.dpad_raw);
+        w.write_u8(self.select);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, Gamepad::STATE_MAGIC)?;
+        if version != Gamepad::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
         }
+        let buttons_raw = r.read_u8()?;
+        let dpad_raw = r.read_u8()?;
+        let select = r.read_u8()?;
+        r.finish()?;
+        self.buttons_raw = buttons_raw & 0xF;
+        self.dpad_raw = dpad_raw & 0xF;
+        self.select = select & Gamepad::SELECT_MASK;
+        Ok(())
     }
 }
 
@@ -105,4 +169,97 @@ impl Default for Gamepad {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_test_pack() -> (Gamepad, InterruptStatus) {
+        let mut is = InterruptStatus::new();
+        is.isrmask = 0xFF;
+        (Gamepad::new(), is)
+    }
+
+    #[test]
+    fn test_nothing_selected_reads_high() {
+        let (mut pad, mut is) = get_test_pack();
+        pad.set_button(Button::A, true, &mut is);
+        pad.set_button(Button::Up, true, &mut is);
+        pad.bus_write8(0xFF00, 0x30);
+        assert_eq!(pad.bus_read8(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn test_dpad_nibble() {
+        let (mut pad, mut is) = get_test_pack();
+        pad.bus_write8(0xFF00, 0x20);
+        assert_eq!(pad.bus_read8(0xFF00), 0xEF);
+
+        pad.set_button(Button::Right, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xEE);
+        pad.set_button(Button::Left, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xEC);
+        pad.set_button(Button::Up, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xE8);
+        pad.set_button(Button::Down, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xE0);
+
+        // Action buttons don't show up in the direction nibble.
+        pad.set_button(Button::Right, false, &mut is);
+        pad.set_button(Button::A, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xE1);
+    }
+
+    #[test]
+    fn test_action_nibble() {
+        let (mut pad, mut is) = get_test_pack();
+        pad.bus_write8(0xFF00, 0x10);
+        assert_eq!(pad.bus_read8(0xFF00), 0xDF);
+
+        pad.set_button(Button::A, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xDE);
+        pad.set_button(Button::B, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xDC);
+        pad.set_button(Button::Select, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xD8);
+        pad.set_button(Button::Start, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xD0);
+
+        pad.set_button(Button::A, false, &mut is);
+        pad.set_button(Button::Up, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xD1);
+    }
+
+    #[test]
+    fn test_both_selected_combines_nibbles() {
+        let (mut pad, mut is) = get_test_pack();
+        pad.bus_write8(0xFF00, 0x00);
+        pad.set_button(Button::A, true, &mut is);
+        pad.set_button(Button::Left, true, &mut is);
+        assert_eq!(pad.bus_read8(0xFF00), 0xC0 | 0b1100);
+    }
+
+    #[test]
+    fn test_press_requests_interrupt_when_selected() {
+        let (mut pad, mut is) = get_test_pack();
+        pad.bus_write8(0xFF00, 0x20);
+
+        // Action buttons aren't selected, so no interrupt.
+        pad.set_button(Button::Start, true, &mut is);
+        assert!(!is.is_joypad_active());
+
+        pad.set_button(Button::Down, true, &mut is);
+        assert!(is.is_joypad_active());
+        is.clear_joypad();
+
+        // Holding or releasing a button doesn't request another interrupt.
+        pad.set_button(Button::Down, true, &mut is);
+        pad.set_button(Button::Down, false, &mut is);
+        assert!(!is.is_joypad_active());
+
+        pad.bus_write8(0xFF00, 0x10);
+        pad.set_button(Button::A, true, &mut is);
+        assert!(is.is_joypad_active());
+    }
+}
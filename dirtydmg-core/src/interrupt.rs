@@ -1,4 +1,48 @@
 use crate::bus::BusRW;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
+
+/// The interrupt sources, in priority order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// All interrupts, highest priority first.
+    const PRIORITY: [Interrupt;5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Gets the address of the interrupt's service routine.
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+
+    /// Gets the interrupt's bit in the IF and IE registers.
+    fn mask(&self) -> u8 {
+        match self {
+            Interrupt::VBlank => InterruptStatus::VBLANK_MASK,
+            Interrupt::LcdStat => InterruptStatus::LCDSTAT_MASK,
+            Interrupt::Timer => InterruptStatus::TIMER_MASK,
+            Interrupt::Serial => InterruptStatus::SERIAL_MASK,
+            Interrupt::Joypad => InterruptStatus::JOYPAD_MASK,
+        }
+    }
+}
 
 pub struct InterruptStatus {
     /// Interrupt status request byte. Use the assorted XXX_MASK constants to check for requests.
@@ -16,6 +60,12 @@ impl InterruptStatus {
     const JOYPAD_MASK:u8 =  0b10000;
     const ISR_EN_ADDR:usize = 0xFFFF;
     const ISR_FLAG_ADDR:usize = 0xFF0F;
+    /// Only the lower 5 bits of IF exist, the rest read as 1.
+    const ISR_FLAG_UNUSED_MASK:u8 = 0b1110_0000;
+    /// Magic bytes at the start of a saved interrupt state.
+    const STATE_MAGIC: &'static [u8;4] = b"DINT";
+    /// The current binary format version.
+    const STATE_VERSION: u16 = 1;
 
     /// Constructs a new InterruptStatus object
     /// 
@@ -75,7 +125,32 @@ impl InterruptStatus {
 
     /// Clears the joypad status.
     pub fn clear_joypad(&mut self) {
-        self.isrreq = !InterruptStatus::JOYPAD_MASK;
+        self.isrreq &= !InterruptStatus::JOYPAD_MASK;
+    }
+
+    /// Gets the highest priority interrupt that is both requested and enabled.
+    pub fn next_pending(&self) -> Option<Interrupt> {
+        let pending = self.isrreq & self.isrmask;
+        Interrupt::PRIORITY.iter().copied().find(|x| pending & x.mask() != 0)
+    }
+
+    /// Clears an interrupt's request.
+    pub fn clear(&mut self, interrupt: Interrupt) {
+        self.isrreq &= !interrupt.mask();
+    }
+
+    /// Gets the requested interrupts as IF bits, VBlank in bit 0 through 
+    /// joypad in bit 4.
+    /// 
+    /// Unlike reads of IF, the unused upper bits are 0.
+    pub fn pending_flags(&self) -> u8 {
+        self.isrreq
+    }
+
+    /// Sets the requested interrupts from IF bits, ignoring the unused 
+    /// upper bits.
+    pub fn set_pending_flags(&mut self, flags: u8) {
+        self.isrreq = flags & !InterruptStatus::ISR_FLAG_UNUSED_MASK;
     }
 
     /// Checks if the vblank interrupt is active
@@ -108,31 +183,62 @@ impl BusRW for InterruptStatus {
 
     fn bus_read8(&mut self, addr: usize) -> u8 {
         match addr {
-            InterruptStatus::ISR_EN_ADDR => {
-                self.isrmask
+            InterruptStatus::ISR_EN_ADDR | InterruptStatus::ISR_FLAG_ADDR => {
+                self.bus_peek8(addr)
             },
-            InterruptStatus::ISR_FLAG_ADDR => {
-                self.isrreq
-            }
             _ => {
                 panic!("Interrupt status bus fault reading from {:04X}", addr);
             }
         }
     }
 
+    fn bus_peek8(&self, addr: usize) -> u8 {
+        match addr {
+            InterruptStatus::ISR_EN_ADDR => self.isrmask,
+            InterruptStatus::ISR_FLAG_ADDR => self.isrreq | InterruptStatus::ISR_FLAG_UNUSED_MASK,
+            _ => 0xFF,
+        }
+    }
+
     fn bus_write8(&mut self, addr: usize, value: u8) {
         match addr {
             InterruptStatus::ISR_EN_ADDR => {
                 self.isrmask = value;
             },
             InterruptStatus::ISR_FLAG_ADDR => {
-                self.isrreq = value;
+                self.isrreq = value & !InterruptStatus::ISR_FLAG_UNUSED_MASK;
             },
             _ => {
                 panic!("Interrupt status bus fault writing to {:04X}", addr);
             }
         }
     }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+impl Snapshotable for InterruptStatus {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(InterruptStatus::STATE_MAGIC, InterruptStatus::STATE_VERSION);
+        w.write_u8(self.isrreq);
+        w.write_u8(self.isrmask);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, InterruptStatus::STATE_MAGIC)?;
+        if version != InterruptStatus::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let isrreq = r.read_u8()?;
+        let isrmask = r.read_u8()?;
+        r.finish()?;
+        self.isrreq = isrreq & !InterruptStatus::ISR_FLAG_UNUSED_MASK;
+        self.isrmask = isrmask;
+        Ok(())
+    }
 }
 
 impl Default for InterruptStatus {
@@ -184,7 +290,7 @@ mod test {
         isr.request_vblank();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::VBLANK_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::VBLANK_MASK);
         assert_eq!(isr.is_vblank_active(), true);
     }
 
@@ -196,7 +302,7 @@ mod test {
         isr.request_lcdstat();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::LCDSTAT_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::LCDSTAT_MASK);
         assert_eq!(isr.is_lcdstat_active(), true);
     }
 
@@ -208,7 +314,7 @@ mod test {
         isr.request_timer();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::TIMER_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::TIMER_MASK);
         assert_eq!(isr.is_timer_active(), true);
     }
 
@@ -220,7 +326,7 @@ mod test {
         isr.request_serial();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::SERIAL_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::SERIAL_MASK);
         assert_eq!(isr.is_serial_active(), true);
     }
 
@@ -232,7 +338,7 @@ mod test {
         isr.request_joypad();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::JOYPAD_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::JOYPAD_MASK);
         assert_eq!(isr.is_joypad_active(), true);
     }
 
@@ -245,7 +351,7 @@ mod test {
         isr.request_vblank();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::VBLANK_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::VBLANK_MASK);
         assert_eq!(isr.is_vblank_active(), false);
     }
 
@@ -258,7 +364,7 @@ mod test {
         isr.request_lcdstat();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::LCDSTAT_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::LCDSTAT_MASK);
         assert_eq!(isr.is_lcdstat_active(), false);
     }
 
@@ -271,7 +377,7 @@ mod test {
         isr.request_timer();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::TIMER_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::TIMER_MASK);
         assert_eq!(isr.is_timer_active(), false);
     }
 
@@ -284,7 +390,7 @@ mod test {
         isr.request_serial();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::SERIAL_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::SERIAL_MASK);
         assert_eq!(isr.is_timer_active(), false);
     }
 
@@ -297,7 +403,7 @@ mod test {
         isr.request_joypad();
 
         // then
-        assert_eq!(isr.read_isr_flag(), InterruptStatus::JOYPAD_MASK);
+        assert_eq!(isr.read_isr_flag(), InterruptStatus::ISR_FLAG_UNUSED_MASK | InterruptStatus::JOYPAD_MASK);
         assert_eq!(isr.is_joypad_active(), false);
     }
 
@@ -335,4 +441,117 @@ mod test {
         isr.clear_joypad();
         assert_eq!(isr.read_isr_flag(), !InterruptStatus::JOYPAD_MASK);
     }
+
+    #[test]
+    fn test_timer_serial_joypad_set_query_clear(){
+        type Source = (fn(&mut InterruptStatus), fn(&InterruptStatus) -> bool, fn(&mut InterruptStatus));
+        let sources: [Source; 3] = [
+            (InterruptStatus::request_timer, InterruptStatus::is_timer_active, InterruptStatus::clear_timer),
+            (InterruptStatus::request_serial, InterruptStatus::is_serial_active, InterruptStatus::clear_serial),
+            (InterruptStatus::request_joypad, InterruptStatus::is_joypad_active, InterruptStatus::clear_joypad),
+        ];
+
+        for (request, is_active, clear) in sources.iter() {
+            let mut isr = get_isr_all_enabled();
+            isr.request_vblank();
+            assert!(!is_active(&isr));
+
+            request(&mut isr);
+            assert!(is_active(&isr));
+
+            // Clearing only touches the one source.
+            clear(&mut isr);
+            assert!(!is_active(&isr));
+            assert_eq!(isr.isrreq, InterruptStatus::VBLANK_MASK);
+        }
+    }
+
+    #[test]
+    fn test_next_pending_priority(){
+        let mut isr = get_isr_all_enabled();
+        assert_eq!(isr.next_pending(), None);
+
+        isr.request_joypad();
+        isr.request_timer();
+        isr.request_vblank();
+        assert_eq!(isr.next_pending(), Some(Interrupt::VBlank));
+        assert_eq!(isr.next_pending().unwrap().vector(), 0x40);
+
+        isr.clear(Interrupt::VBlank);
+        assert_eq!(isr.next_pending(), Some(Interrupt::Timer));
+        assert_eq!(isr.next_pending().unwrap().vector(), 0x50);
+
+        // Disabled interrupts are skipped.
+        isr.write_isr_mask(!InterruptStatus::TIMER_MASK);
+        assert_eq!(isr.next_pending(), Some(Interrupt::Joypad));
+        assert_eq!(isr.next_pending().unwrap().vector(), 0x60);
+
+        isr.clear(Interrupt::Joypad);
+        assert_eq!(isr.next_pending(), None);
+        assert_eq!(isr.isrreq, InterruptStatus::TIMER_MASK);
+    }
+
+    #[test]
+    fn test_pending_flags(){
+        let mut isr = InterruptStatus::new();
+        assert_eq!(isr.pending_flags(), 0);
+
+        // Requests are reported whether enabled or not.
+        isr.request_lcdstat();
+        isr.request_serial();
+        assert_eq!(isr.pending_flags(), 0b0_1010);
+
+        isr.set_pending_flags(0xFF);
+        assert_eq!(isr.pending_flags(), 0b1_1111);
+        assert_eq!(isr.read_isr_flag(), 0xFF);
+        isr.set_pending_flags(0b0_0100);
+        assert!(!isr.is_timer_active());
+        isr.write_isr_mask(0xFF);
+        assert_eq!(isr.next_pending(), Some(Interrupt::Timer));
+    }
+
+    #[test]
+    fn test_interrupt_vectors(){
+        let vectors = [0x40, 0x48, 0x50, 0x58, 0x60];
+        for (interrupt, vector) in Interrupt::PRIORITY.iter().zip(vectors.iter()) {
+            assert_eq!(interrupt.vector(), *vector);
+        }
+    }
+
+    #[test]
+    fn test_flag_register_rw(){
+        let mut isr = InterruptStatus::new();
+        assert_eq!(isr.read_isr_flag(), 0xE0);
+
+        // Only the 5 interrupt bits are stored.
+        isr.bus_write8(InterruptStatus::ISR_FLAG_ADDR, 0x15);
+        assert_eq!(isr.isrreq, 0x15);
+        assert_eq!(isr.read_isr_flag(), 0xF5);
+        isr.bus_write8(InterruptStatus::ISR_FLAG_ADDR, 0xE0);
+        assert_eq!(isr.isrreq, 0);
+        assert_eq!(isr.read_isr_flag(), 0xE0);
+
+        // Requests show up in the register.
+        isr.request_vblank();
+        assert_eq!(isr.read_isr_flag(), 0xE1);
+        isr.request_timer();
+        assert_eq!(isr.read_isr_flag(), 0xE5);
+    }
+
+    #[test]
+    fn test_enable_register_rw(){
+        let mut isr = InterruptStatus::new();
+        assert_eq!(isr.read_isr_mask(), 0);
+        isr.write_isr_mask(0x1F);
+        assert_eq!(isr.read_isr_mask(), 0x1F);
+        assert_eq!(isr.isrmask, 0x1F);
+
+        // The enable register is fully readable and writable.
+        isr.write_isr_mask(0xFF);
+        assert_eq!(isr.read_isr_mask(), 0xFF);
+        isr.request_vblank();
+        assert!(isr.is_vblank_active());
+        isr.write_isr_mask(0xFE);
+        assert!(!isr.is_vblank_active());
+    }
 }
@@ -2,6 +2,7 @@
     clippy::bool_comparison,
 )]
 
+pub mod bootrom;
 pub mod bus;
 #[allow(dead_code)]
 pub mod cartrige;
@@ -14,6 +15,8 @@ pub mod ppu;
 pub mod ram;
 #[allow(dead_code)]
 pub mod serial;
+pub mod state;
+pub mod system;
 pub mod timer;
 pub mod interface;
 pub mod input;
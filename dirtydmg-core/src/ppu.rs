@@ -1,6 +1,10 @@
 use crate::bus::{BusRW};
+use crate::ram::HighRam;
 use crate::interrupt::InterruptStatus;
 use crate::interface::ScanlineBuffer;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
 
 /// Overall size of the ram block used for tile sets.
 const TILESET_RAM:usize = 0x1800;
@@ -41,6 +45,8 @@ const OAM_SPRITE_COUNT: usize = 40;
 const OAM_SPRITE_SIZE: usize = 4;
 /// The amount of ram used for OAM.
 const OAM_RAM_SIZE:usize = OAM_SPRITE_COUNT * OAM_SPRITE_SIZE;
+/// The maximum number of sprites drawn on a single line.
+const LINE_SPRITE_LIMIT:usize = 10;
 const OAM_START_ADDRESS:usize = 0xFE00;
 const OAM_END_ADDRESS:usize = OAM_START_ADDRESS + OAM_RAM_SIZE;
 
@@ -58,11 +64,48 @@ const WX_ADDRESS:usize = 0xFF4B;
 
 const OAM_DMA_REGISTER_ADDRESS:usize = 0xFF46;
 
+/// CGB VRAM bank select register.
+const VBK_ADDRESS:usize = 0xFF4F;
+
+// CGB VRAM DMA registers
+const HDMA1_ADDRESS:usize = 0xFF51;
+const HDMA2_ADDRESS:usize = 0xFF52;
+const HDMA3_ADDRESS:usize = 0xFF53;
+const HDMA4_ADDRESS:usize = 0xFF54;
+const HDMA5_ADDRESS:usize = 0xFF55;
+
+// CGB color palette registers
+const BCPS_ADDRESS:usize = 0xFF68;
+const BCPD_ADDRESS:usize = 0xFF69;
+const OCPS_ADDRESS:usize = 0xFF6A;
+const OCPD_ADDRESS:usize = 0xFF6B;
+
 // Palette registers
 const BG_PALETTE_ADDRESS:usize = 0xFF47;
 const OBJ_PALETTE1_ADDRESS:usize = 0xFF48;
 const OBJ_PALETTE2_ADDRESS:usize = 0xFF49;
 
+/// The size of one bank of VRAM, 0x8000-0x9FFF.
+pub const VRAM_SIZE:usize = TILESET_RAM + TILEMAPS_SIZE;
+
+/// The width of the LCD in pixels.
+pub const LCD_WIDTH:usize = 160;
+/// The height of the LCD in pixels, the number of visible lines.
+pub const LCD_HEIGHT:usize = 144;
+/// The number of pixels in a complete frame.
+pub const FRAMEBUFFER_SIZE:usize = LCD_WIDTH * LCD_HEIGHT;
+
+/// The classic green shades of the original DMG screen, lightest first.
+pub const DMG_GREEN_SHADES: [[u8;3];4] = [
+    [0x9B, 0xBC, 0x0F],
+    [0x8B, 0xAC, 0x0F],
+    [0x30, 0x62, 0x30],
+    [0x0F, 0x38, 0x0F],
+];
+
+/// Evenly spaced grey levels, white first.
+pub const GREY_SHADES: [u8;4] = [0xFF, 0xAA, 0x55, 0x00];
+
 #[derive(Clone, Copy)]
 /// Structure to hold tile pixel data in an easily accessable format.
 struct Tile {
@@ -133,16 +176,22 @@ impl Palette {
     }
 }
 
+/// A sprite decoded from its 4 bytes of OAM data.
 #[derive(Clone, Copy, PartialEq, Debug)]
-struct OamSprite{
+pub struct OamSprite{
+    /// Vertical position, offset by 16 so 0 is fully above the screen.
     pub ypos: u8,
+    /// Horizontal position, offset by 8 so 0 is fully left of the screen.
     pub xpos: u8,
+    /// Tile index of the sprite.
     pub tile: u8,
 
     //These fields are all packed into a single byte.
+    /// True if the sprite is hidden behind background colors 1-3.
     pub behind_background: bool,
     pub xflip: bool,
     pub yflip: bool,
+    /// True if the sprite uses object palette 2, false for palette 1.
     pub palette: bool,
 }
 
@@ -173,14 +222,318 @@ impl OamSprite {
     }
 }
 
+/// CGB color palette memory, 8 palettes of 4 RGB555 colors.
+/// 
+/// The memory is only accessible one byte at a time through an index 
+/// register, which can automatically increment after each data write.
+struct ColorPaletteRam{
+    data: [u8;ColorPaletteRam::SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColorPaletteRam {
+    const SIZE:usize = 64;
+    const INDEX_MASK:u8 =          0b0011_1111;
+    const AUTO_INCREMENT_MASK:u8 = 0b1000_0000;
+
+    fn new() -> ColorPaletteRam{
+        ColorPaletteRam{
+            data: [0;ColorPaletteRam::SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// Reads the index register, the unused bit reads as 1.
+    fn spec_read(&self) -> u8 {
+        let increment = if self.auto_increment {ColorPaletteRam::AUTO_INCREMENT_MASK} else {0};
+        increment | 0b0100_0000 | self.index
+    }
+
+    fn spec_write(&mut self, value:u8) {
+        self.index = value & ColorPaletteRam::INDEX_MASK;
+        self.auto_increment = value & ColorPaletteRam::AUTO_INCREMENT_MASK != 0;
+    }
+
+    fn data_read(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    fn data_write(&mut self, value:u8) {
+        self.data[self.index as usize] = value;
+        if self.auto_increment {
+            self.index = (self.index + 1) & ColorPaletteRam::INDEX_MASK;
+        }
+    }
+
+    /// Gets an RGB555 color from a palette.
+    fn color(&self, palette:usize, color:usize) -> u16 {
+        let offset = (palette * 4 + color) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+}
+
+/// CGB attributes of a background or window tile, stored in VRAM bank 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct TileAttributes{
+    /// The CGB background palette number.
+    palette: u8,
+    /// True if the tile data is in VRAM bank 1.
+    bank: bool,
+    xflip: bool,
+    yflip: bool,
+    /// True if background colors 1-3 are drawn over all sprites.
+    priority: bool,
+}
+
+impl TileAttributes {
+    const PALETTE_MASK:u8 =  0b0000_0111;
+    const BANK_MASK:u8 =     0b0000_1000;
+    const XFLIP_MASK:u8 =    0b0010_0000;
+    const YFLIP_MASK:u8 =    0b0100_0000;
+    const PRIORITY_MASK:u8 = 0b1000_0000;
+
+    fn new(data:u8) -> TileAttributes{
+        TileAttributes{
+            palette: data & TileAttributes::PALETTE_MASK,
+            bank: data & TileAttributes::BANK_MASK != 0,
+            xflip: data & TileAttributes::XFLIP_MASK != 0,
+            yflip: data & TileAttributes::YFLIP_MASK != 0,
+            priority: data & TileAttributes::PRIORITY_MASK != 0,
+        }
+    }
+}
+
+/// Function called with each completed frame.
+pub type FrameCallback = Box<dyn FnMut(&[u8])>;
+
+/// Function called with the line number and pixels of each drawn line.
+pub type ScanlineCallback = Box<dyn FnMut(u8, &[u8])>;
+
+/// The PPU mode, as reported in the low bits of STAT.
 #[derive(Clone, Copy, PartialEq, Debug)]
-enum Mode{
+pub enum Mode{
+    /// Mode 0, idle until the end of the line.
     HBlank = 0,
+    /// Mode 1, the lines below the screen.
     VBlank = 1,
+    /// Mode 2, searching OAM for the line's sprites.
     SpriteSearch = 2,
+    /// Mode 3, drawing pixels to the LCD.
     LcdTransfer = 3
 }
 
+impl Mode {
+    fn from_u8(value: u8) -> Option<Mode> {
+        match value {
+            0 => Some(Mode::HBlank),
+            1 => Some(Mode::VBlank),
+            2 => Some(Mode::SpriteSearch),
+            3 => Some(Mode::LcdTransfer),
+            _ => None
+        }
+    }
+}
+
+/// The kind of CPU access that triggers the DMG OAM bug.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OamBugAccess {
+    /// A read from 0xFE00-0xFEFF.
+    Read,
+    /// A write to 0xFE00-0xFEFF, or a 16 bit increment or decrement of a
+    /// register pointing there.
+    Write,
+}
+
+/// The layer a framebuffer pixel was drawn from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PixelSource {
+    /// The background, including color 0 shown through a transparent
+    /// sprite pixel, and pixels drawn with the background disabled.
+    Background,
+    /// The window.
+    Window,
+    /// A sprite.
+    Sprite,
+}
+
+/// How VRAM and OAM are filled at power on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VramInit {
+    /// Every byte is 0.
+    Zero,
+    /// Every byte is the given value.
+    Fill(u8),
+    /// Pseudo-random bytes, the same for each seed, like the uninitialized
+    /// memory of real hardware.
+    Random(u64),
+}
+
+/// Counts of the PPU events since they were last polled.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct PpuEvents {
+    /// Changes of the STAT mode.
+    pub mode_changes: u32,
+    /// Times LY moved on to the next line, including the wrap to line 0.
+    pub lines: u32,
+    /// VBlank starts, when the completed frame is published.
+    pub vblanks: u32,
+    /// Frames completed, including their VBlank lines.
+    pub frames: u32,
+}
+
+/// The persistent state of the PPU.
+/// 
+/// Only the raw memory and register values are kept, the decoded tile and 
+/// sprite caches are rebuilt from them on restore. The frame being drawn is
+/// not saved, so restoring mid-frame leaves the lines already drawn blank 
+/// until the next frame.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct PpuState {
+    tile_data: Vec<u8>,
+    tile_data_bank1: Vec<u8>,
+    tilemaps: Vec<u8>,
+    tile_attributes: Vec<u8>,
+    sprite_data: Vec<u8>,
+    vram_bank: u8,
+
+    lcdc: u8,
+    lcds: u8,
+    line_compare: bool,
+    mode: u8,
+    stat_line: bool,
+
+    scroll_y: u8,
+    scroll_x: u8,
+    line_y: u8,
+    line_compare_value: u8,
+    window_y: u8,
+    window_x: u8,
+    window_line: u8,
+
+    bg_palette: u8,
+    obj_palette1: u8,
+    obj_palette2: u8,
+    bg_color_palettes: Vec<u8>,
+    bg_color_spec: u8,
+    obj_color_palettes: Vec<u8>,
+    obj_color_spec: u8,
+
+    oam_dma_ticks: u16,
+    oam_dma_src: u16,
+    hdma_src: u16,
+    hdma_dst: u16,
+    hdma_blocks: u8,
+    gdma_pending: bool,
+    hdma_active: bool,
+
+    line_sprites: Vec<u8>,
+    line_sprite_count: u8,
+    sprite_penalty: u16,
+    mode3_penalty: u16,
+    tick_counter: u16,
+}
+
+impl PpuState {
+    /// Magic bytes at the start of a saved PPU state.
+    const MAGIC: &'static [u8;4] = b"DPPU";
+    /// The current binary format version.
+    const VERSION: u16 = 2;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(PpuState::MAGIC, PpuState::VERSION);
+        w.write_bytes(&self.tile_data);
+        w.write_bytes(&self.tile_data_bank1);
+        w.write_bytes(&self.tilemaps);
+        w.write_bytes(&self.tile_attributes);
+        w.write_bytes(&self.sprite_data);
+        w.write_u8(self.vram_bank);
+        w.write_u8(self.lcdc);
+        w.write_u8(self.lcds);
+        w.write_bool(self.line_compare);
+        w.write_u8(self.mode);
+        w.write_bool(self.stat_line);
+        w.write_u8(self.scroll_y);
+        w.write_u8(self.scroll_x);
+        w.write_u8(self.line_y);
+        w.write_u8(self.line_compare_value);
+        w.write_u8(self.window_y);
+        w.write_u8(self.window_x);
+        w.write_u8(self.window_line);
+        w.write_u8(self.bg_palette);
+        w.write_u8(self.obj_palette1);
+        w.write_u8(self.obj_palette2);
+        w.write_bytes(&self.bg_color_palettes);
+        w.write_u8(self.bg_color_spec);
+        w.write_bytes(&self.obj_color_palettes);
+        w.write_u8(self.obj_color_spec);
+        w.write_u16(self.oam_dma_ticks);
+        w.write_u16(self.oam_dma_src);
+        w.write_u16(self.hdma_src);
+        w.write_u16(self.hdma_dst);
+        w.write_u8(self.hdma_blocks);
+        w.write_bool(self.gdma_pending);
+        w.write_bool(self.hdma_active);
+        w.write_bytes(&self.line_sprites);
+        w.write_u8(self.line_sprite_count);
+        w.write_u16(self.sprite_penalty);
+        w.write_u16(self.mode3_penalty);
+        w.write_u16(self.tick_counter);
+        w.finish()
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<PpuState, StateError> {
+        let (mut r, version) = StateReader::new(data, PpuState::MAGIC)?;
+        if version != PpuState::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let state = PpuState {
+            tile_data: r.read_bytes(TILESET_RAM)?.to_vec(),
+            tile_data_bank1: r.read_bytes(TILESET_RAM)?.to_vec(),
+            tilemaps: r.read_bytes(TILEMAPS_SIZE)?.to_vec(),
+            tile_attributes: r.read_bytes(TILEMAPS_SIZE)?.to_vec(),
+            sprite_data: r.read_bytes(OAM_RAM_SIZE)?.to_vec(),
+            vram_bank: r.read_u8()?,
+            lcdc: r.read_u8()?,
+            lcds: r.read_u8()?,
+            line_compare: r.read_bool()?,
+            mode: r.read_u8()?,
+            stat_line: r.read_bool()?,
+            scroll_y: r.read_u8()?,
+            scroll_x: r.read_u8()?,
+            line_y: r.read_u8()?,
+            line_compare_value: r.read_u8()?,
+            window_y: r.read_u8()?,
+            window_x: r.read_u8()?,
+            window_line: r.read_u8()?,
+            bg_palette: r.read_u8()?,
+            obj_palette1: r.read_u8()?,
+            obj_palette2: r.read_u8()?,
+            bg_color_palettes: r.read_bytes(ColorPaletteRam::SIZE)?.to_vec(),
+            bg_color_spec: r.read_u8()?,
+            obj_color_palettes: r.read_bytes(ColorPaletteRam::SIZE)?.to_vec(),
+            obj_color_spec: r.read_u8()?,
+            oam_dma_ticks: r.read_u16()?,
+            oam_dma_src: r.read_u16()?,
+            hdma_src: r.read_u16()?,
+            hdma_dst: r.read_u16()?,
+            hdma_blocks: r.read_u8()?,
+            gdma_pending: r.read_bool()?,
+            hdma_active: r.read_bool()?,
+            line_sprites: r.read_bytes(LINE_SPRITE_LIMIT)?.to_vec(),
+            line_sprite_count: r.read_u8()?,
+            sprite_penalty: r.read_u16()?,
+            mode3_penalty: r.read_u16()?,
+            tick_counter: r.read_u16()?,
+        };
+        r.finish()?;
+        Ok(state)
+    }
+}
+
 pub struct PPU {
     /// Raw tile data stored in the origial gameboy format.
     tile_data: [u8;TILESET_RAM],
@@ -190,6 +543,15 @@ pub struct PPU {
     /// tilemap data.
     tilemaps: [u8;TILEMAPS_SIZE],
 
+    /// Raw tile data in CGB VRAM bank 1.
+    tile_data_bank1: [u8;TILESET_RAM],
+    /// Decoded tiles in CGB VRAM bank 1.
+    tiles_bank1: [Tile;TILESET_COUNT],
+    /// CGB tile attributes, stored in bank 1 alongside the tile maps.
+    tile_attributes: [u8;TILEMAPS_SIZE],
+    /// The VRAM bank currently mapped for the CPU.
+    vram_bank: u8,
+
     /// Decoded sprite object data
     sprites: [OamSprite; OAM_SPRITE_COUNT],
     /// Raw OAM data.
@@ -215,6 +577,8 @@ pub struct PPU {
     mode0_is: bool, 
     line_compare: bool,
     mode: Mode,
+    /// State of the combined STAT interrupt line.
+    stat_line: bool,
 
     // scroll registers
     scroll_y: u8,
@@ -223,20 +587,80 @@ pub struct PPU {
     line_compare_value: u8,
     window_y: u8,
     window_x: u8,
+    /// Internal window line counter, only advances on lines the window is drawn.
+    window_line: u8,
+    /// True once the window has been drawn on the current line.
+    window_drawn: bool,
 
     // Pallet 
     bg_palette: Palette,
     obj_palette1: Palette,
     obj_palette2: Palette,
+    /// CGB background color palettes.
+    bg_color_palettes: ColorPaletteRam,
+    /// CGB object color palettes.
+    obj_color_palettes: ColorPaletteRam,
 
     // OAM DMA
-    oam_dma_ticks: u8,
+    oam_dma_ticks: u16,
     oam_dma_src: u16,
 
+    // CGB VRAM DMA
+    hdma_src: u16,
+    /// Destination offset from the start of VRAM.
+    hdma_dst: u16,
+    /// Number of 16 byte blocks left to transfer.
+    hdma_blocks: u8,
+    /// True if a general purpose transfer is waiting to be executed.
+    gdma_pending: bool,
+    /// True if a transfer is copying one block each HBlank.
+    hdma_active: bool,
+
+    // Sprites selected for the current line by the OAM search.
+    line_sprites: [u8;LINE_SPRITE_LIMIT],
+    line_sprite_count: usize,
+    /// Extra mode 3 ticks spent fetching the line sprites.
+    sprite_penalty: u16,
+    /// Extra mode 3 ticks on the current line, latched as mode 3 starts so
+    /// a later SCX write can't move the start of HBlank.
+    mode3_penalty: u16,
+
     // Misc State tracking.
     tick_counter: u16,
     pub line_buffer: ScanlineBuffer,
-    pub line_pending: bool
+    pub line_pending: bool,
+    /// Number of pixels of the current line drawn so far.
+    line_x: u8,
+    /// True if pixels are drawn as they are shifted out during mode 3, 
+    /// rather than all at once at the end of the line.
+    pixel_fifo: bool,
+    /// True if LCDC bit 0 is the CGB master priority rather than the DMG
+    /// background enable.
+    cgb_mode: bool,
+    /// True if CPU accesses near OAM during mode 2 corrupt it like the DMG.
+    oam_bug: bool,
+
+    /// Frame currently being drawn, one color index per pixel.
+    frame_progress: [u8;FRAMEBUFFER_SIZE],
+    /// The last fully drawn frame, one color index per pixel.
+    framebuffer: [u8;FRAMEBUFFER_SIZE],
+    /// Layer each pixel of frame_progress was drawn from.
+    source_progress: [PixelSource;FRAMEBUFFER_SIZE],
+    /// Layer each pixel of framebuffer was drawn from.
+    sources: [PixelSource;FRAMEBUFFER_SIZE],
+    /// CGB palette number of each pixel of frame_progress. Kept on the heap,
+    /// as the PPU is already large enough to strain the stack.
+    palette_progress: Vec<u8>,
+    /// CGB palette number of each pixel of framebuffer.
+    palettes: Vec<u8>,
+    /// Called with the completed frame each time VBlank starts.
+    frame_callback: Option<FrameCallback>,
+    /// Called with each line as it is finished.
+    scanline_callback: Option<ScanlineCallback>,
+    /// Grey level of each DMG shade, used by framebuffer_grey.
+    shades: [u8;4],
+    /// Events since the last poll_events.
+    events: PpuEvents,
 }
 
 impl PPU {
@@ -254,48 +678,122 @@ impl PPU {
     const LCDS_MODE2_IS_MASK: u8 =    1<<5;
     const LCDS_MODE1_IS_MASK: u8 =    1<<4;
     const LCDS_MODE0_IS_MASK: u8 =    1<<3;
+    /// STAT bit 7 is unused and always reads as 1.
+    const LCDS_UNUSED_MASK: u8 =      1<<7;
+
+    const OAM_DMA_TRANSFER_TICKS: u16 = 640; // In cpu ticks or "T" cycles.
+    /// OAM DMA copies one byte per machine cycle.
+    const OAM_DMA_TICKS_PER_BYTE: u16 = 4;
 
-    const OAM_DMA_TRANSFER_TICKS: u8 = 160; // In cpu ticks or "T" cycles.
+    const HDMA_BLOCK_SIZE: usize = 16;
+    /// VRAM DMA destinations wrap within the 8KB of VRAM.
+    const HDMA_DST_MASK: u16 = 0x1FFF;
     
     const LCD_TICKS_PER_LINE: u16 = 456;
-    const LCD_LINE_VBLANK_START: u8 = 144;
+    const LCD_LINE_VBLANK_START: u8 = LCD_HEIGHT as u8;
     const LCD_LINE_VBLANK_END: u8 = 153;
-    const LCD_WIDTH: u8 = 160;
-
-    /// Checks if a DMA transfer is currently executing.
-    fn dma_active(&self) -> bool{
+    const LCD_WIDTH: u8 = LCD_WIDTH as u8;
+    /// Ticks into line 153 before LY reads as 0.
+    const LINE_153_LY_TICKS: u16 = 4;
+    /// Length of mode 2 at the start of each visible line.
+    const OAM_SEARCH_TICKS: u16 = 80;
+    /// Length of mode 3 with no scroll or sprite delays.
+    const MODE3_MIN_TICKS: u16 = 172;
+    /// The largest WX value that still shows the window on screen.
+    const WINDOW_X_MAX: u8 = 166;
+
+    /// Checks if an OAM DMA transfer is currently executing.
+    pub fn dma_active(&self) -> bool{
         self.oam_dma_ticks != 0
     }
 
+    /// Checks if an OAM DMA transfer blocks the CPU from an address.
+    /// 
+    /// While a transfer is running the CPU can only access high RAM, all 
+    /// other reads should return 0xFF and writes should be dropped.
+    pub fn dma_blocks_address(&self, addr: usize) -> bool{
+        self.dma_active() && !(HighRam::START_ADDRESS..=HighRam::END_ADDRESS).contains(&addr)
+    }
+
+    /// Checks if the CPU can currently access VRAM.
+    /// 
+    /// VRAM is locked while the PPU is transferring pixels to the LCD.
+    fn vram_accessible(&self) -> bool{
+        !(self.lcd_enabled && self.mode == Mode::LcdTransfer)
+    }
+
+    /// Checks if the CPU can currently access OAM.
+    /// 
+    /// OAM is locked while the PPU is searching or drawing sprites, and for
+    /// the whole of an OAM DMA transfer.
+    fn oam_accessible(&self) -> bool{
+        let ppu_busy = self.lcd_enabled && 
+            (self.mode == Mode::SpriteSearch || self.mode == Mode::LcdTransfer);
+        !(ppu_busy || self.dma_active())
+    }
+
     fn update_dma(&mut self, ticks:u16, bus:&mut impl BusRW){
         // If there is a DMA transfer in progress
         if self.oam_dma_ticks > 0 {
-            // If the transfer was just initiated.
-            if self.oam_dma_ticks == PPU::OAM_DMA_TRANSFER_TICKS{
-                self.dma_transfer(bus);
-            }
+            let start = PPU::OAM_DMA_TRANSFER_TICKS - self.oam_dma_ticks;
+
+            // Update the number of remaining DMA ticks, ending the transfer
+            // if more ticks ran than were left.
+            self.oam_dma_ticks = self.oam_dma_ticks.saturating_sub(ticks);
+
+            // Copy every byte completed by these ticks.
+            let end = PPU::OAM_DMA_TRANSFER_TICKS - self.oam_dma_ticks;
+            self.dma_transfer(
+                (start / PPU::OAM_DMA_TICKS_PER_BYTE) as usize, 
+                (end / PPU::OAM_DMA_TICKS_PER_BYTE) as usize, 
+                bus);
+        }
+    }
 
-            // Update the number of remaining DMA ticks.
-            if self.oam_dma_ticks as u16 > ticks{
-                self.oam_dma_ticks -= ticks as u8;
-            } else {
-                self.oam_dma_ticks = 0;
+    /// Runs any pending general purpose VRAM DMA.
+    /// 
+    /// The CPU is halted for the whole of a general purpose transfer, so it 
+    /// is done in one go.
+    fn update_gdma(&mut self, bus:&mut impl BusRW){
+        if self.gdma_pending {
+            self.gdma_pending = false;
+            while self.hdma_blocks > 0 {
+                self.hdma_transfer_block(bus);
             }
         }
     }
 
-    /// Computes the correct tileset index for a given map value.
-    fn calc_tileset_index(&self, tiledata: u8) -> usize{
-        if self.bg_window_signed_addressing {
-            (TILESET_HIGH_BASE_INDEX + (tiledata as i8 as isize)) as usize
+    /// Copies the next 16 byte block of a VRAM DMA transfer.
+    fn hdma_transfer_block(&mut self, bus:&mut impl BusRW){
+        for _ in 0..PPU::HDMA_BLOCK_SIZE {
+            let value = bus.bus_read8(self.hdma_src as usize);
+            self.vram_write(value, TILESET_START_ADDRESS + self.hdma_dst as usize);
+            self.hdma_src = self.hdma_src.wrapping_add(1);
+            self.hdma_dst = (self.hdma_dst + 1) & PPU::HDMA_DST_MASK;
+        }
+
+        self.hdma_blocks -= 1;
+        if self.hdma_blocks == 0 {
+            self.hdma_active = false;
+        }
+    }
+
+    /// Computes the tiles index for a raw tile map value.
+    /// 
+    /// Unsigned addressing counts up from the tile at 0x8000. Signed
+    /// addressing treats the value as an offset from tile 256 at 0x9000, so
+    /// 128-255 reach back to the shared tiles at 0x8800.
+    fn tile_index_to_cache(&self, raw: u8, signed: bool) -> usize{
+        if signed {
+            (TILESET_HIGH_BASE_INDEX + (raw as i8 as isize)) as usize
         } else {
-            tiledata as usize
+            raw as usize
         }
     }
 
     /// Populates an array with sprite indicies that overlap the current line.
     /// Returns the number of sprites found.
-    fn get_line_sprites(&self, sprites:&mut[u8;10]) -> usize{
+    fn get_line_sprites(&self, line: u8, sprites:&mut[u8;LINE_SPRITE_LIMIT]) -> usize{
         let mut found = 0;
         let height = if self.obj_double_sprites {TILE_DIMENSION*2} 
                      else {TILE_DIMENSION}
@@ -303,14 +801,14 @@ impl PPU {
 
         for (index, sprite) in self.sprites.iter().enumerate(){
             // Check for a Y collision.
-            let diff = self.line_y as i16 - (sprite.ypos as i16 - 16);
+            let diff = line as i16 - (sprite.ypos as i16 - 16);
             if diff >= 0 && diff < height{
                 sprites[found] = index as u8;
                 found += 1;
             }
 
             // Abort if we have found our limit of sprites
-            if found >= 10{
+            if found >= LINE_SPRITE_LIMIT{
                 break;
             }
             
@@ -319,6 +817,89 @@ impl PPU {
         found
     }
 
+    /// Performs the mode 2 OAM search, selecting the sprites drawn on the current line.
+    /// 
+    /// The selected sprites are ordered by drawing priority. On the DMG the 
+    /// sprite with the smallest X position is drawn on top, with ties going
+    /// to the sprite with the lowest OAM index.
+    fn oam_search(&mut self) {
+        self.select_line_sprites(self.line_y);
+        self.sprite_penalty = self.calc_sprite_penalty();
+    }
+
+    /// Selects the sprites drawn on a line, in drawing priority order.
+    fn select_line_sprites(&mut self, line: u8) {
+        let mut sprites = [0u8;LINE_SPRITE_LIMIT];
+        let count = self.get_line_sprites(line, &mut sprites);
+
+        // The sort is stable, so equal X positions remain in OAM order.
+        sprites[..count].sort_by_key(|&index| self.sprites[index as usize].xpos);
+
+        self.line_sprite_count = count;
+        self.line_sprites = sprites;
+    }
+
+    /// Gets the value of the LY register.
+    /// 
+    /// LY only reads as 153 for the first few ticks of the last line, then 
+    /// reads as 0 for the remainder of VBlank.
+    fn ly(&self) -> u8 {
+        if self.line_y == PPU::LCD_LINE_VBLANK_END && self.tick_counter >= PPU::LINE_153_LY_TICKS {
+            0
+        } else {
+            self.line_y
+        }
+    }
+
+    /// Checks if any of the enabled STAT interrupt conditions are met.
+    /// 
+    /// The mode 2 condition is also met for the first tick of line 144, as 
+    /// the hardware checks it on entering VBlank as if starting a new line.
+    fn stat_condition(&self) -> bool {
+        let vblank_mode2 = self.line_y == PPU::LCD_LINE_VBLANK_START && self.tick_counter == 0;
+        (self.line_compare_is && self.line_compare) ||
+        (self.mode0_is && self.mode == Mode::HBlank) ||
+        (self.mode1_is && self.mode == Mode::VBlank) ||
+        (self.mode2_is && (self.mode == Mode::SpriteSearch || vblank_mode2))
+    }
+
+    /// Updates the combined STAT interrupt line.
+    /// 
+    /// The STAT sources are ORed together, and the interrupt is only 
+    /// requested when that line rises. A source that becomes active while 
+    /// another is still holding the line high does not trigger again.
+    fn update_stat_line(&mut self, is: &mut InterruptStatus) {
+        let stat_line = self.stat_condition();
+        if stat_line && !self.stat_line {
+            is.request_lcdstat();
+        }
+        self.stat_line = stat_line;
+    }
+
+    /// Calculates how many ticks fetching the line sprites adds to mode 3.
+    /// 
+    /// Each sprite costs 6 ticks. The first sprite over each background tile
+    /// also waits up to 5 more ticks for that tile's fetch to complete,
+    /// depending on where in the tile the sprite lands.
+    fn calc_sprite_penalty(&self) -> u16 {
+        if !self.obj_enabled {
+            return 0;
+        }
+
+        let mut penalty = 0;
+        let mut fetched_tiles = [u16::MAX;LINE_SPRITE_LIMIT];
+        for (i, index) in self.line_sprites[..self.line_sprite_count].iter().enumerate() {
+            let x = self.sprites[*index as usize].xpos as u16 + self.scroll_x as u16;
+            let tile = x / 8;
+            penalty += 6;
+            if !fetched_tiles.contains(&tile) {
+                penalty += 5 - std::cmp::min(5, x % 8);
+                fetched_tiles[i] = tile;
+            }
+        }
+        penalty
+    }
+
     fn check_collision_sprite(&self, xpos:u8, sprite: &OamSprite) -> bool{
         if xpos >= 168{
             return false;
@@ -331,45 +912,69 @@ impl PPU {
         false 
     }
 
-    fn draw_line(&mut self) {
-        // Used to hold pixel data.
-        let mut pixel_block:u8 = 0;
-        let mut sprite_pixel:u8;
-        let mut bg_pixel = 0u8;
-        let mut sprite_behind:bool;
-        let mut bg_trans = false;
-
-        ////BG rendering data////
+    /// Reads the color index of a background or window tile pixel.
+    /// 
+    /// The tile's CGB attributes select the tile bank and flips, and are 
+    /// returned alongside the color.
+    fn bg_tile_pixel(&self, tilemap_index: usize, x: u8, y: u8) -> (u8, TileAttributes) {
+        let attributes = if self.cgb_mode {
+            TileAttributes::new(self.tile_attributes[tilemap_index])
+        } else {
+            TileAttributes::new(0)
+        };
+        let tileset_index = self.tile_index_to_cache(
+            self.tilemaps[tilemap_index], self.bg_window_signed_addressing);
+        let tiles = if attributes.bank {&self.tiles_bank1} else {&self.tiles};
+        let x = if attributes.xflip {(TILE_DIMENSION-1) as u8 - x} else {x};
+        let y = if attributes.yflip {(TILE_DIMENSION-1) as u8 - y} else {y};
+        (tiles[tileset_index].read_pixel(x, y), attributes)
+    }
+
+    /// Draws the pixels from `line_x` up to, but not including, `end` on 
+    /// the current line using the current register values.
+    fn draw_pixels(&mut self, end: u8) {
         // Find the row of tiles the current line falls on.
-        let tile_row = (((self.line_y as u16 + self.scroll_y as u16) as u8) / 8) as usize;
-        let tile_pixel_y = ((self.line_y as u16 + self.scroll_y as u16) % 8) as u8;
-        // Find the column of tiles the current line starts on.
-        let tile_col = (self.scroll_x / 8) as usize;
-        // The pixel within the tile.
-        let mut tile_pixel_x = self.scroll_x % 8;
-        // Find the tile index.
-        let mut tile_index = tile_row * TILEMAP_DIMENSION + tile_col;
-        // Use upper tile map if dictated by current configuration.
-        if self.bg_tiles_high {
-            tile_index += TILEMAP_ITEM_COUNT;
-        }
+        // The 256x256 background wraps around vertically.
+        let bg_y = self.line_y.wrapping_add(self.scroll_y);
+        let tile_row = (bg_y / 8) as usize;
+        let tile_pixel_y = bg_y % 8;
+
+        // In CGB mode LCDC bit 0 is the master priority, and the background
+        // is always drawn.
+        let bg_visible = self.bg_window_enable || self.cgb_mode;
+        let master_priority = self.bg_window_enable;
 
         //// Window rendering data ////
-        let window_tile_row = (self.line_y.wrapping_sub(self.window_y) / 8) as usize;
-        let window_tile_pixel_y = self.line_y.wrapping_sub(self.window_y) % 8;
+        // The window is drawn on this line if it is enabled, and positioned on screen.
+        let window_visible = bg_visible &&
+                             self.window_enabled &&
+                             self.window_y <= self.line_y &&
+                             self.window_x <= PPU::WINDOW_X_MAX;
+        let window_tile_row = (self.window_line / 8) as usize;
+        let window_tile_pixel_y = self.window_line % 8;
 
         ////Sprite data////
-        let mut line_sprites = [0u8;10];
-        let sprite_count = self.get_line_sprites(&mut line_sprites);
-        let line_sprites = line_sprites;
-
-        // For each pixel in the scanline
-        for scanline_index in 0..PPU::LCD_WIDTH {
-            if self.bg_window_enable{
+        // Sprites were selected during the OAM search.
+        let line_sprites = self.line_sprites;
+        let sprite_count = self.line_sprite_count;
+
+        // Offset of the current line within the frame.
+        debug_assert!((self.line_y as usize) < LCD_HEIGHT, "drawing line {} off screen", self.line_y);
+        debug_assert!(end as usize <= LCD_WIDTH, "drawing up to pixel {} off screen", end);
+        let frame_offset = self.line_y as usize * LCD_WIDTH;
+
+        // For each pixel left to draw
+        for scanline_index in self.line_x..end {
+            let mut bg_pixel = 0u8;
+            let mut bg_trans = false;
+            let mut bg_priority = false;
+            let mut bg_source = PixelSource::Background;
+            let mut bg_palette_number = 0;
+            if bg_visible{
                 // If this is going to be a window pixel
-                if self.window_enabled && 
-                   self.window_y <= self.line_y && 
-                   self.window_x <= scanline_index + 7 {
+                if window_visible && self.window_x <= scanline_index + 7 {
+                    self.window_drawn = true;
+                    bg_source = PixelSource::Window;
                     
                     // Calculate what map block we are in
                     let window_tile_col = ((scanline_index + 7 - self.window_x) / 8) as usize;
@@ -379,41 +984,38 @@ impl PPU {
                         window_tilemap_index += TILEMAP_ITEM_COUNT;
                     } 
 
-                    // Translate the tilemap block into an index in the tile set.
-                    let tileset_index = self.calc_tileset_index(self.tilemaps[window_tilemap_index]);
-                    bg_pixel = self.tiles[tileset_index].read_pixel(window_tile_pixel_x, window_tile_pixel_y);
+                    // Translate the tilemap block into a tile pixel.
+                    let (color, attributes) = self.bg_tile_pixel(
+                        window_tilemap_index, window_tile_pixel_x, window_tile_pixel_y);
 
                     // Decide if the pixel is transparent, then get the pixel value from the palette.
-                    bg_trans = bg_pixel == 0;
-                    bg_pixel = self.bg_palette.table[bg_pixel as usize];
+                    bg_trans = color == 0;
+                    bg_priority = attributes.priority;
+                    bg_palette_number = attributes.palette;
+                    bg_pixel = self.bg_palette.table[color as usize];
                 }
                 // Draw the background for this pixel.
                 else {
-                    // get the tileset index from the map
-                    let tileset_index = self.calc_tileset_index(self.tilemaps[tile_index]);
-                    bg_pixel = self.tiles[tileset_index].read_pixel(tile_pixel_x, tile_pixel_y);
-                    bg_trans = bg_pixel == 0;
-                    bg_pixel = self.bg_palette.table[bg_pixel as usize];
-
-                    // If we have a tile pixel overflow
-                    tile_pixel_x += 1;
-                    if tile_pixel_x & 0b111 == 0 {
-                        // Reset tile pixel to zero
-                        tile_pixel_x = 0;
-
-                        // Advance to the next tile, and check for overflow
-                        tile_index += 1;
-                        if tile_index & 0b11111 == 0 {
-                            // println!("tile_index {}", tile_index); // DEBUG!
-                            tile_index -= TILEMAP_DIMENSION;
-                        }
+                    // The background wraps around horizontally.
+                    let bg_x = scanline_index.wrapping_add(self.scroll_x);
+                    let mut tile_index = tile_row * TILEMAP_DIMENSION + (bg_x / 8) as usize;
+                    // Use upper tile map if dictated by current configuration.
+                    if self.bg_tiles_high {
+                        tile_index += TILEMAP_ITEM_COUNT;
                     }
+
+                    // get the tile pixel from the map
+                    let (color, attributes) = self.bg_tile_pixel(tile_index, bg_x % 8, tile_pixel_y);
+                    bg_trans = color == 0;
+                    bg_priority = attributes.priority;
+                    bg_palette_number = attributes.palette;
+                    bg_pixel = self.bg_palette.table[color as usize];
                 }
             }
 
             // If objects are enabled.
-            sprite_behind = true;
-            sprite_pixel = 4;
+            let mut sprite_behind = true;
+            let mut sprite_pixel = 4;
             if self.obj_enabled{
                 for line_sprite in line_sprites.iter().take(sprite_count){
                     let sprt = &self.sprites[*line_sprite as usize];
@@ -463,112 +1065,215 @@ impl PPU {
                     
                 }
             }
-            pixel_block >>= 2;
-            let pixel =
-                if sprite_behind && !bg_trans{
-                    bg_pixel
+            let (pixel, source, palette_number) =
+                if master_priority && (sprite_behind || bg_priority) && !bg_trans{
+                    (bg_pixel, bg_source, bg_palette_number)
                 } 
                 else if sprite_pixel != 4{
-                    sprite_pixel
+                    (sprite_pixel, PixelSource::Sprite, 0)
                 }
                 else {
-                    bg_pixel
+                    (bg_pixel, bg_source, bg_palette_number)
                 };
-            pixel_block |= pixel << 6;
+            self.frame_progress[frame_offset + scanline_index as usize] = pixel;
+            self.source_progress[frame_offset + scanline_index as usize] = source;
+            self.palette_progress[frame_offset + scanline_index as usize] = palette_number;
+
+            // Pack the pixel into the scanline buffer, first pixel in the
+            // low bits of each block.
+            let shift = (scanline_index & 0b11) * 2;
+            let block = &mut self.line_buffer.pixeldata[(scanline_index/4) as usize];
+            *block = (*block & !(0b11 << shift)) | (pixel << shift);
+        }
+        self.line_x = std::cmp::max(self.line_x, end);
+    }
 
-            // If we have completed a pixel block
-            if (scanline_index + 1) & 0b11 == 0{
-                // update the scanline buffer.
-                self.line_buffer.pixeldata[(scanline_index/4) as usize] = pixel_block;
-            }
+    /// Finishes drawing the current line.
+    /// 
+    /// Palettes are applied as each pixel is drawn rather than snapshotted 
+    /// at the start of the frame, so a palette written between lines is used
+    /// from the next line drawn.
+    fn draw_line(&mut self) {
+        self.draw_pixels(PPU::LCD_WIDTH);
+
+        // The window line only advances on lines the window was drawn.
+        if self.window_drawn {
+            self.window_line += 1;
         }
+        self.window_drawn = false;
+        self.line_x = 0;
         self.line_pending = true;
+
+        if let Some(callback) = self.scanline_callback.as_mut() {
+            let start = self.line_y as usize * LCD_WIDTH;
+            callback(self.line_y, &self.frame_progress[start..start + LCD_WIDTH]);
+        }
+    }
+
+    /// Gets the number of pixels that have been shifted out to the LCD by 
+    /// the current tick of the line.
+    /// 
+    /// One pixel is pushed per tick over the end of mode 3, after the fine
+    /// scroll and sprite fetches have delayed the start of the line.
+    fn pixels_shifted_out(&self) -> u8 {
+        let first_pixel_tick = self.transfer_end() + 1 - PPU::LCD_WIDTH as u16;
+        if self.tick_counter < first_pixel_tick {
+            0
+        } else {
+            std::cmp::min(self.tick_counter - first_pixel_tick, PPU::LCD_WIDTH as u16) as u8
+        }
+    }
+
+    /// Gets the number of ticks mode 3 lasts on the current line.
+    fn mode3_length(&self) -> u16 {
+        PPU::MODE3_MIN_TICKS + self.mode3_penalty
+    }
+
+    /// Gets the last tick of mode 3 on the current line, HBlank starts on
+    /// the tick after.
+    fn transfer_end(&self) -> u16 {
+        PPU::OAM_SEARCH_TICKS + self.mode3_length() - 1
+    }
+
+    /// Gets the number of ticks until the next mode or line change.
+    /// 
+    /// Lets a driver step the CPU and PPU together to the next point where
+    /// the STAT mode, LY or an interrupt could change. Nothing changes while 
+    /// the LCD is off, so this is u16::MAX then.
+    pub fn cycles_until_next_event(&self) -> u16 {
+        if !self.lcd_enabled {
+            return u16::MAX;
+        }
+        let next_event = if self.line_y < PPU::LCD_LINE_VBLANK_START {
+            match self.tick_counter {
+                x if x < PPU::OAM_SEARCH_TICKS => PPU::OAM_SEARCH_TICKS,
+                x if x <= self.transfer_end() => self.transfer_end() + 1,
+                _ => PPU::LCD_TICKS_PER_LINE
+            }
+        } else if self.line_y == PPU::LCD_LINE_VBLANK_END && self.tick_counter < PPU::LINE_153_LY_TICKS {
+            PPU::LINE_153_LY_TICKS
+        } else {
+            PPU::LCD_TICKS_PER_LINE
+        };
+        next_event - self.tick_counter
     }
 
     /// # Executes the specified number of clock ticks.
-    pub fn execute_ticks(&mut self, ticks:u16, bus:&mut impl BusRW, is: &mut InterruptStatus){
+    /// 
+    /// Ticks are consumed up to each mode and line change in turn, so any 
+    /// number of ticks can be executed at once without missing interrupts
+    /// or lines.
+    pub fn execute_ticks(&mut self, mut ticks:u16, bus:&mut impl BusRW, is: &mut InterruptStatus){
         self.update_dma(ticks, bus);
+        self.update_gdma(bus);
 
-        // TODO this is really, Really, REALLY wildly inacurate.
         if self.lcd_enabled{
-            self.tick_counter += ticks;
+            while ticks > 0 {
+                let step = std::cmp::min(ticks, self.cycles_until_next_event());
+                self.advance(step, bus, is);
+                ticks -= step;
+            }
+        }
+    }
 
-            // If the line has expired
-            if self.tick_counter >= PPU::LCD_TICKS_PER_LINE {
+    /// Advances the LCD by a number of ticks, up to the next mode or line 
+    /// change.
+    fn advance(&mut self, ticks:u16, bus:&mut impl BusRW, is: &mut InterruptStatus){
+        // TODO this is really, Really, REALLY wildly inacurate.
+        self.tick_counter += ticks;
 
-                // Draw the line if this is not VBLANK
-                if self.line_y < PPU::LCD_LINE_VBLANK_START{
-                    self.draw_line();
-                }
+        // If the line has expired
+        if self.tick_counter >= PPU::LCD_TICKS_PER_LINE {
 
-                // correct the tick count and increment the line count.
-                self.tick_counter -= PPU::LCD_TICKS_PER_LINE;
-                self.line_y += 1;
-                self.line_compare = self.line_compare_value == self.line_y;
-                if self.line_compare && self.line_compare_is {
-                    is.request_lcdstat();
+            // Draw the line if this is not VBLANK
+            if self.line_y < PPU::LCD_LINE_VBLANK_START{
+                self.draw_line();
+            }
+
+            // correct the tick count and increment the line count.
+            self.tick_counter -= PPU::LCD_TICKS_PER_LINE;
+            self.line_y += 1;
+            self.events.lines += 1;
+
+            // if start of vblank
+            if self.line_y == PPU::LCD_LINE_VBLANK_START {
+                // Set the mode
+                self.mode = Mode::VBlank;
+                self.events.mode_changes += 1;
+                self.events.vblanks += 1;
+
+                // The frame is complete, publish it.
+                std::mem::swap(&mut self.framebuffer, &mut self.frame_progress);
+                std::mem::swap(&mut self.sources, &mut self.source_progress);
+                std::mem::swap(&mut self.palettes, &mut self.palette_progress);
+                if let Some(callback) = self.frame_callback.as_mut() {
+                    callback(&self.framebuffer);
                 }
 
-                // if start of vblank
-                if self.line_y == PPU::LCD_LINE_VBLANK_START {
-                    // Set the mode
-                    self.mode = Mode::VBlank;
+                // Trigger interrupts
+                is.request_vblank();
+            }
 
-                    // Trigger interrupts
-                    is.request_vblank();
-                    if self.mode1_is {
-                        is.request_lcdstat();
-                    }
-                }
+            // start of new frame.
+            if self.line_y > PPU::LCD_LINE_VBLANK_END {
+                self.line_y = 0;
+                self.window_line = 0;
+                self.mode = Mode::SpriteSearch;
+                self.events.mode_changes += 1;
+                self.events.frames += 1;
+                self.oam_search();
+            }
+        }
 
-                // start of new frame.
-                if self.line_y > PPU::LCD_LINE_VBLANK_END {
-                    self.line_y = 0;
-                    self.line_compare = self.line_compare_value == self.line_y;
-                    if self.line_compare && self.line_compare_is {
-                        is.request_lcdstat();
-                    }
-                    self.mode = Mode::SpriteSearch;
-                }
+        // If we are not in vblank
+        if self.line_y < PPU::LCD_LINE_VBLANK_START {
+            // Catch up on the pixels shifted out since the last update.
+            if self.pixel_fifo {
+                let end = self.pixels_shifted_out();
+                self.draw_pixels(end);
             }
 
-            // If we are not in vblank
-            if self.line_y < PPU::LCD_LINE_VBLANK_START {
-                
-                let new_mode = match self.tick_counter {
-                    // Mode 2 - OAM_SCAN
-                    0..=79 => {
-                        Mode::SpriteSearch
-                    }
-                    // Mode 3 - Drawing Pixels
-                    80..=251 => {
-                        Mode::LcdTransfer
+            let transfer_end = self.transfer_end();
+
+            let new_mode = match self.tick_counter {
+                // Mode 2 - OAM_SCAN
+                x if x < PPU::OAM_SEARCH_TICKS => {
+                    Mode::SpriteSearch
+                }
+                // Mode 3 - Drawing Pixels
+                x if x <= transfer_end => {
+                    Mode::LcdTransfer
+                }
+                // Mode 0 - HBLANK
+                _ => {
+                    Mode::HBlank
+                }
+            };
+
+            // Sprites are selected when entering the OAM search, and 
+            // HBlank VRAM DMA copies a block at the start of each HBlank.
+            if new_mode != self.mode {
+                self.mode = new_mode;
+                self.events.mode_changes += 1;
+                match new_mode {
+                    Mode::SpriteSearch => {
+                        self.oam_search();
                     }
-                    // Mode 0 - HBLANK
-                    _ => {
-                        Mode::HBlank
+                    Mode::LcdTransfer => {
+                        // Discarding the fine scroll pixels and fetching 
+                        // sprites both extend mode 3.
+                        self.mode3_penalty = (self.scroll_x % 8) as u16 + self.sprite_penalty;
                     }
-                };
-
-                // If there was a mode change, set any interrupts.
-                if new_mode != self.mode {
-                    self.mode = new_mode;
-                    match new_mode {
-                        Mode::SpriteSearch => {
-                            if self.mode2_is{
-                                is.request_lcdstat();
-                            }
-                        }
-                        Mode::HBlank => {
-                            if self.mode0_is {
-                                is.request_lcdstat();
-                            }
-                        }
-                        _ => {}
+                    Mode::HBlank if self.hdma_active => {
+                        self.hdma_transfer_block(bus);
                     }
+                    _ => {}
                 }
             }
         }
+
+        self.line_compare = self.line_compare_value == self.ly();
+        self.update_stat_line(is);
     }
 
     pub fn new() -> PPU {
@@ -578,10 +1283,16 @@ impl PPU {
             tile_data: [0;TILESET_RAM],
             tiles: [blank_tile;TILESET_COUNT],
             tilemaps:[0;TILEMAPS_SIZE],
+            tile_data_bank1: [0;TILESET_RAM],
+            tiles_bank1: [blank_tile;TILESET_COUNT],
+            tile_attributes: [0;TILEMAPS_SIZE],
+            vram_bank: 0,
             sprites: [default_sprite;OAM_SPRITE_COUNT],
             sprite_data: [0;OAM_RAM_SIZE],
             lcdc: 0,
-            lcd_enabled: false,
+            // The boot rom leaves the LCD running, so setting up LCDC below 
+            // is not an enable.
+            lcd_enabled: true,
             obj_double_sprites: false,
             obj_enabled: false,
             bg_tiles_high: false,
@@ -596,20 +1307,48 @@ impl PPU {
             line_compare_value: 0,
             window_y: 0,
             window_x: 0,
+            window_line: 0,
+            window_drawn: false,
             line_compare_is: false,
             mode2_is: false,
             mode1_is: false,
             mode0_is: false, 
             line_compare: false,
             mode: Mode::HBlank,
+            stat_line: false,
             bg_palette: Palette::new(),
             obj_palette1: Palette::new(),
             obj_palette2: Palette::new(),
+            bg_color_palettes: ColorPaletteRam::new(),
+            obj_color_palettes: ColorPaletteRam::new(),
             oam_dma_src: 0,
             oam_dma_ticks: 0,
+            hdma_src: 0,
+            hdma_dst: 0,
+            hdma_blocks: 0,
+            gdma_pending: false,
+            hdma_active: false,
+            line_sprites: [0;LINE_SPRITE_LIMIT],
+            line_sprite_count: 0,
+            sprite_penalty: 0,
+            mode3_penalty: 0,
             tick_counter: 0,
             line_buffer: ScanlineBuffer::new(),
             line_pending: false,
+            line_x: 0,
+            pixel_fifo: false,
+            cgb_mode: false,
+            oam_bug: false,
+            frame_progress: [0;FRAMEBUFFER_SIZE],
+            framebuffer: [0;FRAMEBUFFER_SIZE],
+            source_progress: [PixelSource::Background;FRAMEBUFFER_SIZE],
+            sources: [PixelSource::Background;FRAMEBUFFER_SIZE],
+            palette_progress: vec![0;FRAMEBUFFER_SIZE],
+            palettes: vec![0;FRAMEBUFFER_SIZE],
+            frame_callback: None,
+            scanline_callback: None,
+            shades: GREY_SHADES,
+            events: PpuEvents::default(),
         };
         // Setup the screen into a post bootrom state.
         ppu.lcdc_write(
@@ -620,61 +1359,756 @@ impl PPU {
         ppu
     }
 
-    fn tile_write(&mut self, data:u8, addr:usize)
-    {
-        let index = (addr - TILESET_START_ADDRESS) / TILE_SIZE;
-        let y = (addr>>1) & 0x7;
-        let msb =  (addr & 0x01) != 0;
+    /// Creates a PPU with VRAM and OAM filled according to `init`.
+    /// 
+    /// Both VRAM banks are filled, along with OAM, and the tile and sprite
+    /// caches are decoded to match. PPU::new is the same as VramInit::Zero.
+    pub fn with_vram_init(init: VramInit) -> PPU {
+        let mut ppu = PPU::new();
+        let mut next_byte: Box<dyn FnMut() -> u8> = match init {
+            VramInit::Zero => Box::new(|| 0),
+            VramInit::Fill(value) => Box::new(move || value),
+            VramInit::Random(seed) => {
+                // xorshift64, which needs a non-zero state. A splitmix64 
+                // step spreads the seed out, and is one to one so 
+                // different seeds never share a state. The one seed that
+                // mixes to zero is given a fixed state instead.
+                let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                state ^= state >> 31;
+                if state == 0 {
+                    state = 0x9E37_79B9_7F4A_7C15;
+                }
+                Box::new(move || {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    (state >> 32) as u8
+                })
+            }
+        };
 
-        // Update the raw copy of the data
-        self.tile_data[addr - TILESET_START_ADDRESS] = data;
-        // Update the tile data.
-        self.tiles[index].update_row(data, y, msb);
+        for byte in ppu.tile_data.iter_mut()
+            .chain(ppu.tile_data_bank1.iter_mut())
+            .chain(ppu.tilemaps.iter_mut())
+            .chain(ppu.tile_attributes.iter_mut())
+            .chain(ppu.sprite_data.iter_mut()) {
+            *byte = next_byte();
+        }
+        ppu.rebuild_caches();
+        ppu
+    }
 
-        // println!("tilewrite to {:#4X}: {:#2X}", addr, data);
+    /// Creates a PPU in the state the DMG boot ROM leaves it in.
+    /// 
+    /// LCDC is 0x91, BGP is 0xFC and both object palettes are 0xFF. The
+    /// boot ROM hands over part way through line 153, where LY already 
+    /// reads 0, so STAT reads 0x85 with the LY=LYC flag set in VBlank.
+    pub fn new_post_boot() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.lcdc_write(
+            PPU::LCDC_ENABLE_MASK |
+            PPU::LCDC_BG_WINDOW_TILE_MAP_SELECT_MASK |
+            PPU::LCDC_BG_WINDOW_PRIORITY_MASK
+        );
+        ppu.bg_palette.update(0xFC);
+        ppu.obj_palette1.update(0xFF);
+        ppu.obj_palette2.update(0xFF);
+        ppu.line_y = PPU::LCD_LINE_VBLANK_END;
+        ppu.tick_counter = PPU::LINE_153_LY_TICKS;
+        ppu.mode = Mode::VBlank;
+        ppu.line_compare = ppu.line_compare_value == ppu.ly();
+        ppu.stat_line = ppu.stat_condition();
+        ppu
     }
 
-    fn sprite_write(&mut self, data:u8, addr:usize) {
-        let index = (addr - OAM_START_ADDRESS) / OAM_SPRITE_SIZE;
-        let field = addr & 0b11;
+    /// Returns the PPU to its power on state.
+    /// 
+    /// All registers, timing state and the framebuffer are reset. If 
+    /// `preserve_vram` is set, the tile data, tilemaps and OAM are kept.
+    /// Callbacks and rendering options remain as they were.
+    pub fn reset(&mut self, preserve_vram: bool) {
+        let mut fresh = PPU::new();
+        if preserve_vram {
+            fresh.tile_data = self.tile_data;
+            fresh.tiles = self.tiles;
+            fresh.tilemaps = self.tilemaps;
+            fresh.tile_data_bank1 = self.tile_data_bank1;
+            fresh.tiles_bank1 = self.tiles_bank1;
+            fresh.tile_attributes = self.tile_attributes;
+            fresh.sprites = self.sprites;
+            fresh.sprite_data = self.sprite_data;
+        }
+        fresh.keep_host_settings(self);
+        *self = fresh;
+    }
 
-        // Update the sprite attributes
-        match field {
-            0 => {self.sprites[index].ypos = data;},
-            1 => {self.sprites[index].xpos = data;},
-            2 => {self.sprites[index].tile = data;},
-            3 => {self.sprites[index].set_attrib_byte(data);},
-            _ => {panic!("THIS IS A BUG! invalid sprite field write");}
+    /// Moves the callbacks and rendering options over from another PPU.
+    /// 
+    /// These belong to the host rather than the emulated hardware, so they 
+    /// survive resets and state loads.
+    fn keep_host_settings(&mut self, other: &mut PPU) {
+        self.frame_callback = other.frame_callback.take();
+        self.scanline_callback = other.scanline_callback.take();
+        self.pixel_fifo = other.pixel_fifo;
+        self.cgb_mode = other.cgb_mode;
+        self.oam_bug = other.oam_bug;
+        self.shades = other.shades;
+    }
+
+    /// Decodes the tile and sprite caches from the raw VRAM and OAM data.
+    /// 
+    /// Bus writes keep the caches up to date, so this is only needed after
+    /// the raw data is changed some other way.
+    pub fn rebuild_caches(&mut self) {
+        for (tile_data, tiles) in [
+            (&self.tile_data, &mut self.tiles),
+            (&self.tile_data_bank1, &mut self.tiles_bank1)
+        ].iter_mut() {
+            for (offset, data) in tile_data.iter().enumerate() {
+                tiles[offset / TILE_SIZE].update_row(*data, (offset >> 1) & 0x7, offset & 0x01 != 0);
+            }
         }
 
-        // Save the raw sprite data.
-        self.sprite_data[addr - OAM_START_ADDRESS] = data;
+        let sprite_data = self.sprite_data;
+        for (offset, data) in sprite_data.iter().enumerate() {
+            self.sprite_write(*data, OAM_START_ADDRESS + offset);
+        }
     }
 
-    fn lcdc_write(&mut self, data:u8) {
-        // Save the new LCDC value
-        self.lcdc = data;
-        
-        self.lcd_enabled = data & PPU::LCDC_ENABLE_MASK != 0;
-        self.window_tiles_high = data & PPU::LCDC_WINDOW_TILE_MAP_MASK != 0;
-        self.window_enabled = data & PPU::LCDC_WINDOW_DISPLAY_ENABLE_MASK != 0;
-        self.bg_window_signed_addressing = data & PPU::LCDC_BG_WINDOW_TILE_MAP_SELECT_MASK == 0;
-        self.bg_tiles_high = data & PPU::LCDC_BG_TILE_MAP_SELECT_MASK != 0;
-        self.obj_double_sprites = data & PPU::LCDC_OBJ_SIZE_MASK != 0;
-        self.obj_enabled = data & PPU::LCDC_OBJ_DISPLAY_ENABLE_MASK != 0;
-        self.bg_window_enable = data & PPU::LCDC_BG_WINDOW_PRIORITY_MASK != 0;
+    /// Captures the persistent state of the PPU.
+    fn state(&self) -> PpuState {
+        PpuState {
+            tile_data: self.tile_data.to_vec(),
+            tile_data_bank1: self.tile_data_bank1.to_vec(),
+            tilemaps: self.tilemaps.to_vec(),
+            tile_attributes: self.tile_attributes.to_vec(),
+            sprite_data: self.sprite_data.to_vec(),
+            vram_bank: self.vram_bank,
+            lcdc: self.lcdc,
+            lcds: self.lcds,
+            line_compare: self.line_compare,
+            mode: self.mode as u8,
+            stat_line: self.stat_line,
+            scroll_y: self.scroll_y,
+            scroll_x: self.scroll_x,
+            line_y: self.line_y,
+            line_compare_value: self.line_compare_value,
+            window_y: self.window_y,
+            window_x: self.window_x,
+            window_line: self.window_line,
+            bg_palette: self.bg_palette.raw,
+            obj_palette1: self.obj_palette1.raw,
+            obj_palette2: self.obj_palette2.raw,
+            bg_color_palettes: self.bg_color_palettes.data.to_vec(),
+            bg_color_spec: self.bg_color_palettes.spec_read(),
+            obj_color_palettes: self.obj_color_palettes.data.to_vec(),
+            obj_color_spec: self.obj_color_palettes.spec_read(),
+            oam_dma_ticks: self.oam_dma_ticks,
+            oam_dma_src: self.oam_dma_src,
+            hdma_src: self.hdma_src,
+            hdma_dst: self.hdma_dst,
+            hdma_blocks: self.hdma_blocks,
+            gdma_pending: self.gdma_pending,
+            hdma_active: self.hdma_active,
+            line_sprites: self.line_sprites.to_vec(),
+            line_sprite_count: self.line_sprite_count as u8,
+            sprite_penalty: self.sprite_penalty,
+            mode3_penalty: self.mode3_penalty,
+            tick_counter: self.tick_counter,
+        }
+    }
 
-        // println!("lcd_enabled {}", self.lcd_enabled);
-        // println!("window_tiles_high {}", self.window_tiles_high);
-        // println!("window_enabled {}", self.window_enabled);
-        // println!("bg_window_signed_addressing {}", self.bg_window_signed_addressing);
-        // println!("bg_tiles_high {}", self.bg_tiles_high);
-        // println!("obj_double_sprites {}", self.obj_double_sprites);
-        // println!("obj_enabled {}", self.obj_enabled);
-        // println!("bg_window_enable {}", self.bg_window_enable);
+    /// Restores a PPU from a captured state.
+    fn from_state(state: &PpuState) -> Result<PPU, StateError> {
+        let lengths = [
+            (state.tile_data.len(), TILESET_RAM),
+            (state.tile_data_bank1.len(), TILESET_RAM),
+            (state.tilemaps.len(), TILEMAPS_SIZE),
+            (state.tile_attributes.len(), TILEMAPS_SIZE),
+            (state.sprite_data.len(), OAM_RAM_SIZE),
+            (state.bg_color_palettes.len(), ColorPaletteRam::SIZE),
+            (state.obj_color_palettes.len(), ColorPaletteRam::SIZE),
+            (state.line_sprites.len(), LINE_SPRITE_LIMIT),
+        ];
+        if lengths.iter().any(|(len, expected)| len != expected) {
+            return Err(StateError::Invalid("memory block of the wrong size".to_owned()));
+        }
+        if state.line_sprite_count as usize > LINE_SPRITE_LIMIT {
+            return Err(StateError::Invalid("too many line sprites".to_owned()));
+        }
+        let mode = Mode::from_u8(state.mode)
+            .ok_or_else(|| StateError::Invalid(format!("invalid mode {}", state.mode)))?;
+
+        let mut ppu = PPU::new();
+        ppu.tile_data.copy_from_slice(&state.tile_data);
+        ppu.tile_data_bank1.copy_from_slice(&state.tile_data_bank1);
+        ppu.tilemaps.copy_from_slice(&state.tilemaps);
+        ppu.tile_attributes.copy_from_slice(&state.tile_attributes);
+        ppu.sprite_data.copy_from_slice(&state.sprite_data);
+        ppu.rebuild_caches();
+        ppu.vram_bank = state.vram_bank & 0x01;
+
+        // Match the enable state first so restoring LCDC isn't an edge.
+        ppu.lcd_enabled = state.lcdc & PPU::LCDC_ENABLE_MASK != 0;
+        ppu.lcdc_write(state.lcdc);
+        ppu.lcds_write(state.lcds);
+        ppu.line_compare = state.line_compare;
+        ppu.mode = mode;
+        ppu.stat_line = state.stat_line;
+
+        ppu.scroll_y = state.scroll_y;
+        ppu.scroll_x = state.scroll_x;
+        ppu.line_y = state.line_y;
+        ppu.line_compare_value = state.line_compare_value;
+        ppu.window_y = state.window_y;
+        ppu.window_x = state.window_x;
+        ppu.window_line = state.window_line;
+
+        ppu.bg_palette.update(state.bg_palette);
+        ppu.obj_palette1.update(state.obj_palette1);
+        ppu.obj_palette2.update(state.obj_palette2);
+        ppu.bg_color_palettes.data.copy_from_slice(&state.bg_color_palettes);
+        ppu.bg_color_palettes.spec_write(state.bg_color_spec);
+        ppu.obj_color_palettes.data.copy_from_slice(&state.obj_color_palettes);
+        ppu.obj_color_palettes.spec_write(state.obj_color_spec);
+
+        ppu.oam_dma_ticks = state.oam_dma_ticks;
+        ppu.oam_dma_src = state.oam_dma_src;
+        ppu.hdma_src = state.hdma_src;
+        ppu.hdma_dst = state.hdma_dst;
+        ppu.hdma_blocks = state.hdma_blocks;
+        ppu.gdma_pending = state.gdma_pending;
+        ppu.hdma_active = state.hdma_active;
+
+        ppu.line_sprites.copy_from_slice(&state.line_sprites);
+        ppu.line_sprite_count = state.line_sprite_count as usize;
+        ppu.sprite_penalty = state.sprite_penalty;
+        ppu.mode3_penalty = state.mode3_penalty;
+        ppu.tick_counter = state.tick_counter;
+        Ok(ppu)
     }
 
-    fn lcds_write(&mut self, data:u8) {
+    /// Saves the PPU state to a versioned binary blob.
+    /// 
+    /// VRAM, OAM, registers and timing state are saved. The frame callback
+    /// and the frame being drawn are not.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.state().to_bytes()
+    }
+
+    /// Restores a state saved by `save_state`.
+    /// 
+    /// The PPU is left untouched if the data can't be restored. Callbacks
+    /// and rendering options remain as they were.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut restored = PPU::from_state(&PpuState::from_bytes(data)?)?;
+        restored.keep_host_settings(self);
+        *self = restored;
+        Ok(())
+    }
+
+    /// Selects how lines are rendered.
+    /// 
+    /// By default each line is drawn in one go when it ends, using the
+    /// register values at that point. With the pixel FIFO enabled pixels are
+    /// drawn as they are shifted out during mode 3, so SCX, WX and palette
+    /// writes made partway through a line only affect the pixels after them.
+    /// This costs a little more per tick.
+    pub fn set_pixel_fifo(&mut self, enabled: bool) {
+        self.pixel_fifo = enabled;
+    }
+
+    /// Selects between DMG and CGB behaviour.
+    /// 
+    /// On the DMG clearing LCDC bit 0 blanks the background and window. In 
+    /// CGB mode they are always drawn, and clearing the bit instead drops 
+    /// their priority so sprites are always drawn on top.
+    /// 
+    /// VBK and the bank 1 tile attributes only exist in CGB mode. Outside it
+    /// VBK reads 0xFF and ignores writes, bank 0 is always selected, and 
+    /// tiles are drawn without attributes.
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+        if !enabled {
+            self.vram_bank = 0;
+        }
+    }
+
+    /// Enables the DMG OAM corruption bug, off by default.
+    /// 
+    /// When enabled, accesses reported through oam_bug_access corrupt OAM
+    /// the same way as the hardware.
+    pub fn set_oam_bug(&mut self, enabled: bool) {
+        self.oam_bug = enabled;
+    }
+
+    /// Reports a CPU access that may trigger the OAM bug.
+    /// 
+    /// The bus layer calls this for reads and writes of 0xFE00-0xFEFF, and 
+    /// for 16 bit increments and decrements of registers pointing there, as
+    /// those put the address on the bus too. While the OAM search is running
+    /// the row it is reading is corrupted. OAM is treated as 20 rows of four
+    /// 16 bit words, and the search reads one row each machine cycle. The 
+    /// first word of the row is mixed with words from the row before, and
+    /// the other three are copied from it. The first row is never affected.
+    pub fn oam_bug_access(&mut self, addr: usize, kind: OamBugAccess) {
+        const ROW_SIZE: usize = 8;
+        const ROW_COUNT: usize = OAM_RAM_SIZE / ROW_SIZE;
+
+        if !self.oam_bug || !(0xFE00..=0xFEFF).contains(&addr) ||
+            !self.lcd_enabled || self.mode != Mode::SpriteSearch {
+            return;
+        }
+        let row = (self.tick_counter / 4) as usize;
+        if row == 0 || row >= ROW_COUNT {
+            return;
+        }
+
+        let word = |data: &[u8;OAM_RAM_SIZE], offset: usize| {
+            data[offset] as u16 | (data[offset + 1] as u16) << 8
+        };
+        let current = row * ROW_SIZE;
+        let previous = current - ROW_SIZE;
+        let a = word(&self.sprite_data, current);
+        let b = word(&self.sprite_data, previous);
+        let c = word(&self.sprite_data, previous + 4);
+        let first = match kind {
+            OamBugAccess::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBugAccess::Read => b | (a & c),
+        };
+
+        let mut corrupted = [0u8;ROW_SIZE];
+        corrupted.copy_from_slice(&self.sprite_data[previous..current]);
+        corrupted[..2].copy_from_slice(&first.to_le_bytes());
+        for (offset, data) in corrupted.iter().enumerate() {
+            self.sprite_write(*data, OAM_START_ADDRESS + current + offset);
+        }
+    }
+
+    /// Takes the events counted by execute_ticks since the last call.
+    pub fn poll_events(&mut self) -> PpuEvents {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Gets the current PPU mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Gets the window's internal line counter.
+    /// 
+    /// This is the row of the window drawn next. It only advances on lines
+    /// the window is drawn, and resets at the start of each frame.
+    pub fn window_line(&self) -> u8 {
+        self.window_line
+    }
+
+    /// Checks if the STAT interrupt line is high.
+    /// 
+    /// The line is the OR of every enabled STAT source, and an interrupt is
+    /// only requested when it rises. It is updated by execute_ticks, so a 
+    /// register write shows up after the next update.
+    pub fn stat_line(&self) -> bool {
+        self.stat_line
+    }
+
+    /// Gets the raw LCDC register, as read from the bus.
+    pub fn lcdc(&self) -> u8 {
+        self.lcdc
+    }
+
+    /// Gets the raw STAT register, as read from the bus.
+    /// 
+    /// This includes the current mode, the LY=LYC flag and the unused bit 7,
+    /// which always reads as 1.
+    pub fn stat(&self) -> u8 {
+        self.lcds_read()
+    }
+
+    /// Gets the line currently being processed, 0-153.
+    /// 
+    /// Unlike reads of LY, this is not affected by LY reading 0 early on 
+    /// line 153.
+    pub fn line(&self) -> u8 {
+        self.line_y
+    }
+
+    /// Gets a decoded sprite from OAM.
+    /// 
+    /// # Panics
+    /// If the index is not one of the 40 OAM sprites.
+    pub fn sprite(&self, index: usize) -> OamSprite {
+        self.sprites[index]
+    }
+
+    /// Gets the decoded pixels of a tile, indexed as `[y][x]`.
+    /// 
+    /// Tiles are indexed from the start of tile data at 0x8000, so valid 
+    /// indices are 0 to 383.
+    /// 
+    /// # Panics
+    /// If the index is outside of the tile set.
+    pub fn tile_pixels(&self, index: usize) -> [[u8;TILE_DIMENSION];TILE_DIMENSION] {
+        assert!(index < TILESET_COUNT, "tile index {} out of range", index);
+        self.tiles[index].pixel
+    }
+
+    /// Gets a copy of the decoded sprite table, in OAM order.
+    pub fn oam(&self) -> [OamSprite; OAM_SPRITE_COUNT] {
+        self.sprites
+    }
+
+    /// Gets the sprites the OAM search selects for a line, in OAM order.
+    /// 
+    /// These are the first 10 sprites whose rows cover the line, using the
+    /// current sprite height. The line is counted from the top of the screen.
+    pub fn sprites_on_line(&self, line: u8) -> Vec<OamSprite> {
+        let mut sprites = [0u8;LINE_SPRITE_LIMIT];
+        let count = self.get_line_sprites(line, &mut sprites);
+        sprites[..count].iter().map(|index| self.sprites[*index as usize]).collect()
+    }
+
+    /// Renders a visible line straight away, from the current registers,
+    /// VRAM and OAM.
+    /// 
+    /// The timing state, frame in progress and framebuffer are untouched. 
+    /// The window row is worked out as if the window was drawn on every 
+    /// line since WY.
+    /// 
+    /// # Panics
+    /// If the line is not one of the 144 visible lines.
+    pub fn render_scanline(&mut self, line: u8) -> [u8; LCD_WIDTH] {
+        assert!(line < PPU::LCD_LINE_VBLANK_START, "line {} is not visible", line);
+        let start = line as usize * LCD_WIDTH;
+        let end = start + LCD_WIDTH;
+
+        // Save everything drawing a line changes.
+        let line_y = self.line_y;
+        let line_x = self.line_x;
+        let window_line = self.window_line;
+        let window_drawn = self.window_drawn;
+        let line_sprites = self.line_sprites;
+        let line_sprite_count = self.line_sprite_count;
+        let line_buffer = self.line_buffer.pixeldata;
+        let mut pixels = [0u8; LCD_WIDTH];
+        pixels.copy_from_slice(&self.frame_progress[start..end]);
+        let mut sources = [PixelSource::Background; LCD_WIDTH];
+        sources.copy_from_slice(&self.source_progress[start..end]);
+        let mut palettes = [0u8; LCD_WIDTH];
+        palettes.copy_from_slice(&self.palette_progress[start..end]);
+
+        self.line_y = line;
+        self.line_x = 0;
+        self.window_line = line.saturating_sub(self.window_y);
+        self.select_line_sprites(line);
+        self.draw_pixels(PPU::LCD_WIDTH);
+
+        // Swap the drawn line out for the saved one, and restore the rest.
+        self.frame_progress[start..end].swap_with_slice(&mut pixels);
+        self.source_progress[start..end].copy_from_slice(&sources);
+        self.palette_progress[start..end].copy_from_slice(&palettes);
+        self.line_y = line_y;
+        self.line_x = line_x;
+        self.window_line = window_line;
+        self.window_drawn = window_drawn;
+        self.line_sprites = line_sprites;
+        self.line_sprite_count = line_sprite_count;
+        self.line_buffer.pixeldata = line_buffer;
+        pixels
+    }
+
+    /// Loads a whole tile in the original 16 byte format, decoding it 
+    /// straight away.
+    /// 
+    /// Tiles are indexed the same as tile_pixels, in VRAM bank 0. The VRAM 
+    /// lock is ignored.
+    /// 
+    /// # Panics
+    /// If the index is outside of the tile set.
+    pub fn load_tile(&mut self, index: usize, raw: &[u8;TILE_SIZE]) {
+        assert!(index < TILESET_COUNT, "tile index {} out of range", index);
+        let offset = index * TILE_SIZE;
+        self.tile_data[offset..offset + TILE_SIZE].copy_from_slice(raw);
+        for (i, data) in raw.iter().enumerate() {
+            self.tiles[index].update_row(*data, i >> 1, i & 0x01 != 0);
+        }
+    }
+
+    /// Copies the whole of VRAM, 0x8000-0x9FFF, out in one go.
+    /// 
+    /// The currently selected VRAM bank is read, and the VRAM lock is 
+    /// ignored.
+    /// 
+    /// # Panics
+    /// If `out` is not VRAM_SIZE bytes long.
+    pub fn read_vram(&self, out: &mut [u8]) {
+        assert_eq!(out.len(), VRAM_SIZE, "VRAM block must be {} bytes", VRAM_SIZE);
+        let (tile_data, maps) = if self.vram_bank == 1 {
+            (&self.tile_data_bank1, &self.tile_attributes)
+        } else {
+            (&self.tile_data, &self.tilemaps)
+        };
+        out[..TILESET_RAM].copy_from_slice(tile_data);
+        out[TILESET_RAM..].copy_from_slice(maps);
+    }
+
+    /// Overwrites the whole of VRAM, 0x8000-0x9FFF, in one go.
+    /// 
+    /// The currently selected VRAM bank is written, and the VRAM lock is 
+    /// ignored. The tiles are decoded straight away.
+    /// 
+    /// # Panics
+    /// If `data` is not VRAM_SIZE bytes long.
+    pub fn write_vram(&mut self, data: &[u8]) {
+        assert_eq!(data.len(), VRAM_SIZE, "VRAM block must be {} bytes", VRAM_SIZE);
+        let (tile_data, tiles, maps) = if self.vram_bank == 1 {
+            (&mut self.tile_data_bank1, &mut self.tiles_bank1, &mut self.tile_attributes)
+        } else {
+            (&mut self.tile_data, &mut self.tiles, &mut self.tilemaps)
+        };
+        tile_data.copy_from_slice(&data[..TILESET_RAM]);
+        maps.copy_from_slice(&data[TILESET_RAM..]);
+        for (offset, data) in tile_data.iter().enumerate() {
+            tiles[offset / TILE_SIZE].update_row(*data, (offset >> 1) & 0x7, offset & 0x01 != 0);
+        }
+    }
+
+    /// Gets the raw tile index at a position in a tile map.
+    /// 
+    /// Map 0 is the tile map at 0x9800, and map 1 is the tile map at 0x9C00.
+    /// 
+    /// # Panics
+    /// If the map is not 0 or 1, or x or y are outside of the 32x32 map.
+    pub fn tilemap_entry(&self, map: usize, x: usize, y: usize) -> u8 {
+        assert!(map < TILEMAPS_COUNT, "tile map {} out of range", map);
+        assert!(x < TILEMAP_DIMENSION && y < TILEMAP_DIMENSION, 
+            "tile map position ({}, {}) out of range", x, y);
+        self.tilemaps[map * TILEMAP_ITEM_COUNT + y * TILEMAP_DIMENSION + x]
+    }
+
+    /// Gets the last fully drawn frame.
+    /// 
+    /// Pixels are stored row by row as palette resolved color indices (0-3).
+    /// The frame is only updated at the start of VBlank, so it never contains
+    /// a partially drawn image.
+    pub fn framebuffer(&self) -> &[u8;FRAMEBUFFER_SIZE] {
+        &self.framebuffer
+    }
+
+    /// Gets the layer each pixel of the last fully drawn frame came from.
+    /// 
+    /// Entries line up with framebuffer, so a color 0 pixel can be told 
+    /// apart as background, window or an opaque sprite pixel using color 0
+    /// of its palette.
+    pub fn framebuffer_sources(&self) -> &[PixelSource;FRAMEBUFFER_SIZE] {
+        &self.sources
+    }
+
+    /// Gets the CGB palette number (0-7) of each pixel of the last fully 
+    /// drawn frame.
+    /// 
+    /// Entries line up with framebuffer. Background and window pixels use
+    /// their tile's attribute palette, which is always 0 without CGB tile 
+    /// attributes. Sprite pixels are 0.
+    pub fn framebuffer_palettes(&self) -> &[u8] {
+        &self.palettes
+    }
+
+    /// Converts the last fully drawn frame to packed RGB888.
+    /// 
+    /// Each color index is mapped through `shades`, giving 3 bytes per pixel
+    /// in the same row by row order as the framebuffer.
+    pub fn framebuffer_rgb(&self, shades: [[u8;3];4]) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(FRAMEBUFFER_SIZE * 3);
+        for pixel in self.framebuffer.iter() {
+            rgb.extend_from_slice(&shades[*pixel as usize]);
+        }
+        rgb
+    }
+
+    /// Renders every tile in VRAM bank 0 for a tile viewer.
+    /// 
+    /// Tiles are laid out in index order, 16 to a row over 24 rows, giving a
+    /// 128x192 image of color indices through the background palette. The 
+    /// pixels are in row by row order, the same as the framebuffer.
+    pub fn dump_tileset(&self) -> Vec<u8> {
+        const COLUMNS: usize = 16;
+        let width = COLUMNS * TILE_DIMENSION;
+        let height = TILESET_COUNT / COLUMNS * TILE_DIMENSION;
+        let mut image = vec![0u8; width * height];
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let left = (index % COLUMNS) * TILE_DIMENSION;
+            let top = (index / COLUMNS) * TILE_DIMENSION;
+            for y in 0..TILE_DIMENSION {
+                for x in 0..TILE_DIMENSION {
+                    let color = tile.read_pixel(x as u8, y as u8);
+                    image[(top + y) * width + left + x] = self.bg_palette.table[color as usize];
+                }
+            }
+        }
+        image
+    }
+
+    /// Renders a whole 256x256 background map, ignoring scroll.
+    /// 
+    /// `map` 0 is the tilemap at 0x9800 and 1 is the one at 0x9C00. Tiles are
+    /// found with the current addressing mode and colored through the 
+    /// background palette. The pixels are in row by row order.
+    pub fn dump_background(&self, map: usize) -> Vec<u8> {
+        assert!(map < TILEMAPS_COUNT, "There is no tilemap {}", map);
+        let size = TILEMAP_DIMENSION * TILE_DIMENSION;
+        let mut image = vec![0u8; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let tilemap_index = map * TILEMAP_ITEM_COUNT + 
+                    (y / TILE_DIMENSION) * TILEMAP_DIMENSION + x / TILE_DIMENSION;
+                let (color, _) = self.bg_tile_pixel(
+                    tilemap_index, (x % TILE_DIMENSION) as u8, (y % TILE_DIMENSION) as u8);
+                image[y * size + x] = self.bg_palette.table[color as usize];
+            }
+        }
+        image
+    }
+
+    /// Sets the grey level of each DMG shade, lightest shade first.
+    /// 
+    /// Defaults to GREY_SHADES. The framebuffer keeps its color indices, the
+    /// shades are applied by framebuffer_grey.
+    pub fn set_shades(&mut self, shades: [u8;4]) {
+        self.shades = shades;
+    }
+
+    /// Converts the last fully drawn frame to one grey level byte per pixel,
+    /// ready to display.
+    pub fn framebuffer_grey(&self) -> Vec<u8> {
+        self.framebuffer.iter().map(|pixel| self.shades[*pixel as usize]).collect()
+    }
+
+    /// Sets a function to be called each time a frame is completed.
+    /// 
+    /// The callback runs at the start of VBlank and is passed the newly
+    /// published framebuffer.
+    pub fn set_frame_callback(&mut self, callback: impl FnMut(&[u8]) + 'static) {
+        self.frame_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called each time a visible line is drawn.
+    /// 
+    /// The callback runs at the end of each of lines 0-143, and is passed
+    /// the line number and its 160 pixels, in the same format as the 
+    /// framebuffer.
+    pub fn set_scanline_callback(&mut self, callback: impl FnMut(u8, &[u8]) + 'static) {
+        self.scanline_callback = Some(Box::new(callback));
+    }
+
+    fn tile_write(&mut self, data:u8, addr:usize)
+    {
+        let index = (addr - TILESET_START_ADDRESS) / TILE_SIZE;
+        let y = (addr>>1) & 0x7;
+        let msb =  (addr & 0x01) != 0;
+
+        let (tile_data, tiles) = if self.vram_bank == 1 {
+            (&mut self.tile_data_bank1, &mut self.tiles_bank1)
+        } else {
+            (&mut self.tile_data, &mut self.tiles)
+        };
+
+        // Update the raw copy of the data
+        tile_data[addr - TILESET_START_ADDRESS] = data;
+        // Update the tile data.
+        tiles[index].update_row(data, y, msb);
+
+        // println!("tilewrite to {:#4X}: {:#2X}", addr, data);
+    }
+
+    /// Writes to the currently selected VRAM bank.
+    fn vram_write(&mut self, data:u8, addr:usize) {
+        match addr {
+            TILESET_START_ADDRESS..=TILESET_END_ADDRESS => {
+                self.tile_write(data, addr);
+            }
+
+            // Bank 1 holds the tile attributes.
+            _ => {
+                if self.vram_bank == 1 {
+                    self.tile_attributes[addr-TILEMAP_START_ADDRESS] = data;
+                } else {
+                    self.tilemaps[addr-TILEMAP_START_ADDRESS] = data;
+                }
+            }
+        }
+    }
+
+    fn sprite_write(&mut self, data:u8, addr:usize) {
+        let index = (addr - OAM_START_ADDRESS) / OAM_SPRITE_SIZE;
+        let field = addr & 0b11;
+
+        // Update the sprite attributes
+        match field {
+            0 => {self.sprites[index].ypos = data;},
+            1 => {self.sprites[index].xpos = data;},
+            2 => {self.sprites[index].tile = data;},
+            3 => {self.sprites[index].set_attrib_byte(data);},
+            _ => {panic!("THIS IS A BUG! invalid sprite field write");}
+        }
+
+        // Save the raw sprite data.
+        self.sprite_data[addr - OAM_START_ADDRESS] = data;
+    }
+
+    fn lcdc_write(&mut self, data:u8) {
+        // Save the new LCDC value
+        self.lcdc = data;
+        let was_enabled = self.lcd_enabled;
+        
+        self.lcd_enabled = data & PPU::LCDC_ENABLE_MASK != 0;
+        self.window_tiles_high = data & PPU::LCDC_WINDOW_TILE_MAP_MASK != 0;
+        self.window_enabled = data & PPU::LCDC_WINDOW_DISPLAY_ENABLE_MASK != 0;
+        self.bg_window_signed_addressing = data & PPU::LCDC_BG_WINDOW_TILE_MAP_SELECT_MASK == 0;
+        self.bg_tiles_high = data & PPU::LCDC_BG_TILE_MAP_SELECT_MASK != 0;
+        self.obj_double_sprites = data & PPU::LCDC_OBJ_SIZE_MASK != 0;
+        self.obj_enabled = data & PPU::LCDC_OBJ_DISPLAY_ENABLE_MASK != 0;
+        self.bg_window_enable = data & PPU::LCDC_BG_WINDOW_PRIORITY_MASK != 0;
+
+        // Turning the LCD off parks the PPU at the top of the frame in HBlank.
+        if was_enabled && !self.lcd_enabled {
+            self.line_y = 0;
+            self.tick_counter = 0;
+            self.window_line = 0;
+            self.window_drawn = false;
+            self.line_x = 0;
+            self.mode = Mode::HBlank;
+            self.stat_line = false;
+        }
+
+        // Turning the LCD on starts a new frame with the OAM search. The
+        // STAT line starts out high for any condition already met, so an 
+        // LY=LYC match at the start of the frame does not interrupt.
+        if !was_enabled && self.lcd_enabled {
+            self.line_y = 0;
+            self.tick_counter = 0;
+            self.window_line = 0;
+            self.window_drawn = false;
+            self.line_x = 0;
+            self.mode = Mode::SpriteSearch;
+            self.oam_search();
+            self.line_compare = self.line_compare_value == self.line_y;
+            self.stat_line = self.stat_condition();
+        }
+
+        // println!("lcd_enabled {}", self.lcd_enabled);
+        // println!("window_tiles_high {}", self.window_tiles_high);
+        // println!("window_enabled {}", self.window_enabled);
+        // println!("bg_window_signed_addressing {}", self.bg_window_signed_addressing);
+        // println!("bg_tiles_high {}", self.bg_tiles_high);
+        // println!("obj_double_sprites {}", self.obj_double_sprites);
+        // println!("obj_enabled {}", self.obj_enabled);
+        // println!("bg_window_enable {}", self.bg_window_enable);
+    }
+
+    fn lcds_write(&mut self, data:u8) {
         // Only these bits are writable
         self.line_compare_is = data & PPU::LCDS_LINE_CMP_IS_MASK != 0;
         self.mode2_is = data & PPU::LCDS_MODE2_IS_MASK != 0;
@@ -686,7 +2120,18 @@ impl PPU {
             PPU::LCDS_MODE1_IS_MASK |
             PPU::LCDS_MODE0_IS_MASK)
 }
-    fn lcds_read(&mut self) -> u8 {
+    /// Writes LYC, comparing it with the current line straight away.
+    /// 
+    /// Bus writes can't request interrupts, so a resulting STAT interrupt
+    /// is requested by the next execute_ticks.
+    fn lyc_write(&mut self, data:u8) {
+        self.line_compare_value = data;
+        if self.lcd_enabled {
+            self.line_compare = self.line_compare_value == self.ly();
+        }
+    }
+
+    fn lcds_read(&self) -> u8 {
         // Reassemble the LCDS value one bit at a time, starting with the msb.
         let mut value = 0;
         value |= self.line_compare_is as u8;
@@ -700,7 +2145,7 @@ impl PPU {
         value |= self.line_compare as u8;
         value <<= 2;
         value |= self.mode as u8;
-        value
+        value | PPU::LCDS_UNUSED_MASK
     }
 
     /// # Stage a DMA transfer
@@ -712,14 +2157,42 @@ impl PPU {
         self.oam_dma_ticks = PPU::OAM_DMA_TRANSFER_TICKS;
     }
 
-    /// #Executes the DMA memory transfer.
+    /// # Start or stop a CGB VRAM DMA transfer.
     /// 
-    /// This is not done tick by tick, but in one large operation. It should 
-    /// not have any negative effects, since the source area and target area
-    /// will be blocked during the transfer.
-    fn dma_transfer(&mut self, bus:&mut impl BusRW){
+    /// Bit 7 selects an HBlank transfer, and the lower bits are the number 
+    /// of 16 byte blocks minus one. Writing bit 7 clear during an HBlank 
+    /// transfer stops it instead.
+    fn hdma_start(&mut self, value: u8) {
+        let hblank = value & 0x80 != 0;
+        if self.hdma_active && !hblank {
+            self.hdma_active = false;
+            return;
+        }
+
+        self.hdma_blocks = (value & 0x7F) + 1;
+        self.hdma_active = hblank;
+        self.gdma_pending = !hblank;
+    }
+
+    /// Reads the remaining length of a VRAM DMA transfer.
+    /// 
+    /// Reads 0xFF once a transfer completes, bit 7 is set when stopped.
+    fn hdma_status(&self) -> u8 {
+        if self.hdma_active {
+            self.hdma_blocks - 1
+        } else if self.hdma_blocks > 0 {
+            0x80 | (self.hdma_blocks - 1)
+        } else {
+            0xFF
+        }
+    }
+
+    /// #Executes part of the DMA memory transfer.
+    /// 
+    /// Copies the bytes from offset `start` up to, but not including, `end`.
+    fn dma_transfer(&mut self, start:usize, end:usize, bus:&mut impl BusRW){
         let address = self.oam_dma_src as usize;
-        for x in 0..OAM_RAM_SIZE{
+        for x in start..end{
             self.sprite_write(
                 bus.bus_read8(address + x), 
                 OAM_START_ADDRESS + x);
@@ -727,17 +2200,28 @@ impl PPU {
     }
 }
 
-impl BusRW for PPU{
-    fn bus_read8(&mut self, addr: usize)-> u8{
-        match addr {
+impl PPU {
+    /// Reads a byte without checking whether VRAM and OAM are locked.
+    /// 
+    /// Returns None for addresses the PPU doesn't own.
+    fn read(&self, addr: usize) -> Option<u8> {
+        let value = match addr {
             // Tile data read
             TILESET_START_ADDRESS..=TILESET_END_ADDRESS => {
-                self.tile_data[addr-TILESET_START_ADDRESS]
+                if self.vram_bank == 1 {
+                    self.tile_data_bank1[addr-TILESET_START_ADDRESS]
+                } else {
+                    self.tile_data[addr-TILESET_START_ADDRESS]
+                }
             },
 
-            // Tile map read
+            // Tile map read, bank 1 holds the tile attributes.
             TILEMAP_START_ADDRESS..=TILEMAP_END_ADDRESS => {
-                self.tilemaps[addr-TILEMAP_START_ADDRESS]
+                if self.vram_bank == 1 {
+                    self.tile_attributes[addr-TILEMAP_START_ADDRESS]
+                } else {
+                    self.tilemaps[addr-TILEMAP_START_ADDRESS]
+                }
             },
 
             // Object attribute memory read
@@ -750,7 +2234,7 @@ impl BusRW for PPU{
             LCDS_ADDRESS => {self.lcds_read()}
             SCY_ADDRESS => {self.scroll_y}
             SCX_ADDRESS => {self.scroll_x}
-            LY_ADDRESS => {self.line_y}
+            LY_ADDRESS => {self.ly()}
             LYC_ADDRES => {self.line_compare_value}
             WY_ADDRESS => {self.window_y}
             WX_ADDRESS => {self.window_x}
@@ -758,26 +2242,74 @@ impl BusRW for PPU{
             OBJ_PALETTE1_ADDRESS => {self.obj_palette1.raw}
             OBJ_PALETTE2_ADDRESS => {self.obj_palette2.raw}
             OAM_DMA_REGISTER_ADDRESS => {(self.oam_dma_src>>8) as u8}
+            VBK_ADDRESS => {if self.cgb_mode {0xFE | self.vram_bank} else {0xFF}}
+            HDMA1_ADDRESS..=HDMA4_ADDRESS => {0xFF}
+            HDMA5_ADDRESS => {self.hdma_status()}
+            BCPS_ADDRESS => {self.bg_color_palettes.spec_read()}
+            BCPD_ADDRESS => {self.bg_color_palettes.data_read()}
+            OCPS_ADDRESS => {self.obj_color_palettes.spec_read()}
+            OCPD_ADDRESS => {self.obj_color_palettes.data_read()}
 
             // Unknown read address.
             _ => {
-                panic!("Unknown PPU read at address: 0x{:4X}", addr)
+                return None;
             }
+        };
+        Some(value)
+    }
+}
+
+impl Snapshotable for PPU {
+    fn save_state(&self) -> Vec<u8> {
+        PPU::save_state(self)
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        PPU::load_state(self, data)
+    }
+}
+
+impl BusRW for PPU{
+    fn bus_read8(&mut self, addr: usize)-> u8{
+        match addr {
+            // VRAM reads while locked return garbage.
+            TILESET_START_ADDRESS..=TILEMAP_END_ADDRESS if !self.vram_accessible() => {
+                0xFF
+            },
+
+            // OAM reads while locked return garbage.
+            OAM_START_ADDRESS..=OAM_END_ADDRESS if !self.oam_accessible() => {
+                0xFF
+            },
+
+            _ => self.read(addr).unwrap_or_else(|| 
+                panic!("Unknown PPU read at address: 0x{:4X}", addr))
         }
     }
 
+    /// Reads VRAM and OAM even while the PPU has them locked. Unknown 
+    /// addresses read as 0xFF.
+    fn bus_peek8(&self, addr: usize) -> u8 {
+        self.read(addr).unwrap_or(0xFF)
+    }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
     fn bus_write8(&mut self, addr: usize, value: u8){
         // TODO
         match addr {
-            // Tile data write
-            TILESET_START_ADDRESS..=TILESET_END_ADDRESS => {
-                self.tile_write(value, addr);
+            // VRAM writes while locked are dropped.
+            TILESET_START_ADDRESS..=TILEMAP_END_ADDRESS if !self.vram_accessible() => {}
+
+            // Tile data and tile map write
+            TILESET_START_ADDRESS..=TILEMAP_END_ADDRESS => {
+                self.vram_write(value, addr);
             }
 
-            // Tile map write
-            TILEMAP_START_ADDRESS..=TILEMAP_END_ADDRESS => {
-                self.tilemaps[addr-TILEMAP_START_ADDRESS] = value;
-            },
+            // OAM writes while locked are dropped.
+            OAM_START_ADDRESS..=OAM_END_ADDRESS if !self.oam_accessible() => {}
 
             // OAM memory write.
             OAM_START_ADDRESS..=OAM_END_ADDRESS => {
@@ -798,13 +2330,27 @@ impl BusRW for PPU{
             SCY_ADDRESS => {self.scroll_y = value;}
             SCX_ADDRESS => {self.scroll_x = value;}
             LY_ADDRESS => {/*Dead Write*/}
-            LYC_ADDRES => {self.line_compare_value = value;}
+            LYC_ADDRES => {self.lyc_write(value);}
             WY_ADDRESS => {self.window_y = value;}
             WX_ADDRESS => {self.window_x = value;}
             BG_PALETTE_ADDRESS => {self.bg_palette.update(value);}
             OBJ_PALETTE1_ADDRESS => {self.obj_palette1.update(value);}
             OBJ_PALETTE2_ADDRESS => {self.obj_palette2.update(value);}
             OAM_DMA_REGISTER_ADDRESS => {self.dma_start(value);}
+            VBK_ADDRESS => {
+                if self.cgb_mode {
+                    self.vram_bank = value & 0x01;
+                }
+            }
+            HDMA1_ADDRESS => {self.hdma_src = (self.hdma_src & 0x00FF) | (value as u16) << 8;}
+            HDMA2_ADDRESS => {self.hdma_src = (self.hdma_src & 0xFF00) | (value & 0xF0) as u16;}
+            HDMA3_ADDRESS => {self.hdma_dst = (self.hdma_dst & 0x00FF) | ((value & 0x1F) as u16) << 8;}
+            HDMA4_ADDRESS => {self.hdma_dst = (self.hdma_dst & 0xFF00) | (value & 0xF0) as u16;}
+            HDMA5_ADDRESS => {self.hdma_start(value);}
+            BCPS_ADDRESS => {self.bg_color_palettes.spec_write(value);}
+            BCPD_ADDRESS => {self.bg_color_palettes.data_write(value);}
+            OCPS_ADDRESS => {self.obj_color_palettes.spec_write(value);}
+            OCPD_ADDRESS => {self.obj_color_palettes.data_write(value);}
 
             // Unknown address.
             _ => {
@@ -820,6 +2366,21 @@ impl Default for PPU {
     }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for PPU {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.state().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for PPU {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PPU, D::Error> {
+        let state = PpuState::deserialize(deserializer)?;
+        PPU::from_state(&state).map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -878,13 +2439,130 @@ mod test {
             assert_eq!(x, ppu.bus_read8(address));
             address += 1;
         }
+
+        // The decoded pixels are available through the tile inspection API.
+        assert_eq!(ppu.tile_pixels(0), expected_tile.pixel);
+        assert_eq!(ppu.tile_pixels(TILESET_COUNT - 1), expected_tile.pixel);
+        assert_eq!(ppu.tile_pixels(1), [[0;TILE_DIMENSION];TILE_DIMENSION]);
     }
 
-    fn test_pack() -> (PPU, Ram, InterruptStatus){
-        let ppu = PPU::new();
-        let is = InterruptStatus::new();
-        let ram = Ram::new(0x10000, 0);
-        (ppu, ram, is)
+    #[test]
+    fn test_load_tile()
+    {
+        let tile_data = [0x7C, 0x7C, 0x00, 0xC6, 0xC6, 0x00, 0x00, 0xFE, 0xC6, 0xC6, 0x00, 0xC6, 0xC6, 0x00, 0x00, 0x00];
+        let expected_pixels = [
+            [0, 3, 3, 3, 3, 3, 0, 0],
+            [2, 2, 0, 0, 0, 2, 2, 0],
+            [1, 1, 0, 0, 0, 1, 1, 0],
+            [2, 2, 2, 2, 2, 2, 2, 0],
+            [3, 3, 0, 0, 0, 3, 3, 0],
+            [2, 2, 0, 0, 0, 2, 2, 0],
+            [1, 1, 0, 0, 0, 1, 1, 0],
+            [0, 0, 0, 0, 0, 0, 0, 0],
+        ];
+
+        let mut ppu = PPU::new();
+        // The tile loads into bank 0 whichever bank the CPU has selected.
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        ppu.load_tile(5, &tile_data);
+        ppu.load_tile(TILESET_COUNT - 1, &tile_data);
+        ppu.bus_write8(VBK_ADDRESS, 0);
+
+        assert_eq!(ppu.tile_pixels(5), expected_pixels);
+        assert_eq!(ppu.tile_pixels(TILESET_COUNT - 1), expected_pixels);
+        assert_eq!(ppu.tile_pixels(4), [[0;TILE_DIMENSION];TILE_DIMENSION]);
+        for (i, x) in tile_data.iter().enumerate() {
+            assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS + 5 * TILE_SIZE + i), *x);
+        }
+    }
+
+    #[test]
+    fn test_tile_index_to_cache()
+    {
+        let ppu = PPU::new();
+        for raw in [0u8, 127, 128, 255].iter() {
+            assert_eq!(ppu.tile_index_to_cache(*raw, false), *raw as usize);
+        }
+        assert_eq!(ppu.tile_index_to_cache(0, true), 256);
+        assert_eq!(ppu.tile_index_to_cache(127, true), 383);
+        assert_eq!(ppu.tile_index_to_cache(128, true), 128);
+        assert_eq!(ppu.tile_index_to_cache(255, true), 255);
+    }
+
+    #[test]
+    fn test_rebuild_caches()
+    {
+        let mut ppu = PPU::new();
+        ppu.tile_data[3 * TILE_SIZE] = 0x80;
+        ppu.tile_data[3 * TILE_SIZE + 1] = 0x80;
+        ppu.tile_data_bank1[TILE_SIZE + 15] = 0x01;
+        ppu.sprite_data[4..8].copy_from_slice(&[16, 8, 3, 0x20]);
+        assert_eq!(ppu.tiles[3].read_pixel(0, 0), 0);
+
+        ppu.rebuild_caches();
+        assert_eq!(ppu.tiles[3].read_pixel(0, 0), 3);
+        assert_eq!(ppu.tiles[3].read_pixel(1, 0), 0);
+        assert_eq!(ppu.tiles_bank1[1].read_pixel(7, 7), 2);
+        assert_eq!(ppu.sprites[1].ypos, 16);
+        assert_eq!(ppu.sprites[1].xpos, 8);
+        assert_eq!(ppu.sprites[1].tile, 3);
+        assert!(ppu.sprites[1].xflip);
+    }
+
+    #[test]
+    fn test_vram_block_round_trip()
+    {
+        let block: Vec<u8> = (0..VRAM_SIZE).map(|i| (i * 7 + i / 256) as u8).collect();
+        let mut ppu = PPU::new();
+        ppu.set_cgb_mode(true);
+        ppu.write_vram(&block);
+
+        let mut out = vec![0u8; VRAM_SIZE];
+        ppu.read_vram(&mut out);
+        assert_eq!(out, block);
+
+        // The tiles and maps match what byte by byte writes would give.
+        let mut reference = PPU::new();
+        for (i, x) in block.iter().enumerate() {
+            reference.bus_write8(TILESET_START_ADDRESS + i, *x);
+        }
+        for index in 0..TILESET_COUNT {
+            assert_eq!(ppu.tile_pixels(index), reference.tile_pixels(index));
+        }
+        assert_eq!(ppu.tilemap_entry(1, 31, 31), block[VRAM_SIZE - 1]);
+
+        // Bank 1 is separate.
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        ppu.read_vram(&mut out);
+        assert!(out.iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_vram_block_wrong_length()
+    {
+        PPU::new().write_vram(&[0; VRAM_SIZE - 1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_load_tile_out_of_range()
+    {
+        PPU::new().load_tile(TILESET_COUNT, &[0;TILE_SIZE]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_tile_pixels_out_of_range() {
+        let ppu = PPU::new();
+        ppu.tile_pixels(TILESET_COUNT);
+    }
+
+    fn test_pack() -> (PPU, Ram, InterruptStatus){
+        let ppu = PPU::new();
+        let is = InterruptStatus::new();
+        let ram = Ram::new(0x10000, 0);
+        (ppu, ram, is)
     }
 
     impl PPU{
@@ -924,6 +2602,22 @@ mod test {
         assert_eq!(ppu.sprite_data[(OAM_SPRITE_COUNT-1) * OAM_SPRITE_SIZE..], ref_sprite_data[..]);
     }
 
+    #[test]
+    fn test_sprite_accessor() {
+        let mut ppu = PPU::new();
+        write_sprite(&mut ppu, 5, [20, 30, 7, 0b1101_0000]);
+
+        let sprite = ppu.sprite(5);
+        assert_eq!(sprite.ypos, 20);
+        assert_eq!(sprite.xpos, 30);
+        assert_eq!(sprite.tile, 7);
+        assert!(sprite.behind_background);
+        assert!(sprite.yflip);
+        assert!(!sprite.xflip);
+        assert!(sprite.palette);
+        assert_eq!(ppu.sprite(4), OamSprite::new());
+    }
+
     #[test]
     fn test_lcdc_write() {
         let mut ppu = PPU::new();
@@ -940,6 +2634,194 @@ mod test {
         assert_eq!(ppu.bg_window_enable, false)
     }
 
+    #[test]
+    fn test_lcd_disable_resets_line() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        for _ in 0..50 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(100, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 50);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+
+        ppu.bus_write8(LCDC_ADDRESS, 0);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.tick_counter, 0);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 0);
+
+        // The PPU stays parked while the LCD is off.
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.tick_counter, 0);
+
+        // Turning it back on starts from the top of the frame.
+        ppu.bus_write8(LCDC_ADDRESS, PPU::LCDC_ENABLE_MASK);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 1);
+    }
+
+    #[test]
+    fn test_vram_init() {
+        let ppu = PPU::with_vram_init(VramInit::Fill(0xFF));
+        assert!(ppu.tile_data.iter().all(|x| *x == 0xFF));
+        assert!(ppu.sprite_data.iter().all(|x| *x == 0xFF));
+        assert_eq!(ppu.tile_pixels(0), [[3;TILE_DIMENSION];TILE_DIMENSION]);
+        assert_eq!(ppu.tile_pixels(TILESET_COUNT - 1), [[3;TILE_DIMENSION];TILE_DIMENSION]);
+        assert_eq!(ppu.tilemap_entry(1, 31, 31), 0xFF);
+        assert_eq!(ppu.sprites[39].xpos, 0xFF);
+
+        let ppu = PPU::with_vram_init(VramInit::Zero);
+        assert!(ppu.tile_data.iter().all(|x| *x == 0));
+        assert_eq!(ppu.tile_pixels(0), [[0;TILE_DIMENSION];TILE_DIMENSION]);
+
+        // A seed always gives the same contents, and the cache matches them.
+        let first = PPU::with_vram_init(VramInit::Random(1234));
+        let second = PPU::with_vram_init(VramInit::Random(1234));
+        let other = PPU::with_vram_init(VramInit::Random(5678));
+        assert_eq!(first.tile_data[..], second.tile_data[..]);
+        assert_ne!(first.tile_data[..], other.tile_data[..]);
+        // Neighbouring seeds don't collapse together.
+        let zero = PPU::with_vram_init(VramInit::Random(0));
+        let one = PPU::with_vram_init(VramInit::Random(1));
+        assert_ne!(zero.tile_data[..], one.tile_data[..]);
+        assert!(first.tile_data.iter().any(|x| *x != first.tile_data[0]));
+        let mut raw = [0u8;TILE_SIZE];
+        raw.copy_from_slice(&first.tile_data[7 * TILE_SIZE..8 * TILE_SIZE]);
+        let mut reference = PPU::new();
+        reference.load_tile(7, &raw);
+        assert_eq!(first.tile_pixels(7), reference.tile_pixels(7));
+    }
+
+    #[test]
+    fn test_post_boot_registers() {
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = PPU::new_post_boot();
+        assert_eq!(ppu.bus_read8(LCDC_ADDRESS), 0x91);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS), 0x85);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(BG_PALETTE_ADDRESS), 0xFC);
+        assert_eq!(ppu.bus_read8(OBJ_PALETTE1_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(OBJ_PALETTE2_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(SCX_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(WY_ADDRESS), 0);
+
+        // The first full frame starts at the end of the line.
+        ppu.run(456 - PPU::LINE_153_LY_TICKS, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.mode, Mode::SpriteSearch);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS), 0x86);
+    }
+
+    #[test]
+    fn test_cycles_until_next_event() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(SCX_ADDRESS, 5);
+        ppu.bus_write8(LCDC_ADDRESS, ppu.lcdc | PPU::LCDC_OBJ_DISPLAY_ENABLE_MASK);
+        write_sprite(&mut ppu, 0, [20, 30, 0, 0]);
+        run_frame(&mut ppu, &mut ram, &mut is);
+        assert_eq!(ppu.cycles_until_next_event(), 80);
+
+        // Across a whole frame, nothing changes until the reported tick.
+        let mut ticks = 0u32;
+        while ticks < 456 * 154 {
+            let cycles = ppu.cycles_until_next_event();
+            let state = (ppu.mode, ppu.ly());
+            ppu.execute_ticks(cycles - 1, &mut ram, &mut is);
+            assert_eq!((ppu.mode, ppu.ly()), state, "line {} tick {}", ppu.line_y, ppu.tick_counter);
+            ppu.execute_ticks(1, &mut ram, &mut is);
+            assert_ne!((ppu.mode, ppu.ly()), state, "line {} tick {}", ppu.line_y, ppu.tick_counter);
+            ticks += cycles as u32;
+        }
+        assert_eq!(ticks, 456 * 154);
+
+        ppu.bus_write8(LCDC_ADDRESS, 0);
+        assert_eq!(ppu.cycles_until_next_event(), u16::MAX);
+    }
+
+    #[test]
+    fn test_reset() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(SCX_ADDRESS, 12);
+        ppu.bus_write8(SCY_ADDRESS, 34);
+        ppu.bus_write8(LYC_ADDRES, 56);
+        ppu.bus_write8(LCDS_ADDRESS, 0xFF);
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0x1B);
+        ppu.bus_write8(TILESET_START_ADDRESS, 0xFF);
+        ppu.bus_write8(TILEMAP_START_ADDRESS, 0x22);
+        ppu.bus_write8(OAM_START_ADDRESS, 0x33);
+        for _ in 0..20 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+
+        ppu.reset(true);
+        let fresh = PPU::new();
+        assert_eq!(ppu.bus_read8(SCX_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(SCY_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(LYC_ADDRES), 0);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS), fresh.lcds_read());
+        assert_eq!(ppu.bus_read8(LCDC_ADDRESS), fresh.lcdc);
+        assert_eq!(ppu.bus_read8(BG_PALETTE_ADDRESS), 0);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.tick_counter, 0);
+        assert_eq!(ppu.mode, Mode::HBlank);
+
+        // VRAM and OAM were preserved.
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0xFF);
+        assert_eq!(ppu.tiles[0].read_pixel(0, 0), 1);
+        assert_eq!(ppu.bus_read8(TILEMAP_START_ADDRESS), 0x22);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0x33);
+        assert_eq!(ppu.sprites[0].ypos, 0x33);
+
+        // Until a full reset.
+        ppu.reset(false);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0);
+        assert_eq!(ppu.tiles[0].read_pixel(0, 0), 0);
+        assert_eq!(ppu.bus_read8(TILEMAP_START_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0);
+        assert_eq!(ppu.sprites[0].ypos, 0);
+    }
+
+    #[test]
+    fn test_lcd_enable_restart_state() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        for _ in 0..50 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(100, &mut ram, &mut is);
+
+        // Stale state from a disabled LCD is cleared when it is turned on.
+        ppu.bus_write8(LCDC_ADDRESS, 0);
+        ppu.line_y = 50;
+        ppu.tick_counter = 100;
+        ppu.bus_write8(LYC_ADDRES, 0);
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_LINE_CMP_IS_MASK);
+        is.clear_lcdstat();
+        ppu.bus_write8(LCDC_ADDRESS, PPU::LCDC_ENABLE_MASK);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.tick_counter, 0);
+        assert_eq!(ppu.mode, Mode::SpriteSearch);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS) & 0b111, 0b110);
+
+        // The LYC match on the first line does not interrupt.
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 1);
+        assert!(!is.is_lcdstat_active());
+
+        // But later matches do.
+        for _ in 1..154 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.line_y, 0);
+        assert!(is.is_lcdstat_active());
+
+        // Writing LCDC with the LCD already on does not restart the frame.
+        ppu.run(456, &mut ram, &mut is);
+        ppu.bus_write8(LCDC_ADDRESS, PPU::LCDC_ENABLE_MASK);
+        assert_eq!(ppu.line_y, 1);
+    }
+
     #[test]
     fn test_scy_rw() {
         let mut ppu = PPU::new();
@@ -988,6 +2870,30 @@ mod test {
         assert_eq!(ppu.bus_read8(address), value);
     }
 
+    #[test]
+    fn test_lyc_write_updates_coincidence() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_LINE_CMP_IS_MASK);
+        ppu.bus_write8(0xFF45, 100);
+        for _ in 0..5 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS) & 0b100, 0);
+        assert!(!is.is_lcdstat_active());
+
+        // Matching the current line sets the flag at once, and interrupts
+        // on the next update.
+        ppu.bus_write8(0xFF45, 5);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS) & 0b100, 0b100);
+        ppu.run(4, &mut ram, &mut is);
+        assert!(is.is_lcdstat_active());
+
+        // Moving LYC away clears it just as quickly.
+        ppu.bus_write8(0xFF45, 6);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS) & 0b100, 0);
+    }
+
     #[test]
     fn test_wy_rw() {
         let mut ppu = PPU::new();
@@ -1014,7 +2920,8 @@ mod test {
         let value = 0xFF;
         let address = 0xFF41;
         ppu.bus_write8(address, value);
-        assert_eq!(ppu.bus_read8(address), 0x78);
+        // Bit 7 is unused and reads as 1.
+        assert_eq!(ppu.bus_read8(address), 0xF8);
         assert_eq!(ppu.line_compare_is, true);
         assert_eq!(ppu.mode2_is, true);
         assert_eq!(ppu.mode1_is, true);
@@ -1074,6 +2981,55 @@ mod test {
         assert_eq!(ppu.obj_palette2.table, expected_table);
     }
 
+    #[test]
+    fn test_bg_color_palette_rw(){
+        let mut ppu = PPU::new();
+
+        // Write palette 1, color 1 with auto increment.
+        ppu.bus_write8(BCPS_ADDRESS, 0x80 | 0x0A);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0xCA);
+        ppu.bus_write8(BCPD_ADDRESS, 0x1F);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0xCB);
+        ppu.bus_write8(BCPD_ADDRESS, 0x7C);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0xCC);
+        assert_eq!(ppu.bg_color_palettes.color(1, 1), 0x7C1F);
+
+        // Read it back, reads do not increment.
+        ppu.bus_write8(BCPS_ADDRESS, 0x0A);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0x4A);
+        assert_eq!(ppu.bus_read8(BCPD_ADDRESS), 0x1F);
+        assert_eq!(ppu.bus_read8(BCPD_ADDRESS), 0x1F);
+        ppu.bus_write8(BCPS_ADDRESS, 0x0B);
+        assert_eq!(ppu.bus_read8(BCPD_ADDRESS), 0x7C);
+
+        // Without auto increment the index stays put.
+        ppu.bus_write8(BCPD_ADDRESS, 0x12);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0x4B);
+
+        // The index wraps at the end of palette memory.
+        ppu.bus_write8(BCPS_ADDRESS, 0xBF);
+        ppu.bus_write8(BCPD_ADDRESS, 0x34);
+        assert_eq!(ppu.bus_read8(BCPS_ADDRESS), 0xC0);
+        assert_eq!(ppu.bg_color_palettes.color(7, 3), 0x3400);
+    }
+
+    #[test]
+    fn test_obj_color_palette_rw(){
+        let mut ppu = PPU::new();
+        ppu.bus_write8(OCPS_ADDRESS, 0x80);
+        for x in 0..8 {
+            ppu.bus_write8(OCPD_ADDRESS, x);
+        }
+        assert_eq!(ppu.bus_read8(OCPS_ADDRESS), 0xC8);
+        assert_eq!(ppu.obj_color_palettes.color(0, 3), 0x0706);
+        assert_eq!(ppu.obj_color_palettes.color(1, 0), 0);
+
+        // The background palettes are separate.
+        assert_eq!(ppu.bg_color_palettes.color(0, 3), 0);
+        ppu.bus_write8(OCPS_ADDRESS, 0x02);
+        assert_eq!(ppu.bus_read8(OCPD_ADDRESS), 0x02);
+    }
+
     #[test]
     fn test_dma_transfer_start_ticks_and_addr() {
         let mut ppu = PPU::new();
@@ -1085,7 +3041,7 @@ mod test {
 
         assert_eq!(ppu.bus_read8(address), value);
         assert_eq!(ppu.oam_dma_src, transfer_address);
-        assert_eq!(ppu.oam_dma_ticks, 160);
+        assert_eq!(ppu.oam_dma_ticks, 640);
     }
 
     #[test]
@@ -1101,15 +3057,68 @@ mod test {
         ppu.execute_ticks(7, &mut ram, &mut is);
 
         // Then the ticks count down by the number of executed ticks
-        assert_eq!(ppu.oam_dma_ticks, 153);
+        assert_eq!(ppu.oam_dma_ticks, 633);
 
         // When more ticks are executed than remain on the dma transfer
-        ppu.execute_ticks(154, &mut ram, &mut is);
+        ppu.execute_ticks(634, &mut ram, &mut is);
 
         // Then the ticks will not underflow.
         assert_eq!(ppu.oam_dma_ticks, 0);
     }
 
+    #[test]
+    fn test_dma_transfer_large_tick_steps() {
+        let mut ppu = PPU::new();
+        let mut ram = Ram::new(1024, 0);
+        let mut is = InterruptStatus::new();
+        for x in 256..(256+OAM_RAM_SIZE){
+            ram.bus_write8(x, x as u8);
+        }
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 1);
+
+        // Steps larger than a u8 count down in full.
+        ppu.execute_ticks(300, &mut ram, &mut is);
+        assert_eq!(ppu.oam_dma_ticks, 340);
+        assert_eq!(ppu.sprite_data[74], 74);
+        assert_eq!(ppu.sprite_data[75], 0);
+
+        // A step well past the end finishes the transfer.
+        ppu.execute_ticks(1000, &mut ram, &mut is);
+        assert_eq!(ppu.oam_dma_ticks, 0);
+        assert!(!ppu.dma_active());
+        for x in 0..OAM_RAM_SIZE {
+            assert_eq!(ppu.sprite_data[x], x as u8);
+        }
+    }
+
+    #[test]
+    fn test_dma_active_duration() {
+        // Given a PPU with a staged DMA transfer
+        let (mut ppu, mut ram, mut is) = test_pack();
+        assert!(!ppu.dma_active());
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 0xC0);
+
+        // Then the transfer is active, blocking everything but high RAM.
+        assert!(ppu.dma_active());
+        assert!(ppu.dma_blocks_address(0xC000));
+        assert!(ppu.dma_blocks_address(OAM_START_ADDRESS));
+        assert!(ppu.dma_blocks_address(0xFF0F));
+        assert!(ppu.dma_blocks_address(0xFFFF));
+        assert!(!ppu.dma_blocks_address(0xFF80));
+        assert!(!ppu.dma_blocks_address(0xFFFE));
+
+        // When all but the last tick of the transfer are executed
+        for _ in 0..PPU::OAM_DMA_TRANSFER_TICKS - 1 {
+            ppu.execute_ticks(1, &mut ram, &mut is);
+            assert!(ppu.dma_active());
+        }
+
+        // Then the final tick ends the transfer, and unblocks the bus.
+        ppu.execute_ticks(1, &mut ram, &mut is);
+        assert!(!ppu.dma_active());
+        assert!(!ppu.dma_blocks_address(0xC000));
+    }
+
     #[test]
     fn test_dma_transfer_moves_data()
     {
@@ -1122,8 +3131,8 @@ mod test {
         }
         ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 1);
 
-        // When the ppu executes ticks
-        ppu.execute_ticks(1, &mut ram, &mut is);
+        // When the ppu executes the whole transfer
+        ppu.execute_ticks(640, &mut ram, &mut is);
 
         // Then the OAM memory must contain the new data from the transfer source.
         for x in 0..OAM_RAM_SIZE {
@@ -1132,9 +3141,248 @@ mod test {
     }
 
     #[test]
-    #[ignore]
+    fn test_dma_transfer_is_progressive()
+    {
+        // Given a PPU with a staged DMA transfer, and some initialized ram
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.lcd_enabled = false;
+        for x in 0..OAM_RAM_SIZE{
+            ram.bus_write8(0xC000 + x, 0xFF - x as u8);
+        }
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 0xC0);
+
+        // When fewer than 4 ticks execute, nothing is copied.
+        ppu.execute_ticks(3, &mut ram, &mut is);
+        assert_eq!(ppu.sprite_data[0], 0);
+
+        // When the transfer is half way done
+        ppu.execute_ticks(320 - 3, &mut ram, &mut is);
+
+        // Then only the first half of OAM has been updated.
+        for x in 0..OAM_RAM_SIZE / 2 {
+            assert_eq!(ppu.sprite_data[x], 0xFF - x as u8);
+        }
+        for x in OAM_RAM_SIZE / 2..OAM_RAM_SIZE {
+            assert_eq!(ppu.sprite_data[x], 0);
+        }
+        assert_eq!(ppu.sprites[19].ypos, 0xFF - 76);
+        assert_eq!(ppu.sprites[20].ypos, 0);
+
+        // When the rest of the transfer executes one tick at a time
+        for _ in 0..320 {
+            ppu.execute_ticks(1, &mut ram, &mut is);
+        }
+
+        // Then all of OAM is updated.
+        for x in 0..OAM_RAM_SIZE {
+            assert_eq!(ppu.sprite_data[x], 0xFF - x as u8);
+        }
+        assert!(!ppu.dma_active());
+    }
+
+    #[test]
+    fn test_dma_transfer_restart()
+    {
+        // Given a DMA transfer part way through copying from one page
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.lcd_enabled = false;
+        for x in 0..OAM_RAM_SIZE{
+            ram.bus_write8(0xC000 + x, 0x11);
+            ram.bus_write8(0xD000 + x, 0x22);
+        }
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 0xC0);
+        ppu.execute_ticks(40, &mut ram, &mut is);
+        assert_eq!(ppu.sprite_data[9], 0x11);
+        assert_eq!(ppu.sprite_data[10], 0);
+
+        // When the transfer is restarted from another page
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 0xD0);
+        assert_eq!(ppu.oam_dma_ticks, PPU::OAM_DMA_TRANSFER_TICKS);
+        ppu.execute_ticks(8, &mut ram, &mut is);
+
+        // Then the copy begins again from the start of OAM.
+        assert_eq!(ppu.sprite_data[0..2], [0x22, 0x22]);
+        assert_eq!(ppu.sprite_data[2..10], [0x11;8]);
+
+        // And the finished OAM only holds the second source.
+        ppu.execute_ticks(PPU::OAM_DMA_TRANSFER_TICKS, &mut ram, &mut is);
+        assert!(!ppu.dma_active());
+        assert_eq!(ppu.sprite_data, [0x22;OAM_RAM_SIZE]);
+    }
+
+    /// Stages a VRAM DMA transfer from the source to the VRAM destination.
+    fn hdma_setup(ppu: &mut PPU, src: u16, dst: u16) {
+        ppu.bus_write8(HDMA1_ADDRESS, (src >> 8) as u8);
+        ppu.bus_write8(HDMA2_ADDRESS, src as u8);
+        ppu.bus_write8(HDMA3_ADDRESS, (dst >> 8) as u8);
+        ppu.bus_write8(HDMA4_ADDRESS, dst as u8);
+    }
+
+    #[test]
+    fn test_gdma_transfer(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.set_cgb_mode(true);
+        for x in 0..0x40 {
+            ram.bus_write8(0xC000 + x, x as u8 + 1);
+        }
+
+        // 3 blocks into the tile data, the low address bits are ignored.
+        hdma_setup(&mut ppu, 0xC00F, 0x8105);
+        ppu.bus_write8(HDMA5_ADDRESS, 0x02);
+        ppu.execute_ticks(4, &mut ram, &mut is);
+
+        assert_eq!(ppu.bus_read8(HDMA5_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(0x80FF), 0);
+        for x in 0..0x30 {
+            assert_eq!(ppu.bus_read8(0x8100 + x), x as u8 + 1);
+        }
+        assert_eq!(ppu.bus_read8(0x8130), 0);
+        // Tile data writes are decoded.
+        assert_eq!(ppu.tiles[0x10].read_pixel(7, 0), 1);
+
+        // Transfers target the selected VRAM bank, and can reach the tile maps.
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        hdma_setup(&mut ppu, 0xC000, 0x1800);
+        ppu.bus_write8(HDMA5_ADDRESS, 0x00);
+        ppu.execute_ticks(4, &mut ram, &mut is);
+        assert_eq!(ppu.tile_attributes[0], 1);
+        assert_eq!(ppu.tile_attributes[15], 16);
+        assert_eq!(ppu.tilemaps[0], 0);
+    }
+
+    #[test]
+    fn test_hdma_transfer(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        for x in 0..0x40 {
+            ram.bus_write8(0xC000 + x, x as u8 + 1);
+        }
+
+        // 3 blocks, one per HBlank.
+        hdma_setup(&mut ppu, 0xC000, 0x9800);
+        ppu.bus_write8(HDMA5_ADDRESS, 0x82);
+        assert_eq!(ppu.bus_read8(HDMA5_ADDRESS), 0x02);
+        ppu.run(4, &mut ram, &mut is);
+        assert_eq!(ppu.tilemaps[0], 0);
+
+        // Each HBlank copies a block.
+        for line in 1..=3 {
+            while ppu.mode != Mode::HBlank {
+                ppu.run(4, &mut ram, &mut is);
+            }
+            assert_eq!(ppu.tilemaps[line * 16 - 1], line as u8 * 16);
+            assert_eq!(ppu.tilemaps[line * 16], 0);
+            ppu.run(456 - 252, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.bus_read8(HDMA5_ADDRESS), 0xFF);
+
+        // Later HBlanks copy nothing more.
+        run_frame(&mut ppu, &mut ram, &mut is);
+        assert_eq!(ppu.tilemaps[48], 0);
+    }
+
+    #[test]
+    fn test_hdma_stop(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        for x in 0..0x40 {
+            ram.bus_write8(0xC000 + x, 0xAA);
+        }
+
+        hdma_setup(&mut ppu, 0xC000, 0x9800);
+        ppu.bus_write8(HDMA5_ADDRESS, 0x83);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.bus_read8(HDMA5_ADDRESS), 0x02);
+
+        // Stopping leaves the remaining length readable, with bit 7 set.
+        ppu.bus_write8(HDMA5_ADDRESS, 0x00);
+        assert_eq!(ppu.bus_read8(HDMA5_ADDRESS), 0x82);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.tilemaps[15], 0xAA);
+        assert_eq!(ppu.tilemaps[16], 0);
+    }
+
+    #[test]
     fn test_dma_memory_lock() {
-        panic!("test_dma_memory_lock is not implemented");
+        let mut ppu = PPU::new();
+        let mut ram = Ram::new(1024, 0);
+        let mut is = InterruptStatus::new();
+        ppu.lcd_enabled = false;
+        ppu.bus_write8(OAM_START_ADDRESS, 0x42);
+
+        // OAM is locked from the moment the transfer is staged.
+        ppu.bus_write8(OAM_DMA_REGISTER_ADDRESS, 1);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0xFF);
+        ppu.execute_ticks(4, &mut ram, &mut is);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0xFF);
+        ppu.bus_write8(OAM_START_ADDRESS + 1, 0x24);
+        assert_eq!(ppu.sprite_data[1], 0);
+
+        // And is unlocked once it completes.
+        ppu.execute_ticks(PPU::OAM_DMA_TRANSFER_TICKS, &mut ram, &mut is);
+        assert!(!ppu.dma_active());
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0);
+        ppu.bus_write8(OAM_START_ADDRESS + 1, 0x24);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS + 1), 0x24);
+    }
+
+    #[test]
+    fn test_peek_ignores_locks() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x12);
+        ppu.bus_write8(TILEMAP_START_ADDRESS, 0x34);
+        ppu.bus_write8(OAM_START_ADDRESS, 0x56);
+
+        // Mode 3 locks both VRAM and OAM to reads.
+        ppu.run(456 + 84, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0xFF);
+
+        assert_eq!(ppu.bus_peek8(TILESET_START_ADDRESS), 0x12);
+        assert_eq!(ppu.bus_peek8(TILEMAP_START_ADDRESS), 0x34);
+        assert_eq!(ppu.bus_peek8(OAM_START_ADDRESS), 0x56);
+        assert_eq!(ppu.bus_peek8(LY_ADDRESS), 1);
+        assert_eq!(ppu.bus_peek8(LCDS_ADDRESS) & 0b11, Mode::LcdTransfer as u8);
+        // Addresses the PPU doesn't own read as open bus rather than panic.
+        assert_eq!(ppu.bus_peek8(0xC000), 0xFF);
+    }
+
+    #[test]
+    fn test_oam_locked_during_search_and_transfer() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(OAM_START_ADDRESS, 0x42);
+
+        // Mode 2, locked.
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::SpriteSearch);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0xFF);
+        ppu.bus_write8(OAM_START_ADDRESS, 0x11);
+        assert_eq!(ppu.sprite_data[0], 0x42);
+
+        // Mode 3, locked.
+        ppu.run(80, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0xFF);
+        ppu.bus_write8(OAM_START_ADDRESS, 0x11);
+        assert_eq!(ppu.sprites[0].ypos, 0x42);
+
+        // Mode 0, available.
+        ppu.run(172, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0x42);
+        ppu.bus_write8(OAM_START_ADDRESS, 0x11);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0x11);
+
+        // Mode 1, available.
+        while ppu.mode != Mode::VBlank {
+            ppu.run(4, &mut ram, &mut is);
+        }
+        ppu.bus_write8(OAM_START_ADDRESS, 0x22);
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0x22);
+
+        // With the LCD off OAM is always available.
+        ppu.mode = Mode::SpriteSearch;
+        ppu.lcd_enabled = false;
+        assert_eq!(ppu.bus_read8(OAM_START_ADDRESS), 0x22);
     }
 
     #[test]
@@ -1153,6 +3401,64 @@ mod test {
         assert_eq!(ppu.tilemaps[2047], end_value);
     }
 
+    #[test]
+    fn test_vram_locked_during_transfer() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x12);
+        ppu.bus_write8(TILEMAP_END_ADDRESS, 0x34);
+
+        // Move into mode 3.
+        ppu.run(80, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+
+        // Reads return 0xFF, and writes are dropped.
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(TILEMAP_END_ADDRESS), 0xFF);
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x56);
+        ppu.bus_write8(TILEMAP_END_ADDRESS, 0x78);
+        assert_eq!(ppu.tile_data[0], 0x12);
+        assert_eq!(ppu.tiles[0].read_pixel(1, 0), 0);
+        assert_eq!(ppu.tilemaps[TILEMAPS_SIZE - 1], 0x34);
+
+        // Once in HBlank, VRAM is available again.
+        ppu.run(172, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0x12);
+        ppu.bus_write8(TILEMAP_END_ADDRESS, 0x78);
+        assert_eq!(ppu.bus_read8(TILEMAP_END_ADDRESS), 0x78);
+
+        // VRAM is never locked with the LCD off.
+        ppu.mode = Mode::LcdTransfer;
+        ppu.lcd_enabled = false;
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0x12);
+    }
+
+    #[test]
+    fn test_tilemap_entry() {
+        let mut ppu = PPU::new();
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + 3 * TILEMAP_DIMENSION + 5, 0x12);
+        ppu.bus_write8(TILEMAP_B_START_ADDRESS + 31 * TILEMAP_DIMENSION + 31, 0x34);
+        ppu.bus_write8(TILEMAP_B_START_ADDRESS, 0x56);
+
+        assert_eq!(ppu.tilemap_entry(0, 5, 3), 0x12);
+        assert_eq!(ppu.tilemap_entry(1, 5, 3), 0);
+        assert_eq!(ppu.tilemap_entry(1, 31, 31), 0x34);
+        assert_eq!(ppu.tilemap_entry(1, 0, 0), 0x56);
+        assert_eq!(ppu.tilemap_entry(0, 0, 0), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_tilemap_entry_bad_map() {
+        PPU::new().tilemap_entry(2, 0, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_tilemap_entry_bad_position() {
+        PPU::new().tilemap_entry(0, 32, 0);
+    }
+
     #[test]
     fn test_cycles_through_drawing_modes() {
         // Currently very flawed. Does not account for different timing within a line.
@@ -1178,16 +3484,118 @@ mod test {
         assert_eq!(ppu.mode, Mode::SpriteSearch);
     }
 
-    #[test]
-    #[ignore]
-    fn test_cycles_more_accurate_modes_for_real(){
-        assert!(false);
+    /// Steps a line one tick at a time, and counts the ticks spent in mode 3.
+    fn measure_mode3(ppu: &mut PPU, bus: &mut impl BusRW, is: &mut InterruptStatus) -> u16 {
+        let mut ticks = 0;
+        loop {
+            ppu.execute_ticks(1, bus, is);
+            match ppu.mode {
+                Mode::LcdTransfer => {ticks += 1;}
+                Mode::HBlank if ticks > 0 => {return ticks;}
+                _ => {}
+            }
+        }
     }
 
     #[test]
-    fn test_ycomp_stat_interrupt() {
+    fn test_default_mode_boundaries(){
         let (mut ppu, mut ram, mut is) = test_pack();
-        is.isrmask = 0xFF;
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.mode3_length(), 172);
+        assert_eq!(ppu.transfer_end(), 251);
+
+        // Mode 2 for ticks 0-79, mode 3 for 80-251, then HBlank to 455.
+        let mut modes = vec![ppu.mode];
+        for _ in 1..456 {
+            ppu.execute_ticks(1, &mut ram, &mut is);
+            modes.push(ppu.mode);
+        }
+        assert!(modes[..80].iter().all(|x| *x == Mode::SpriteSearch));
+        assert!(modes[80..252].iter().all(|x| *x == Mode::LcdTransfer));
+        assert!(modes[252..].iter().all(|x| *x == Mode::HBlank));
+    }
+
+    #[test]
+    fn test_mode3_fine_scroll_penalty(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172);
+
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(SCX_ADDRESS, 3);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 175);
+
+        // Only the fine scroll matters.
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(SCX_ADDRESS, 16);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172);
+    }
+
+    #[test]
+    fn test_scx_write_in_hblank_keeps_mode(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_MODE0_IS_MASK);
+        ppu.run(252, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        ppu.poll_events();
+        is.clear_lcdstat();
+
+        // Mode 3 already ended, so the new fine scroll can't restart it.
+        ppu.bus_write8(SCX_ADDRESS, 7);
+        while ppu.line() == 0 {
+            ppu.execute_ticks(1, &mut ram, &mut is);
+            if ppu.line() == 0 {
+                assert_eq!(ppu.mode, Mode::HBlank);
+            }
+        }
+        assert_eq!(ppu.poll_events().mode_changes, 1);
+        assert!(!is.is_lcdstat_active());
+
+        // It applies from the next line.
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 179);
+    }
+
+    #[test]
+    fn test_mode3_sprite_penalty(){
+        // Three sprites in separate tiles, each aligned to the tile start.
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        write_sprite(&mut ppu, 0, [16, 8, 0, 0]);
+        write_sprite(&mut ppu, 1, [16, 24, 0, 0]);
+        write_sprite(&mut ppu, 2, [16, 40, 0, 0]);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172 + 3 * 11);
+
+        // Sprites sharing a tile only wait on its fetch once.
+        let mut ppu = sprite_test_ppu();
+        write_sprite(&mut ppu, 0, [16, 8, 0, 0]);
+        write_sprite(&mut ppu, 1, [16, 8, 0, 0]);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172 + 11 + 6);
+
+        // Sprites late in a tile wait less.
+        let mut ppu = sprite_test_ppu();
+        write_sprite(&mut ppu, 0, [16, 13, 0, 0]);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172 + 6);
+
+        // Sprites on other lines, or disabled sprites, cost nothing.
+        let mut ppu = sprite_test_ppu();
+        write_sprite(&mut ppu, 0, [40, 8, 0, 0]);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172);
+        let mut ppu = sprite_test_ppu();
+        ppu.obj_enabled = false;
+        write_sprite(&mut ppu, 0, [16, 8, 0, 0]);
+        assert_eq!(measure_mode3(&mut ppu, &mut ram, &mut is), 172);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_cycles_more_accurate_modes_for_real(){
+        assert!(false);
+    }
+
+    #[test]
+    fn test_ycomp_stat_interrupt() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
         ppu.lcd_enabled = true;
         ppu.line_compare_is = true;
         ppu.line_compare_value = 1;
@@ -1233,6 +3641,66 @@ mod test {
         assert_eq!(is.is_lcdstat_active(), true);
     }
 
+    #[test]
+    fn test_line_153_reads_ly_zero() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.line_compare_is = true;
+        ppu.line_compare_value = 0;
+        for _ in 0..153 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        is.clear_lcdstat();
+
+        // The first ticks of line 153 read as 153.
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 153);
+        ppu.execute_ticks(3, &mut ram, &mut is);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 153);
+        assert!(!is.is_lcdstat_active());
+
+        // Then LY reads 0 and matches LYC, while still in VBlank.
+        ppu.execute_ticks(1, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 153);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(LCDS_ADDRESS) & 0b100, 0b100);
+        assert!(is.is_lcdstat_active());
+
+        // The real line 0 does not trigger the comparison again.
+        is.clear_lcdstat();
+        ppu.execute_ticks(452, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 0);
+        assert!(!is.is_lcdstat_active());
+    }
+
+    #[test]
+    fn test_lyc_interrupt_on_vblank_lines() {
+        for lyc in [144u8, 150, 152].iter() {
+            let (mut ppu, mut ram, mut is) = test_pack();
+            is.isrmask = 0xFF;
+            ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_LINE_CMP_IS_MASK);
+            ppu.bus_write8(LYC_ADDRES, *lyc);
+            run_frame(&mut ppu, &mut ram, &mut is);
+            is.clear_lcdstat();
+
+            // The only interrupt in the frame is at the start of line LYC,
+            // and VBlank lasts throughout.
+            let mut requests = Vec::new();
+            for _ in 0..456 * 154 / 4 {
+                ppu.run(4, &mut ram, &mut is);
+                if ppu.line_y >= PPU::LCD_LINE_VBLANK_START {
+                    assert_eq!(ppu.mode, Mode::VBlank, "line {}", ppu.line_y);
+                }
+                if is.is_lcdstat_active() {
+                    requests.push((ppu.line_y, ppu.tick_counter));
+                    is.clear_lcdstat();
+                }
+            }
+            assert_eq!(requests, vec![(*lyc, 0)]);
+        }
+    }
+
     #[test]
     fn test_hblank_stat_interrupt() {
         // This is not accurate, since it does not account for variable line timing.
@@ -1256,6 +3724,180 @@ mod test {
         assert_eq!(is.is_lcdstat_active(), false);
     }
 
+    #[test]
+    fn test_mode2_interrupt_entering_vblank() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.mode2_is = true;
+
+        // Finish the visible lines, ignoring their mode 2 interrupts.
+        for _ in 0..143 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(452, &mut ram, &mut is);
+        is.clear_lcdstat();
+        is.clear_vblank();
+
+        ppu.run(4, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert!(is.is_vblank_active());
+        assert!(is.is_lcdstat_active());
+
+        // There is no other mode 2 interrupt until the next frame.
+        is.clear_lcdstat();
+        ppu.run(456 * 10 - 4, &mut ram, &mut is);
+        assert!(!is.is_lcdstat_active());
+        ppu.run(4, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 0);
+        assert!(is.is_lcdstat_active());
+
+        // The mode 1 interrupt already holds the line high, so no extra
+        // request is made with both enabled.
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.mode2_is = true;
+        ppu.mode1_is = true;
+        for _ in 0..143 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(452, &mut ram, &mut is);
+        is.clear_lcdstat();
+        ppu.run(4, &mut ram, &mut is);
+        assert!(is.is_lcdstat_active());
+        is.clear_lcdstat();
+        ppu.run(8, &mut ram, &mut is);
+        assert!(!is.is_lcdstat_active());
+    }
+
+    #[test]
+    fn test_stat_line() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.run(4, &mut ram, &mut is);
+        assert!(!ppu.stat_line());
+
+        // LY=LYC holds the line high until LY moves on.
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_LINE_CMP_IS_MASK);
+        ppu.bus_write8(LYC_ADDRES, 0);
+        ppu.run(4, &mut ram, &mut is);
+        assert!(ppu.stat_line());
+        ppu.run(456, &mut ram, &mut is);
+        assert!(!ppu.stat_line());
+
+        // Mode 0 raises it for HBlank only.
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_MODE0_IS_MASK);
+        while ppu.mode != Mode::HBlank {
+            assert!(!ppu.stat_line());
+            ppu.run(4, &mut ram, &mut is);
+        }
+        assert!(ppu.stat_line());
+    }
+
+    #[test]
+    fn test_register_accessors() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_MODE0_IS_MASK | PPU::LCDS_LINE_CMP_IS_MASK);
+        ppu.bus_write8(LYC_ADDRES, 2);
+
+        // Compare across every mode, and with the LY=LYC flag set.
+        let mut saw_compare = false;
+        for _ in 0..(456 * 3) / 4 {
+            assert_eq!(ppu.lcdc(), ppu.bus_read8(LCDC_ADDRESS));
+            assert_eq!(ppu.stat(), ppu.bus_read8(LCDS_ADDRESS));
+            saw_compare |= ppu.stat() & 0x04 != 0;
+            ppu.run(4, &mut ram, &mut is);
+        }
+        assert!(saw_compare);
+
+        ppu.bus_write8(LCDC_ADDRESS, 0x13);
+        assert_eq!(ppu.lcdc(), 0x13);
+        assert_eq!(ppu.lcdc(), ppu.bus_read8(LCDC_ADDRESS));
+        assert_eq!(ppu.stat(), ppu.bus_read8(LCDS_ADDRESS));
+    }
+
+    #[test]
+    fn test_stat_interrupt_blocking() {
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.line_compare_is = true;
+        ppu.mode2_is = true;
+        ppu.line_compare_value = 1;
+
+        // Finish line 0, and clear the mode 2 interrupt from its start.
+        ppu.run(452, &mut ram, &mut is);
+        is.clear_lcdstat();
+
+        // LYC and mode 2 rise together on line 1, one interrupt.
+        let mut requests = 0;
+        while ppu.line_y < 2 {
+            ppu.run(4, &mut ram, &mut is);
+            if is.is_lcdstat_active() {
+                requests += 1;
+                is.clear_lcdstat();
+            }
+        }
+        assert_eq!(requests, 1);
+
+        // Mode 0 rising while LYC holds the line high does not interrupt.
+        let (mut ppu, mut ram, mut is) = test_pack();
+        is.isrmask = 0xFF;
+        ppu.line_compare_is = true;
+        ppu.mode0_is = true;
+        ppu.line_compare_value = 1;
+        ppu.run(456, &mut ram, &mut is);
+        assert!(is.is_lcdstat_active());
+        is.clear_lcdstat();
+        ppu.run(452, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        assert!(!is.is_lcdstat_active());
+    }
+
+    #[test]
+    fn test_large_tick_steps(){
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        // One PPU steps 4 ticks at a time, the other takes 1000 tick steps.
+        let (mut small, mut ram, mut small_is) = test_pack();
+        let (mut large, _, mut large_is) = test_pack();
+        small_is.isrmask = 0xFF;
+        large_is.isrmask = 0xFF;
+        let large_frames = Rc::new(Cell::new(0));
+        let counter = large_frames.clone();
+        large.set_frame_callback(move |_| counter.set(counter.get() + 1));
+        for ppu in [&mut small, &mut large] {
+            ppu.bg_window_enable = true;
+            ppu.bg_window_signed_addressing = false;
+            ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+            ppu.bus_write8(LCDS_ADDRESS, PPU::LCDS_MODE0_IS_MASK);
+            fill_tile(ppu, 1, 0xFF, 0x00);
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + 5 * TILEMAP_DIMENSION + 3, 1);
+        }
+
+        // 1000 ticks crosses two lines, and each HBlank interrupt fires.
+        small.run(1000, &mut ram, &mut small_is);
+        large.execute_ticks(1000, &mut ram, &mut large_is);
+        assert_eq!(large.line_y, 2);
+        assert_eq!(large.tick_counter, 1000 - 2 * 456);
+        assert_eq!(large.mode, Mode::LcdTransfer);
+        assert_eq!(large.line_y, small.line_y);
+        assert_eq!(large.tick_counter, small.tick_counter);
+        assert_eq!(large_is.isrreq, small_is.isrreq);
+        assert!(large_is.is_lcdstat_active());
+
+        // Every line is drawn, and the frame is published once.
+        for _ in 0..70 {
+            small.run(1000, &mut ram, &mut small_is);
+            large.execute_ticks(1000, &mut ram, &mut large_is);
+        }
+        assert_eq!(large.line_y, small.line_y);
+        assert_eq!(large.mode, small.mode);
+        assert_eq!(large_is.isrreq, small_is.isrreq);
+        assert!(large_is.is_vblank_active());
+        assert_eq!(large_frames.get(), 1);
+        assert_eq!(large.framebuffer()[40 * 160 + 24], 1);
+        assert_eq!(large.framebuffer()[..], small.framebuffer()[..]);
+    }
+
     #[test]
     fn test_vblank_interrupts() {
         let (mut ppu, mut ram, mut is) = test_pack();
@@ -1325,6 +3967,107 @@ mod test {
         assert_eq!(ppu.line_buffer.pixeldata[1], 0b11_11_00_00);
     }
 
+    /// Draws a frame with the background scrolled by (scx, scy), over a map
+    /// with a distinct tile in each corner.
+    fn corner_map_frame(scx: u8, scy: u8) -> Vec<u8> {
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+        ppu.window_enabled = false;
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        fill_tile(&mut ppu, 2, 0x00, 0xFF);
+        fill_tile(&mut ppu, 3, 0xFF, 0xFF);
+        let last = TILEMAP_DIMENSION - 1;
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS, 3);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + last, 2);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + last * TILEMAP_DIMENSION, 2);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + last * TILEMAP_DIMENSION + last, 1);
+        ppu.bus_write8(SCX_ADDRESS, scx);
+        ppu.bus_write8(SCY_ADDRESS, scy);
+        run_frame(&mut ppu, &mut ram, &mut is);
+        ppu.framebuffer().to_vec()
+    }
+
+    #[test]
+    fn test_lcd_dimensions(){
+        let mut ppu = PPU::new();
+        assert_eq!((LCD_WIDTH, LCD_HEIGHT), (160, 144));
+        assert_eq!(ppu.framebuffer().len(), LCD_WIDTH * LCD_HEIGHT);
+        assert_eq!(ppu.framebuffer_sources().len(), LCD_WIDTH * LCD_HEIGHT);
+        assert_eq!(ppu.framebuffer_grey().len(), LCD_WIDTH * LCD_HEIGHT);
+        assert_eq!(ppu.render_scanline(LCD_HEIGHT as u8 - 1).len(), LCD_WIDTH);
+    }
+
+    #[test]
+    fn test_render_scanline(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+        ppu.window_enabled = false;
+        ppu.obj_enabled = true;
+        ppu.obj_double_sprites = false;
+
+        // Map row 0 alternates tiles of color 1 and 2, row 1 is color 2 
+        // with a sprite of color 3 over its first tile.
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        fill_tile(&mut ppu, 2, 0x00, 0xFF);
+        fill_tile(&mut ppu, 3, 0xFF, 0xFF);
+        for col in 0..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + col, 1 + (col & 1) as u8);
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + TILEMAP_DIMENSION + col, 2);
+        }
+        ppu.bus_write8(OBJ_PALETTE1_ADDRESS, 0b11_10_01_00);
+        write_sprite(&mut ppu, 0, [24, 8, 3, 0]);
+
+        // Part way into line 3, rendering other lines changes nothing.
+        for _ in 0..3 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(200, &mut ram, &mut is);
+        let line_y = ppu.line_y;
+        let mode = ppu.mode;
+        let progress = ppu.frame_progress.to_vec();
+
+        let line0 = ppu.render_scanline(0);
+        let line8 = ppu.render_scanline(8);
+        let expected0: Vec<u8> = (0..160).map(|x| 1 + ((x / 8) & 1) as u8).collect();
+        let expected8: Vec<u8> = (0..160).map(|x| if x < 8 {3} else {2}).collect();
+        assert_eq!(line0[..], expected0[..]);
+        assert_eq!(line8[..], expected8[..]);
+
+        assert_eq!(ppu.line_y, line_y);
+        assert_eq!(ppu.mode, mode);
+        assert_eq!(ppu.frame_progress[..], progress[..]);
+
+        // The rest of the frame is drawn as normal.
+        run_frame(&mut ppu, &mut ram, &mut is);
+        assert_eq!(ppu.framebuffer()[8 * 160..9 * 160], expected8[..]);
+    }
+
+    #[test]
+    fn test_background_scroll_wraps(){
+        // Scrolled 4 pixels short of the bottom right corner, the top left
+        // of the map wraps around to 4 pixels in from the top left.
+        let frame = corner_map_frame(252, 252);
+        let pixel = |x: usize, y: usize| frame[y * 160 + x];
+        assert_eq!(pixel(0, 0), 1);
+        assert_eq!(pixel(3, 3), 1);
+        assert_eq!(pixel(4, 0), 2);
+        assert_eq!(pixel(0, 4), 2);
+        assert_eq!(pixel(4, 4), 3);
+        assert_eq!(pixel(11, 11), 3);
+        assert_eq!(pixel(12, 4), 0);
+        assert_eq!(pixel(4, 12), 0);
+
+        // One pixel short, only the first row and column come from the
+        // far edges of the map.
+        let frame = corner_map_frame(255, 255);
+        let pixel = |x: usize, y: usize| frame[y * 160 + x];
+        assert_eq!(pixel(0, 0), 1);
+        assert_eq!(pixel(1, 1), 3);
+        assert_eq!(pixel(8, 8), 3);
+        assert_eq!(pixel(9, 9), 0);
+        assert_eq!(pixel(159, 143), 0);
+    }
+
     #[test]
     fn test_basic_background_scroll_render(){
         let (mut ppu, mut ram, mut is) = test_pack();
@@ -1428,6 +4171,566 @@ mod test {
         assert_eq!(ppu.line_buffer.pixeldata[3], 0x14);
     }
 
+    #[test]
+    fn test_background_tilemap_select(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        // Tile 1 is solid color 1, tile 2 is solid color 2.
+        let solid_1 = [0xFF, 0x00];
+        let solid_2 = [0x00, 0xFF];
+        ppu.lcd_enabled = true;
+        ppu.bg_window_enable = true;
+        ppu.obj_enabled = false;
+        ppu.window_enabled = false;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bg_tiles_high = true;
+
+        // Swap colors 1 and 2, and map 0 to 3.
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b00_01_10_11);
+
+        for row in 0..TILE_DIMENSION {
+            for i in 0..2 {
+                ppu.bus_write8(TILESET_START_ADDRESS + TILE_SIZE + row * 2 + i, solid_1[i]);
+                ppu.bus_write8(TILESET_START_ADDRESS + TILE_SIZE * 2 + row * 2 + i, solid_2[i]);
+            }
+        }
+
+        // Alternate tiles 1 and 2 along the first row of the high tile map only.
+        for x in 0..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_B_START_ADDRESS + x, 1 + (x % 2) as u8);
+        }
+
+        // Render a line.
+        ppu.run(456, &mut ram, &mut is);
+        assert!(ppu.line_pending);
+
+        // Tile 1 (color 1) is mapped to 2, tile 2 (color 2) is mapped to 1.
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0b10_10_10_10);
+        assert_eq!(ppu.line_buffer.pixeldata[1], 0b10_10_10_10);
+        assert_eq!(ppu.line_buffer.pixeldata[2], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[3], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[38], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[39], 0b01_01_01_01);
+
+        // The low tile map is all tile 0, which is color 0 mapped to 3.
+        ppu.bg_tiles_high = false;
+        ppu.run(456, &mut ram, &mut is);
+        for x in 0..40 {
+            assert_eq!(ppu.line_buffer.pixeldata[x], 0xFF);
+        }
+    }
+
+    #[test]
+    fn test_framebuffer_published_at_vblank(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.lcd_enabled = true;
+        ppu.bg_window_enable = true;
+        ppu.obj_enabled = false;
+        ppu.window_enabled = false;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+
+        // Tile 1 is solid color 1, placed at tile (1, 1) of the map.
+        for row in 0..TILE_DIMENSION {
+            ppu.bus_write8(TILESET_START_ADDRESS + TILE_SIZE + row * 2, 0xFF);
+        }
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + TILEMAP_DIMENSION + 1, 1);
+
+        // Draw every visible line but the last, nothing is published yet.
+        for _ in 0..143 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.framebuffer()[8 * 160 + 8], 0);
+
+        // Finishing the last line enters VBlank, and publishes the frame.
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert_eq!(ppu.framebuffer().len(), 160 * 144);
+        assert_eq!(ppu.framebuffer()[8 * 160 + 8], 1);
+        assert_eq!(ppu.framebuffer()[15 * 160 + 15], 1);
+        assert_eq!(ppu.framebuffer()[8 * 160 + 7], 0);
+        assert_eq!(ppu.framebuffer()[7 * 160 + 8], 0);
+        assert_eq!(ppu.framebuffer()[8 * 160 + 16], 0);
+        assert_eq!(ppu.framebuffer()[16 * 160 + 8], 0);
+
+        // The frame is stable through the rest of VBlank.
+        for _ in 0..10 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.line_y, 0);
+        assert_eq!(ppu.framebuffer()[8 * 160 + 8], 1);
+    }
+
+    #[test]
+    fn test_frame_callback_once_per_frame(){
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let (mut ppu, mut ram, mut is) = test_pack();
+        let frames = Rc::new(Cell::new(0));
+        let counter = frames.clone();
+        ppu.set_frame_callback(move |frame| {
+            assert_eq!(frame.len(), FRAMEBUFFER_SIZE);
+            counter.set(counter.get() + 1);
+        });
+
+        // Nothing is reported until the last visible line completes.
+        for _ in 0..143 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(452, &mut ram, &mut is);
+        assert_eq!(frames.get(), 0);
+        ppu.run(4, &mut ram, &mut is);
+        assert_eq!(frames.get(), 1);
+        assert_eq!(ppu.line_y, 144);
+
+        // Exactly one call per 70224 tick frame.
+        for frame in 2..5 {
+            run_frame(&mut ppu, &mut ram, &mut is);
+            assert_eq!(frames.get(), frame);
+        }
+    }
+
+    #[test]
+    fn test_scanline_callback(){
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bg_window_enable = true;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+        // Tile row 1 of the map is solid color 3.
+        fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+        for col in 0..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + TILEMAP_DIMENSION + col, 1);
+        }
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let log = lines.clone();
+        ppu.set_scanline_callback(move |line, pixels| {
+            assert_eq!(pixels.len(), 160);
+            log.borrow_mut().push((line, pixels[0]));
+        });
+        run_frame(&mut ppu, &mut ram, &mut is);
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 144);
+        for (y, (line, pixel)) in lines.iter().enumerate() {
+            assert_eq!(*line as usize, y);
+            assert_eq!(*pixel, if (8..16).contains(&y) {3} else {0});
+        }
+    }
+
+    #[test]
+    fn test_vram_bank_select(){
+        let mut ppu = PPU::new();
+        ppu.set_cgb_mode(true);
+        assert_eq!(ppu.bus_read8(VBK_ADDRESS), 0xFE);
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x11);
+        ppu.bus_write8(TILEMAP_START_ADDRESS, 0x22);
+
+        // Bank 1 has its own tile data, and tile attributes over the maps.
+        ppu.bus_write8(VBK_ADDRESS, 0xFF);
+        assert_eq!(ppu.bus_read8(VBK_ADDRESS), 0xFF);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0);
+        assert_eq!(ppu.bus_read8(TILEMAP_START_ADDRESS), 0);
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x80);
+        ppu.bus_write8(TILEMAP_START_ADDRESS, 0x44);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0x80);
+        assert_eq!(ppu.tiles_bank1[0].read_pixel(0, 0), 1);
+        assert_eq!(ppu.tile_attributes[0], 0x44);
+
+        // Bank 0 is untouched.
+        ppu.bus_write8(VBK_ADDRESS, 0);
+        assert_eq!(ppu.bus_read8(TILESET_START_ADDRESS), 0x11);
+        assert_eq!(ppu.bus_read8(TILEMAP_START_ADDRESS), 0x22);
+        assert_eq!(ppu.tiles[0].read_pixel(0, 0), 0);
+    }
+
+    #[test]
+    fn test_dmg_ignores_bank1(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut frame = |bank1: Option<u8>| {
+            let mut ppu = PPU::with_vram_init(VramInit::Random(7));
+            if let Some(value) = bank1 {
+                ppu.tile_data_bank1 = [value; TILESET_RAM];
+                ppu.tile_attributes = [value; TILEMAPS_SIZE];
+                ppu.rebuild_caches();
+            }
+            ppu.bus_write8(LCDC_ADDRESS, 0x91);
+            ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+            run_frame(&mut ppu, &mut ram, &mut is);
+            run_frame(&mut ppu, &mut ram, &mut is);
+            ppu.framebuffer().to_vec()
+        };
+        let reference = frame(None);
+        assert!(reference.iter().any(|x| *x != reference[0]));
+        assert_eq!(frame(Some(0x00)), reference);
+        assert_eq!(frame(Some(0xFF)), reference);
+
+        // VBK can't select bank 1 either.
+        let mut ppu = PPU::new();
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        assert_eq!(ppu.bus_read8(VBK_ADDRESS), 0xFF);
+        ppu.bus_write8(TILEMAP_START_ADDRESS, 0x12);
+        assert_eq!(ppu.tilemaps[0], 0x12);
+        assert_eq!(ppu.tile_attributes[0], 0);
+    }
+
+    #[test]
+    fn test_cgb_tile_attributes(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.set_cgb_mode(true);
+        ppu.bg_window_enable = true;
+        ppu.bg_window_signed_addressing = false;
+        ppu.obj_enabled = true;
+        ppu.obj_double_sprites = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+        ppu.bus_write8(OBJ_PALETTE1_ADDRESS, 0b11_10_01_00);
+
+        // Tile 1 is color 1 on the left half, tile 2 is color 1 on the top row.
+        fill_tile(&mut ppu, 1, 0xF0, 0x00);
+        ppu.bus_write8(TILESET_START_ADDRESS + 2 * TILE_SIZE, 0xFF);
+        // Tile 3 is solid color 3, and is used by a sprite.
+        fill_tile(&mut ppu, 3, 0xFF, 0xFF);
+        // Tile 1 in bank 1 is solid color 2.
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        fill_tile(&mut ppu, 1, 0x00, 0xFF);
+        ppu.bus_write8(VBK_ADDRESS, 0);
+
+        let map = [1, 1, 1, 1, 2];
+        let attributes = [0x00, 0x20, 0x0D, 0x80, 0x40];
+        for (x, tile) in map.iter().enumerate() {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + x, *tile);
+        }
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        for (x, attribute) in attributes.iter().enumerate() {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + x, *attribute);
+        }
+        ppu.bus_write8(VBK_ADDRESS, 0);
+
+        // A sprite over the BG priority tile.
+        write_sprite(&mut ppu, 0, [16, 32, 3, 0]);
+
+        run_frame(&mut ppu, &mut ram, &mut is);
+        let frame = ppu.framebuffer();
+
+        // Unflipped, then x flipped.
+        assert_eq!(frame[0..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(frame[8..16], [0, 0, 0, 0, 1, 1, 1, 1]);
+        // Bank 1 tile with palette 5, kept apart from the shade.
+        assert_eq!(frame[16..24], [2;8]);
+        assert_eq!(ppu.framebuffer_palettes()[16..24], [5;8]);
+        assert_eq!(ppu.framebuffer_palettes()[0..16], [0;16]);
+        // BG colors 1-3 are drawn over the sprite, color 0 is not.
+        assert_eq!(frame[24..32], [1, 1, 1, 1, 3, 3, 3, 3]);
+        // Y flipped, the top row is drawn at the bottom.
+        assert_eq!(frame[32..40], [0;8]);
+        assert_eq!(frame[7 * 160 + 32..7 * 160 + 40], [1;8]);
+    }
+
+    #[test]
+    fn test_framebuffer_rgb(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bg_window_enable = true;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+
+        // Tile 1 is solid color 2, placed at tile (1, 0) of the map.
+        fill_tile(&mut ppu, 1, 0x00, 0xFF);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + 1, 1);
+        run_frame(&mut ppu, &mut ram, &mut is);
+
+        let rgb = ppu.framebuffer_rgb(DMG_GREEN_SHADES);
+        assert_eq!(rgb.len(), 160 * 144 * 3);
+        assert_eq!(rgb[0..3], DMG_GREEN_SHADES[0]);
+        assert_eq!(rgb[8 * 3..8 * 3 + 3], DMG_GREEN_SHADES[2]);
+
+        let grey = [[255, 255, 255], [170, 170, 170], [85, 85, 85], [0, 0, 0]];
+        let rgb = ppu.framebuffer_rgb(grey);
+        assert_eq!(rgb[(160 + 15) * 3..(160 + 15) * 3 + 3], [85, 85, 85]);
+        assert_eq!(rgb[(160 + 16) * 3..(160 + 16) * 3 + 3], [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_dump_tileset(){
+        let mut ppu = PPU::new();
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b00_01_10_11);
+
+        // Tile 17 is the second tile of the second row, and the last tile 
+        // ends the image.
+        fill_tile(&mut ppu, 17, 0xFF, 0x00);
+        fill_tile(&mut ppu, TILESET_COUNT - 1, 0xFF, 0xFF);
+        let dump = ppu.dump_tileset();
+        assert_eq!(dump.len(), 128 * 192);
+
+        // Color 0 shows as shade 3, and color 1 as shade 2.
+        assert_eq!(dump[0], 3);
+        for y in 8..16 {
+            assert_eq!(dump[y * 128 + 7], 3);
+            assert_eq!(dump[y * 128 + 8..y * 128 + 16], [2;8]);
+            assert_eq!(dump[y * 128 + 16], 3);
+        }
+        assert_eq!(dump[7 * 128 + 8], 3);
+        assert_eq!(dump[16 * 128 + 8], 3);
+        assert_eq!(dump[184 * 128 + 120..184 * 128 + 128], [0;8]);
+        assert_eq!(dump[191 * 128 + 127], 0);
+    }
+
+    #[test]
+    fn test_dump_background(){
+        let mut ppu = window_test_ppu();
+        // Colors 3, 2, 1 then 0 across every row.
+        fill_tile(&mut ppu, 1, 0b1010_0000, 0b1100_0000);
+        ppu.bus_write8(TILEMAP_B_START_ADDRESS + 2 * TILEMAP_DIMENSION + 3, 1);
+        ppu.bus_write8(SCX_ADDRESS, 0x55);
+
+        let dump = ppu.dump_background(1);
+        assert_eq!(dump.len(), 256 * 256);
+        for y in 16..24 {
+            assert_eq!(dump[y * 256 + 23..y * 256 + 33], [0, 3, 2, 1, 0, 0, 0, 0, 0, 0]);
+        }
+        assert_eq!(dump[15 * 256 + 24], 0);
+        assert_eq!(dump[24 * 256 + 24], 0);
+        assert!(ppu.dump_background(0).iter().all(|pixel| *pixel == 0));
+
+        // Signed addressing finds tile 1 at 0x9010 instead.
+        ppu.lcdc_write(ppu.lcdc & !PPU::LCDC_BG_WINDOW_TILE_MAP_SELECT_MASK);
+        assert_eq!(ppu.dump_background(1)[16 * 256 + 24], 0);
+    }
+
+    #[test]
+    fn test_framebuffer_grey(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bg_window_enable = true;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+
+        // Tile 1 is solid color 2, placed at tile (1, 0) of the map.
+        fill_tile(&mut ppu, 1, 0x00, 0xFF);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS + 1, 1);
+        run_frame(&mut ppu, &mut ram, &mut is);
+
+        let grey = ppu.framebuffer_grey();
+        assert_eq!(grey.len(), 160 * 144);
+        assert_eq!(grey[0], 0xFF);
+        assert_eq!(grey[8], 0x55);
+
+        // Custom shades apply to the frame already drawn, and survive reset.
+        ppu.set_shades([0x10, 0x20, 0x30, 0x40]);
+        let grey = ppu.framebuffer_grey();
+        assert_eq!(grey[0], 0x10);
+        assert_eq!(grey[160 + 15], 0x30);
+        assert_eq!(grey[160 + 16], 0x10);
+        ppu.reset(false);
+        assert_eq!(ppu.framebuffer_grey()[0], 0x10);
+    }
+
+    /// Writes the same lsb and msb byte to every row of a tile.
+    fn fill_tile(ppu: &mut PPU, index: usize, lsb: u8, msb: u8) {
+        for row in 0..TILE_DIMENSION {
+            ppu.bus_write8(TILESET_START_ADDRESS + index * TILE_SIZE + row * 2, lsb);
+            ppu.bus_write8(TILESET_START_ADDRESS + index * TILE_SIZE + row * 2 + 1, msb);
+        }
+    }
+
+    /// Gets a PPU configured to draw the background and window.
+    fn window_test_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.lcd_enabled = true;
+        ppu.bg_window_enable = true;
+        ppu.obj_enabled = false;
+        ppu.window_enabled = true;
+        ppu.window_tiles_high = true;
+        ppu.bg_tiles_high = false;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+        ppu
+    }
+
+    /// Draws line 0 with a write of SCX=96 after `split_ticks`, over a map
+    /// of color 0 tiles in the first 20 columns and color 1 tiles after.
+    fn scx_split_line(pixel_fifo: bool, split_ticks: u16) -> Vec<u8> {
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+        ppu.window_enabled = false;
+        ppu.set_pixel_fifo(pixel_fifo);
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        for col in 20..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + col, 1);
+        }
+
+        ppu.run(split_ticks, &mut ram, &mut is);
+        ppu.bus_write8(SCX_ADDRESS, 96);
+        ppu.run(PPU::LCD_TICKS_PER_LINE - split_ticks, &mut ram, &mut is);
+        ppu.frame_progress[..LCD_WIDTH].to_vec()
+    }
+
+    #[test]
+    fn test_pixel_fifo_mid_line_scroll(){
+        // Pixels start being shifted out 12 ticks into mode 3, so the write
+        // lands just before pixel 80.
+        let line = scx_split_line(true, 80 + 12 + 80);
+        assert!(line[..80].iter().all(|pixel| *pixel == 0));
+        assert!(line[80..].iter().all(|pixel| *pixel == 1));
+
+        // Without the FIFO the whole line is drawn with the final SCX, 
+        // reaching column 20 partway through the left half.
+        let line = scx_split_line(false, 80 + 12 + 80);
+        assert!(line[..64].iter().all(|pixel| *pixel == 0));
+        assert!(line[64..].iter().all(|pixel| *pixel == 1));
+    }
+
+    #[test]
+    fn test_pixel_fifo_matches_line_renderer(){
+        // With no mid-line writes both renderers draw the same frame.
+        let frames: Vec<Vec<u8>> = [false, true].iter().map(|pixel_fifo| {
+            let (_, mut ram, mut is) = test_pack();
+            let mut ppu = window_test_ppu();
+            ppu.set_pixel_fifo(*pixel_fifo);
+            fill_tile(&mut ppu, 1, 0xFF, 0x00);
+            fill_tile(&mut ppu, 2, 0x0F, 0xF0);
+            for index in 0..TILEMAP_ITEM_COUNT {
+                ppu.bus_write8(TILEMAP_A_START_ADDRESS + index, (index % 3) as u8);
+                ppu.bus_write8(TILEMAP_B_START_ADDRESS + index, 2 - (index % 3) as u8);
+            }
+            ppu.bus_write8(SCX_ADDRESS, 13);
+            ppu.bus_write8(SCY_ADDRESS, 7);
+            ppu.bus_write8(WY_ADDRESS, 50);
+            ppu.bus_write8(WX_ADDRESS, 60);
+            run_frame(&mut ppu, &mut ram, &mut is);
+            ppu.framebuffer().to_vec()
+        }).collect();
+        assert!(frames[0] == frames[1]);
+    }
+
+    #[test]
+    fn test_palette_change_between_lines(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+        ppu.window_enabled = false;
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        for index in 0..TILEMAP_ITEM_COUNT {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + index, 1);
+        }
+
+        // Color 1 maps to shade 1 on line 0, and shade 3 on line 1.
+        ppu.run(PPU::LCD_TICKS_PER_LINE, &mut ram, &mut is);
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b00_00_11_00);
+        ppu.run(PPU::LCD_TICKS_PER_LINE, &mut ram, &mut is);
+
+        let width = LCD_WIDTH;
+        assert!(ppu.frame_progress[..width].iter().all(|pixel| *pixel == 1));
+        assert!(ppu.frame_progress[width..width * 2].iter().all(|pixel| *pixel == 3));
+    }
+
+    #[test]
+    fn test_window_line_skips_hidden_lines(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+        ppu.bus_write8(WX_ADDRESS, 7);
+
+        for _ in 0..10 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.window_line(), 10);
+
+        // Hidden lines don't advance the counter.
+        ppu.window_enabled = false;
+        for _ in 0..10 {
+            ppu.run(456, &mut ram, &mut is);
+            assert_eq!(ppu.window_line(), 10);
+        }
+
+        // Drawing picks up from the row after the last drawn.
+        ppu.window_enabled = true;
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.window_line(), 11);
+
+        // The counter restarts with the next frame.
+        for _ in 21..154 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.line(), 0);
+        assert_eq!(ppu.window_line(), 0);
+    }
+
+    #[test]
+    fn test_window_starts_partway_down(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+
+        // Solid tiles of color 1, 2 and 3 for window tile rows 0, 1, and 2.
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        fill_tile(&mut ppu, 2, 0x00, 0xFF);
+        fill_tile(&mut ppu, 3, 0xFF, 0xFF);
+        for row in 0..3 {
+            for col in 0..TILEMAP_DIMENSION {
+                ppu.bus_write8(TILEMAP_B_START_ADDRESS + row * TILEMAP_DIMENSION + col, row as u8 + 1);
+            }
+        }
+        ppu.bus_write8(WY_ADDRESS, 20);
+        ppu.bus_write8(WX_ADDRESS, 7);
+
+        // Draw a frame, hiding the window between lines 30 and 40.
+        for line in 0..144 {
+            ppu.window_enabled = !(30..40).contains(&line);
+            ppu.run(456, &mut ram, &mut is);
+        }
+        let frame = ppu.framebuffer();
+
+        // Background above the window.
+        assert_eq!(frame[19 * 160], 0);
+        assert_eq!(frame[19 * 160 + 159], 0);
+        // First window tile row.
+        assert_eq!(frame[20 * 160], 1);
+        assert_eq!(frame[27 * 160 + 159], 1);
+        // Second window tile row.
+        assert_eq!(frame[28 * 160], 2);
+        assert_eq!(frame[29 * 160], 2);
+        // Window hidden.
+        assert_eq!(frame[30 * 160], 0);
+        assert_eq!(frame[39 * 160], 0);
+        // The window resumes where it left off, not at line 40 - WY.
+        assert_eq!(frame[40 * 160], 2);
+        assert_eq!(frame[45 * 160], 2);
+        assert_eq!(frame[46 * 160], 3);
+        // 124 lines from WY to VBlank, less the 10 hidden lines.
+        assert_eq!(ppu.window_line, 114);
+    }
+
+    #[test]
+    fn test_window_x_edges(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = window_test_ppu();
+
+        // Window tile with its left half color 1 and right half color 2.
+        fill_tile(&mut ppu, 1, 0xF0, 0x0F);
+        for col in 0..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_B_START_ADDRESS + col, 1);
+        }
+        ppu.bus_write8(WY_ADDRESS, 0);
+
+        // WX values under 7 shift the window left off of the screen.
+        ppu.bus_write8(WX_ADDRESS, 3);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0b10_10_10_10);
+        assert_eq!(ppu.line_buffer.pixeldata[1], 0b01_01_01_01);
+
+        // WX of 166 only shows the last pixel of the window.
+        ppu.window_line = 0;
+        ppu.bus_write8(WX_ADDRESS, 166);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_buffer.pixeldata[39], 0b01_00_00_00);
+        assert_eq!(ppu.window_line, 1);
+
+        // WX past 166 hides the window, and the window line does not advance.
+        ppu.bus_write8(WX_ADDRESS, 167);
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_buffer.pixeldata[39], 0);
+        assert_eq!(ppu.window_line, 1);
+    }
+
     #[test]
     fn test_sprite_draw_basecase(){
         let (mut ppu, mut ram, mut is) = test_pack();
@@ -1486,15 +4789,321 @@ mod test {
         assert_eq!(ppu.line_buffer.pixeldata[1], 0x28);
     }
 
+    /// Gets a PPU configured to draw only sprites.
+    fn sprite_test_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.lcd_enabled = true;
+        ppu.obj_enabled = true;
+        ppu.obj_double_sprites = false;
+        ppu.bg_window_enable = false;
+        ppu.bus_write8(OBJ_PALETTE1_ADDRESS, 0b11_10_01_00);
+        ppu.bus_write8(OBJ_PALETTE2_ADDRESS, 0b01_10_11_00);
+        ppu
+    }
+
+    /// Writes a sprite's 4 bytes of OAM data.
+    fn write_sprite(ppu: &mut PPU, index: usize, data: [u8;4]) {
+        for (i, x) in data.iter().enumerate() {
+            ppu.bus_write8(OAM_START_ADDRESS + index * OAM_SPRITE_SIZE + i, *x);
+        }
+    }
+
+    /// Runs the PPU for a full frame.
+    fn run_frame(ppu: &mut PPU, bus: &mut impl BusRW, is: &mut InterruptStatus) {
+        for _ in 0..154 {
+            ppu.run(456, bus, is);
+        }
+    }
+
+    #[test]
+    fn test_sprite_flip_and_palette(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+
+        // Tile 0 has color 3 in the top left corner, and color 1 in the bottom right.
+        ppu.bus_write8(TILESET_START_ADDRESS, 0x80);
+        ppu.bus_write8(TILESET_START_ADDRESS + 1, 0x80);
+        ppu.bus_write8(TILESET_START_ADDRESS + 14, 0x01);
+
+        // Sprite 0 is drawn as is at (8, 0).
+        write_sprite(&mut ppu, 0, [16, 16, 0, 0]);
+        // Sprite 1 is flipped both ways, using the second palette at (40, 0).
+        write_sprite(&mut ppu, 1, [16, 48, 0, 0b0111_0000]);
+
+        run_frame(&mut ppu, &mut ram, &mut is);
+        let frame = ppu.framebuffer();
+
+        // Unflipped sprite with the first palette
+        assert_eq!(frame[8], 3);
+        assert_eq!(frame[7 * 160 + 15], 1);
+        assert_eq!(frame[9], 0);
+        assert_eq!(frame[7 * 160 + 14], 0);
+
+        // Flipped sprite with the second palette
+        assert_eq!(frame[40], 3);
+        assert_eq!(frame[7 * 160 + 47], 1);
+        assert_eq!(frame[47], 0);
+        assert_eq!(frame[7 * 160 + 40], 0);
+    }
+
+    #[test]
+    fn test_framebuffer_sources(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        ppu.bg_window_enable = true;
+
+        // Tile 1 is solid color 1, which the palette maps to shade 0 so the
+        // sprite is indistinguishable from the background by color.
+        fill_tile(&mut ppu, 1, 0xFF, 0x00);
+        ppu.bus_write8(OBJ_PALETTE1_ADDRESS, 0b00_00_00_00);
+        write_sprite(&mut ppu, 0, [16, 16, 1, 0]);
+
+        // The window covers the bottom of the screen.
+        ppu.window_enabled = true;
+        ppu.window_y = 100;
+        ppu.window_x = 7;
+
+        run_frame(&mut ppu, &mut ram, &mut is);
+        let frame = ppu.framebuffer();
+        let sources = ppu.framebuffer_sources();
+
+        assert_eq!(frame[8], frame[7]);
+        assert_eq!(sources[8], PixelSource::Sprite);
+        assert_eq!(sources[7 * 160 + 15], PixelSource::Sprite);
+        assert_eq!(sources[7], PixelSource::Background);
+        assert_eq!(sources[16], PixelSource::Background);
+        assert_eq!(sources[8 * 160 + 8], PixelSource::Background);
+        assert_eq!(sources[100 * 160], PixelSource::Window);
+        assert_eq!(sources[143 * 160 + 159], PixelSource::Window);
+    }
+
+    #[test]
+    fn test_sprite_offscreen_culled(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+
+        // Tile 0 is solid color 3.
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // Sprites just off of each edge of the screen.
+        write_sprite(&mut ppu, 0, [16, 0, 0, 0]);
+        write_sprite(&mut ppu, 1, [16, 168, 0, 0]);
+        write_sprite(&mut ppu, 2, [8, 80, 0, 0]);
+        write_sprite(&mut ppu, 3, [160, 80, 0, 0]);
+        // A sprite hanging off of the left edge by half its width.
+        write_sprite(&mut ppu, 4, [40, 4, 0, 0]);
+
+        run_frame(&mut ppu, &mut ram, &mut is);
+        let frame = ppu.framebuffer();
+
+        // Only the partial sprite at lines 24 to 31, columns 0 to 3 is drawn.
+        for (i, pixel) in frame.iter().enumerate() {
+            let (x, y) = (i % 160, i / 160);
+            let expected = if x < 4 && (24..32).contains(&y) {3} else {0};
+            assert_eq!(*pixel, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_sprite_line_limit(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // 12 sprites on line 0, placed right to left so OAM order and X order differ.
+        for i in 0..12 {
+            write_sprite(&mut ppu, i, [16, 8 + 8 * (11 - i as u8), 0, 0]);
+        }
+
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_sprite_count, LINE_SPRITE_LIMIT);
+
+        // Sprites 10 and 11 occupy the 16 leftmost pixels, and are not drawn.
+        for x in 0..40 {
+            let expected = if (4..24).contains(&x) {0xFF} else {0};
+            assert_eq!(ppu.line_buffer.pixeldata[x], expected, "pixel block {}", x);
+        }
+    }
+
+    #[test]
+    fn test_sprite_x_priority(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // Sprite 0 at x 4 uses palette 2 (color 1), sprite 1 at x 0 uses palette 1 (color 3).
+        write_sprite(&mut ppu, 0, [16, 12, 0, 0b0001_0000]);
+        write_sprite(&mut ppu, 1, [16, 8, 0, 0]);
+
+        ppu.run(456, &mut ram, &mut is);
+
+        // The lower X sprite is on top where the two overlap.
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0b11_11_11_11);
+        assert_eq!(ppu.line_buffer.pixeldata[1], 0b11_11_11_11);
+        assert_eq!(ppu.line_buffer.pixeldata[2], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[3], 0);
+    }
+
+    #[test]
+    fn test_render_scanline_sprite_priority(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // Sprite 1 is later in OAM but has the lower X, so it is on top.
+        write_sprite(&mut ppu, 0, [16, 12, 0, 0b0001_0000]);
+        write_sprite(&mut ppu, 1, [16, 8, 0, 0]);
+        run_frame(&mut ppu, &mut ram, &mut is);
+
+        let line = ppu.render_scanline(0);
+        let expected: Vec<u8> = (0..160).map(|x| match x {
+            0..=7 => 3,
+            8..=11 => 1,
+            _ => 0,
+        }).collect();
+        assert_eq!(line[..], expected[..]);
+        assert_eq!(line[..], ppu.framebuffer()[..160]);
+    }
+
+    #[test]
+    fn test_sprite_x_priority_tie(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // Both sprites share a position, the lower OAM index is on top.
+        write_sprite(&mut ppu, 3, [16, 8, 0, 0b0001_0000]);
+        write_sprite(&mut ppu, 5, [16, 8, 0, 0]);
+
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_sprites[..ppu.line_sprite_count], [3, 5]);
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[1], 0b01_01_01_01);
+    }
+
+    #[test]
+    fn test_sprite_behind_background(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        ppu.bg_window_enable = true;
+        ppu.bg_window_signed_addressing = false;
+
+        // Background color 0 is shown as 3, so the decision must use the color index.
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_11);
+        // Tile 0 (background) is color 0 on the left half, and color 2 on the right.
+        fill_tile(&mut ppu, 0, 0x00, 0x0F);
+        // Tile 1 (sprite) is solid color 3, shown as 1 by palette 2.
+        fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0b1001_0000]);
+
+        ppu.run(456, &mut ram, &mut is);
+
+        // The sprite shows over color 0, and is hidden by color 2.
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0b01_01_01_01);
+        assert_eq!(ppu.line_buffer.pixeldata[1], 0b10_10_10_10);
+        // Beyond the sprite is plain background.
+        assert_eq!(ppu.line_buffer.pixeldata[2], 0b11_11_11_11);
+        assert_eq!(ppu.line_buffer.pixeldata[3], 0b10_10_10_10);
+    }
+
+    /// Draws line 0 with solid color 2 background tiles, the first with the
+    /// CGB BG priority attribute, and sprites of color 3 over the first two
+    /// tiles, the second sprite behind the background.
+    fn master_priority_line(cgb_mode: bool, bg_window_enable: bool) -> Vec<u8> {
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        ppu.set_cgb_mode(cgb_mode);
+        ppu.bg_window_enable = bg_window_enable;
+        ppu.bg_window_signed_addressing = false;
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0b11_10_01_00);
+        fill_tile(&mut ppu, 0, 0x00, 0xFF);
+        fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS, 0x80);
+        ppu.bus_write8(VBK_ADDRESS, 0);
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0]);
+        write_sprite(&mut ppu, 1, [16, 16, 1, 0b1000_0000]);
+
+        ppu.run(456, &mut ram, &mut is);
+        ppu.frame_progress[..24].to_vec()
+    }
+
+    #[test]
+    fn test_dmg_background_disable(){
+        // The background is blank, and sprites show even if behind it.
+        assert_eq!(master_priority_line(false, false), [[3;8], [3;8], [0;8]].concat());
+        // With it enabled the sprite priority flag applies. The CGB 
+        // attribute for the first tile is ignored on the DMG.
+        assert_eq!(master_priority_line(false, true), [[3;8], [2;8], [2;8]].concat());
+    }
+
+    #[test]
+    fn test_dmg_background_disable_frame(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+
+        // Solid color 3 tiles everywhere, and a palette that would darken 
+        // even color 0, with the window over the lower half.
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0xFF);
+        ppu.window_enabled = true;
+        ppu.window_y = 72;
+        ppu.window_x = 7;
+        write_sprite(&mut ppu, 0, [16, 8, 0, 0]);
+
+        run_frame(&mut ppu, &mut ram, &mut is);
+
+        // The background and window are white, with the sprite drawn over.
+        for (i, pixel) in ppu.framebuffer().iter().enumerate() {
+            let (x, y) = (i % 160, i / 160);
+            let expected = if x < 8 && y < 8 {3} else {0};
+            assert_eq!(*pixel, expected, "pixel ({}, {})", x, y);
+        }
+        assert_eq!(ppu.framebuffer_sources()[0], PixelSource::Sprite);
+        assert_eq!(ppu.framebuffer_sources()[100 * 160], PixelSource::Background);
+    }
+
+    #[test]
+    fn test_cgb_master_priority(){
+        // Without master priority the background is drawn, but sprites are
+        // always on top.
+        assert_eq!(master_priority_line(true, false), [[3;8], [3;8], [2;8]].concat());
+        // With it the tile and sprite priority flags apply.
+        assert_eq!(master_priority_line(true, true), [[2;8], [2;8], [2;8]].concat());
+    }
+
+    #[test]
+    fn test_sprites_selected_during_oam_search(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = sprite_test_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // Step into mode 3 of the first line, after the OAM search.
+        ppu.run(84, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+
+        // A sprite added now is not drawn on this line, but is on the next.
+        // OAM is locked to the CPU in mode 3, so write it directly.
+        for (i, x) in [15, 8, 0, 0].iter().enumerate() {
+            ppu.sprite_write(*x, OAM_START_ADDRESS + i);
+        }
+        ppu.run(372, &mut ram, &mut is);
+        assert_eq!(ppu.line_y, 1);
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0);
+
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.line_buffer.pixeldata[0], 0xFF);
+    }
+
     #[test]
     fn test_oam_yscan_8x16(){
         let (mut ppu, _ram, _is) = test_pack();
         ppu.obj_double_sprites = true;
         ppu.sprites[10].ypos = 1;
         ppu.sprites[12].ypos = 16;
-        let mut sprites_list = [0u8;10];
+        let mut sprites_list = [0u8;LINE_SPRITE_LIMIT];
 
-        let count = ppu.get_line_sprites(&mut sprites_list);
+        let count = ppu.get_line_sprites(ppu.line_y, &mut sprites_list);
 
         assert_eq!(count, 2);
         assert_eq!(sprites_list[0], 10);
@@ -1508,11 +5117,314 @@ mod test {
         ppu.sprites[10].ypos = 1;
         ppu.sprites[12].ypos = 8;
         ppu.sprites[13].ypos = 9;
-        let mut sprites_list = [0u8;10];
+        let mut sprites_list = [0u8;LINE_SPRITE_LIMIT];
 
-        let count = ppu.get_line_sprites(&mut sprites_list);
+        let count = ppu.get_line_sprites(ppu.line_y, &mut sprites_list);
 
         assert_eq!(count, 1);
         assert_eq!(sprites_list[0], 13);
     }
+
+    #[test]
+    fn test_oam(){
+        let mut ppu = PPU::new();
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0b1000_0000]);
+        write_sprite(&mut ppu, 7, [40, 30, 2, 0b0110_0000]);
+        write_sprite(&mut ppu, 39, [160, 168, 255, 0b0001_0000]);
+
+        let mut expected = [OamSprite::new(); OAM_SPRITE_COUNT];
+        expected[0] = OamSprite{ypos: 16, xpos: 8, tile: 1, behind_background: true, ..OamSprite::new()};
+        expected[7] = OamSprite{ypos: 40, xpos: 30, tile: 2, xflip: true, yflip: true, ..OamSprite::new()};
+        expected[39] = OamSprite{ypos: 160, xpos: 168, tile: 255, palette: true, ..OamSprite::new()};
+        assert_eq!(ppu.oam(), expected);
+    }
+
+    /// Gets a PPU with the OAM bug enabled, 8 ticks into the OAM search of
+    /// line 1, with each OAM byte holding its own offset.
+    fn oam_bug_ppu(ram: &mut Ram, is: &mut InterruptStatus) -> PPU {
+        let mut ppu = PPU::new();
+        for offset in 0..OAM_RAM_SIZE {
+            ppu.bus_write8(OAM_START_ADDRESS + offset, offset as u8);
+        }
+        ppu.set_oam_bug(true);
+        ppu.run(456 + 8, ram, is);
+        assert_eq!(ppu.mode, Mode::SpriteSearch);
+        ppu
+    }
+
+    #[test]
+    fn test_oam_bug_write(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = oam_bug_ppu(&mut ram, &mut is);
+        let before = ppu.sprite_data;
+
+        // Row 2 is being read, and is corrupted from row 1.
+        ppu.oam_bug_access(0xFE00, OamBugAccess::Write);
+        let (a, b, c) = (0x1110u16, 0x0908u16, 0x0D0Cu16);
+        let first = ((a ^ c) & (b ^ c)) ^ c;
+        assert_eq!(ppu.sprite_data[16..18], first.to_le_bytes());
+        assert_eq!(ppu.sprite_data[18..24], before[10..16]);
+        assert_eq!(ppu.sprite_data[..16], before[..16]);
+        assert_eq!(ppu.sprite_data[24..], before[24..]);
+        // The decoded sprites follow the raw data.
+        assert_eq!(ppu.oam()[5].ypos, 12);
+        assert_eq!(ppu.oam()[4].ypos, first as u8);
+    }
+
+    #[test]
+    fn test_oam_bug_read(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = oam_bug_ppu(&mut ram, &mut is);
+        let before = ppu.sprite_data;
+
+        ppu.oam_bug_access(0xFEFF, OamBugAccess::Read);
+        let (a, b, c) = (0x1110u16, 0x0908u16, 0x0D0Cu16);
+        assert_eq!(ppu.sprite_data[16..18], (b | (a & c)).to_le_bytes());
+        assert_eq!(ppu.sprite_data[18..24], before[10..16]);
+    }
+
+    #[test]
+    fn test_oam_bug_conditions(){
+        let (_, mut ram, mut is) = test_pack();
+
+        // Disabled by default.
+        let mut ppu = oam_bug_ppu(&mut ram, &mut is);
+        let before = ppu.sprite_data;
+        ppu.set_oam_bug(false);
+        ppu.oam_bug_access(0xFE00, OamBugAccess::Write);
+        assert_eq!(ppu.sprite_data, before);
+        assert!(!PPU::new().oam_bug);
+
+        // Only addresses in 0xFE00-0xFEFF trigger it.
+        ppu.set_oam_bug(true);
+        ppu.oam_bug_access(0xFDFF, OamBugAccess::Write);
+        ppu.oam_bug_access(0xFF00, OamBugAccess::Write);
+        assert_eq!(ppu.sprite_data, before);
+
+        // Only during the OAM search.
+        ppu.run(80, &mut ram, &mut is);
+        assert_eq!(ppu.mode, Mode::LcdTransfer);
+        ppu.oam_bug_access(0xFE00, OamBugAccess::Write);
+        assert_eq!(ppu.sprite_data, before);
+
+        // The first row is never corrupted.
+        ppu.run(456 - 88, &mut ram, &mut is);
+        assert_eq!(ppu.tick_counter, 0);
+        ppu.oam_bug_access(0xFE00, OamBugAccess::Write);
+        assert_eq!(ppu.sprite_data, before);
+    }
+
+    #[test]
+    fn test_sprites_on_line(){
+        let mut ppu = PPU::new();
+        ppu.obj_double_sprites = false;
+        // Sprites covering lines 0-7, 3-10, 9-16 and 11-18.
+        write_sprite(&mut ppu, 0, [16, 10, 0, 0]);
+        write_sprite(&mut ppu, 1, [19, 5, 1, 0]);
+        write_sprite(&mut ppu, 2, [25, 20, 2, 0]);
+        write_sprite(&mut ppu, 3, [27, 30, 3, 0]);
+
+        let tiles = |line| ppu.sprites_on_line(line).iter().map(|x| x.tile).collect::<Vec<u8>>();
+        assert_eq!(tiles(0), [0]);
+        assert_eq!(tiles(3), [0, 1]);
+        assert_eq!(tiles(8), [1]);
+        assert_eq!(tiles(10), [1, 2]);
+        assert!(tiles(19).is_empty());
+
+        // Tall sprites cover twice the lines.
+        ppu.obj_double_sprites = true;
+        assert_eq!(ppu.sprites_on_line(15).len(), 4);
+        assert_eq!(ppu.sprites_on_line(15)[1], OamSprite{
+            ypos: 19, xpos: 5, tile: 1, ..OamSprite::new()});
+
+        // Only the first 10 are selected.
+        for index in 0..OAM_SPRITE_COUNT {
+            write_sprite(&mut ppu, index, [100, index as u8, index as u8, 0]);
+        }
+        let tiles: Vec<u8> = ppu.sprites_on_line(90).iter().map(|x| x.tile).collect();
+        assert_eq!(tiles, (0..10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_poll_events(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(LCDC_ADDRESS, 0x00);
+        ppu.bus_write8(LCDC_ADDRESS, 0x80);
+        assert_eq!(ppu.poll_events(), PpuEvents::default());
+
+        // Mode 2 to 3, 3 to 0, then 0 to 2 on the next line.
+        ppu.run(456, &mut ram, &mut is);
+        assert_eq!(ppu.poll_events(), PpuEvents{mode_changes: 3, lines: 1, ..Default::default()});
+        assert_eq!(ppu.poll_events(), PpuEvents::default());
+
+        // The rest of the visible lines, then into VBlank.
+        for _ in 1..144 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.poll_events(), PpuEvents{mode_changes: 143 * 3, lines: 143, vblanks: 1, frames: 0});
+
+        // The frame completes at the end of VBlank, in one large step.
+        ppu.execute_ticks(456 * 10, &mut ram, &mut is);
+        assert_eq!(ppu.poll_events(), PpuEvents{mode_changes: 1, lines: 10, vblanks: 0, frames: 1});
+    }
+
+    #[test]
+    fn test_mode_and_line_accessors(){
+        let (mut ppu, mut ram, mut is) = test_pack();
+        ppu.bus_write8(LCDC_ADDRESS, 0x00);
+        ppu.bus_write8(LCDC_ADDRESS, 0x80);
+        assert_eq!(ppu.mode(), Mode::SpriteSearch);
+        assert_eq!(ppu.line(), 0);
+
+        ppu.run(80, &mut ram, &mut is);
+        assert_eq!(ppu.mode(), Mode::LcdTransfer);
+        ppu.run(456 - 80, &mut ram, &mut is);
+        assert_eq!(ppu.mode(), Mode::SpriteSearch);
+        assert_eq!(ppu.line(), 1);
+
+        for _ in 1..144 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert_eq!(ppu.line(), 144);
+
+        // LY reads 0 early on line 153, but the line doesn't.
+        for _ in 144..153 {
+            ppu.run(456, &mut ram, &mut is);
+        }
+        ppu.run(8, &mut ram, &mut is);
+        assert_eq!(ppu.line(), 153);
+        assert_eq!(ppu.bus_read8(LY_ADDRESS), 0);
+    }
+
+    /// Gets a PPU with a background, sprites and non-default registers that
+    /// is part way through drawing a frame.
+    fn mid_frame_ppu(ram: &mut Ram, is: &mut InterruptStatus) -> PPU {
+        let mut ppu = PPU::new();
+        fill_tile(&mut ppu, 1, 0xF0, 0x0F);
+        fill_tile(&mut ppu, 2, 0xAA, 0x55);
+        for x in 0..TILEMAP_DIMENSION {
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + x * 3, 1);
+            ppu.bus_write8(TILEMAP_A_START_ADDRESS + x * 5 + 1, 2);
+        }
+        write_sprite(&mut ppu, 0, [40, 30, 2, 0x30]);
+        write_sprite(&mut ppu, 5, [50, 60, 1, 0x80]);
+        ppu.bus_write8(VBK_ADDRESS, 1);
+        ppu.bus_write8(TILEMAP_A_START_ADDRESS, 0x23);
+        ppu.bus_write8(VBK_ADDRESS, 0);
+
+        ppu.bus_write8(LCDC_ADDRESS, 0x93);
+        ppu.bus_write8(LCDS_ADDRESS, 0x48);
+        ppu.bus_write8(SCX_ADDRESS, 3);
+        ppu.bus_write8(SCY_ADDRESS, 7);
+        ppu.bus_write8(LYC_ADDRES, 70);
+        ppu.bus_write8(WY_ADDRESS, 90);
+        ppu.bus_write8(WX_ADDRESS, 40);
+        ppu.bus_write8(BG_PALETTE_ADDRESS, 0xE4);
+        ppu.bus_write8(OBJ_PALETTE1_ADDRESS, 0x1B);
+        ppu.bus_write8(OBJ_PALETTE2_ADDRESS, 0x93);
+        ppu.bus_write8(BCPS_ADDRESS, 0x80);
+        for x in 0..8 {
+            ppu.bus_write8(BCPD_ADDRESS, x * 17);
+        }
+
+        // Stop part way through a line.
+        for _ in 0..60 {
+            ppu.run(456, ram, is);
+        }
+        ppu.run(100, ram, is);
+        ppu
+    }
+
+    #[test]
+    fn test_save_state_round_trip(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = mid_frame_ppu(&mut ram, &mut is);
+
+        let data = ppu.save_state();
+        let mut restored = PPU::new();
+        restored.load_state(&data).unwrap();
+
+        assert_eq!(restored.state(), ppu.state());
+        assert_eq!(restored.save_state(), data);
+        for index in 0..TILESET_COUNT {
+            assert_eq!(restored.tile_pixels(index), ppu.tile_pixels(index));
+        }
+        for index in 0..OAM_SPRITE_COUNT {
+            assert_eq!(restored.sprite(index), ppu.sprite(index));
+        }
+
+        let mut restored_is = InterruptStatus::new();
+        for _ in 0..2 {
+            run_frame(&mut ppu, &mut ram, &mut is);
+            run_frame(&mut restored, &mut ram, &mut restored_is);
+        }
+        assert_eq!(restored.framebuffer()[..], ppu.framebuffer()[..]);
+    }
+
+    #[test]
+    fn test_load_state_rejects_bad_data(){
+        let (_, mut ram, mut is) = test_pack();
+        let data = mid_frame_ppu(&mut ram, &mut is).save_state();
+        let mut ppu = PPU::new();
+        let before = ppu.state();
+
+        assert_eq!(ppu.load_state(&data[..data.len() - 1]), Err(StateError::Truncated));
+        assert_eq!(ppu.load_state(b"garbage garbage"), Err(StateError::BadMagic));
+        assert_eq!(ppu.load_state(&[]), Err(StateError::BadMagic));
+
+        let mut future = data.clone();
+        future[4] = 3;
+        assert_eq!(ppu.load_state(&future), Err(StateError::UnsupportedVersion(3)));
+
+        let mut extra = data.clone();
+        extra.push(0);
+        assert!(ppu.load_state(&extra).is_err());
+
+        // A failed load leaves the PPU alone.
+        assert_eq!(ppu.state(), before);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip(){
+        let (_, mut ram, mut is) = test_pack();
+        let mut ppu = mid_frame_ppu(&mut ram, &mut is);
+
+        let json = serde_json::to_string(&ppu).unwrap();
+        let mut restored: PPU = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.state(), ppu.state());
+        for addr in LCDC_ADDRESS..=WX_ADDRESS {
+            assert_eq!(restored.bus_read8(addr), ppu.bus_read8(addr), "register {:#X}", addr);
+        }
+        for index in 0..TILESET_COUNT {
+            assert_eq!(restored.tile_pixels(index), ppu.tile_pixels(index));
+            assert_eq!(restored.tiles_bank1[index].pixel, ppu.tiles_bank1[index].pixel);
+        }
+        for index in 0..OAM_SPRITE_COUNT {
+            assert_eq!(restored.sprite(index), ppu.sprite(index));
+        }
+
+        // Both carry on to draw the same frames.
+        let mut restored_is = InterruptStatus::new();
+        for _ in 0..2 {
+            run_frame(&mut ppu, &mut ram, &mut is);
+            run_frame(&mut restored, &mut ram, &mut restored_is);
+        }
+        assert_eq!(restored.framebuffer()[..], ppu.framebuffer()[..]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_bad_state(){
+        let ppu = PPU::new();
+        let mut state = serde_json::to_value(&ppu).unwrap();
+        state["mode"] = serde_json::json!(7);
+        assert!(serde_json::from_value::<PPU>(state).is_err());
+
+        let mut state = serde_json::to_value(&ppu).unwrap();
+        state["tile_data"] = serde_json::json!([0, 1, 2]);
+        assert!(serde_json::from_value::<PPU>(state).is_err());
+    }
 }
@@ -1,18 +1,70 @@
 use crate::bus::{BusRW};
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
+
+/// The current binary format version of the saved memory states.
+const STATE_VERSION:u16 = 1;
 
 // Plain old read write memory, no special actions.
 pub struct Ram {
 	storage: Vec<u8>,
-	start:	usize
+	start:	usize,
+	read_only: bool
 }
 
 impl Ram {
 	pub fn new(size:usize, start_address:usize) -> Ram {
 		Ram{
 			storage: vec![0u8; size],
-			start: start_address
+			start: start_address,
+			read_only: false
+		}
+	}
+
+	/// Creates a Ram sized to and holding a copy of `data`.
+	pub fn from_bytes(data:&[u8], start_address:usize) -> Ram {
+		Ram{
+			storage: data.to_vec(),
+			start: start_address,
+			read_only: false
 		}
 	}
+
+	/// Creates a read only Ram holding a copy of `data`, like a ROM.
+	pub fn read_only_from_bytes(data:&[u8], start_address:usize) -> Ram {
+		let mut ram = Ram::from_bytes(data, start_address);
+		ram.read_only = true;
+		ram
+	}
+
+	/// Sets whether bus writes are dropped. Loading with `load_at` still
+	/// works while read only.
+	pub fn set_read_only(&mut self, read_only:bool) {
+		self.read_only = read_only;
+	}
+
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
+	}
+
+	/// Sets every byte of the Ram to `value`, even while read only.
+	pub fn fill(&mut self, value:u8) {
+		for byte in self.storage.iter_mut() {
+			*byte = value;
+		}
+	}
+
+	/// Sets every byte of the Ram to 0.
+	pub fn clear(&mut self) {
+		self.fill(0);
+	}
+
+	/// Copies `data` into the Ram starting `offset` bytes from its start.
+	///
+	/// # Panics
+	/// If the data doesn't fit in the Ram.
+	pub fn load_at(&mut self, offset:usize, data:&[u8]) {
+		self.storage[offset..offset + data.len()].copy_from_slice(data);
+	}
 }
 
 impl BusRW for Ram {
@@ -21,8 +73,350 @@ impl BusRW for Ram {
 		self.storage[addr - self.start]
 	}
 
+	fn bus_peek8(&self, addr:usize) -> u8
+	{
+		self.storage[addr - self.start]
+	}
+
+	fn bus_write8(&mut self, addr:usize, value:u8)
+	{
+		if !self.read_only {
+			self.storage[addr - self.start] = value;
+		}
+	}
+
+	fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+		Some(self)
+	}
+}
+
+/// Reads a saved block of memory, checking it is the expected size.
+fn read_storage<'a>(data:&'a [u8], magic:&[u8; 4], len:usize) -> Result<(StateReader<'a>, &'a [u8]), StateError> {
+	let (mut r, version) = StateReader::new(data, magic)?;
+	if version != STATE_VERSION {
+		return Err(StateError::UnsupportedVersion(version));
+	}
+	let saved_len = r.read_u32()? as usize;
+	if saved_len != len {
+		return Err(StateError::Invalid(format!("{} bytes of memory saved, expected {}", saved_len, len)));
+	}
+	let storage = r.read_bytes(len)?;
+	Ok((r, storage))
+}
+
+/// Saves a block of memory, prefixed with its size.
+fn write_storage(magic:&[u8; 4], storage:&[u8]) -> StateWriter {
+	let mut w = StateWriter::new(magic, STATE_VERSION);
+	w.write_u32(storage.len() as u32);
+	w.write_bytes(storage);
+	w
+}
+
+impl Snapshotable for Ram {
+	/// Saves the contents. Whether the Ram is read only is a property of 
+	/// how it is used, so it isn't saved.
+	fn save_state(&self) -> Vec<u8> {
+		write_storage(b"DRAM", &self.storage).finish()
+	}
+
+	fn load_state(&mut self, data:&[u8]) -> Result<(), StateError> {
+		let (r, storage) = read_storage(data, b"DRAM", self.storage.len())?;
+		r.finish()?;
+		self.storage.copy_from_slice(storage);
+		Ok(())
+	}
+}
+
+/// Work RAM at 0xC000-0xDFFF.
+///
+/// On CGB the upper 4KB is a switchable bank selected through SVBK (0xFF70),
+/// giving 32KB in total. On DMG it is a flat 8KB and SVBK does nothing.
+pub struct WorkRam {
+	storage: Vec<u8>,
+	bank: usize,
+	cgb: bool,
+}
+
+impl WorkRam {
+	pub const START_ADDRESS:usize = 0xC000;
+	pub const BANKED_ADDRESS:usize = 0xD000;
+	pub const END_ADDRESS:usize = 0xDFFF;
+	pub const SVBK_ADDRESS:usize = 0xFF70;
+	const BANK_SIZE:usize = 0x1000;
+	const SVBK_MASK:u8 = 0b111;
+
+	pub fn new(cgb:bool) -> WorkRam {
+		let banks = if cgb { 8 } else { 2 };
+		WorkRam{
+			storage: vec![0u8; banks * WorkRam::BANK_SIZE],
+			bank: 1,
+			cgb
+		}
+	}
+
+	/// The bank currently mapped at 0xD000-0xDFFF.
+	pub fn bank(&self) -> usize {
+		self.bank
+	}
+
+	fn offset(&self, addr:usize) -> usize {
+		if addr < WorkRam::BANKED_ADDRESS {
+			addr - WorkRam::START_ADDRESS
+		} else {
+			self.bank * WorkRam::BANK_SIZE + (addr - WorkRam::BANKED_ADDRESS)
+		}
+	}
+}
+
+impl BusRW for WorkRam {
+	fn bus_read8(&mut self, addr:usize) -> u8
+	{
+		match addr {
+			WorkRam::START_ADDRESS..=WorkRam::END_ADDRESS
+			| WorkRam::SVBK_ADDRESS => self.bus_peek8(addr),
+			_ => panic!("WorkRam: Unknown read at address {:#X}", addr)
+		}
+	}
+
+	fn bus_peek8(&self, addr:usize) -> u8
+	{
+		match addr {
+			WorkRam::START_ADDRESS..=WorkRam::END_ADDRESS => self.storage[self.offset(addr)],
+			WorkRam::SVBK_ADDRESS if self.cgb => !WorkRam::SVBK_MASK | self.bank as u8,
+			_ => 0xFF
+		}
+	}
+
+	fn bus_write8(&mut self, addr:usize, value:u8)
+	{
+		match addr {
+			WorkRam::START_ADDRESS..=WorkRam::END_ADDRESS => {
+				let offset = self.offset(addr);
+				self.storage[offset] = value;
+			}
+			WorkRam::SVBK_ADDRESS => {
+				if self.cgb {
+					// Bank 0 can't be selected, it maps to bank 1.
+					self.bank = ((value & WorkRam::SVBK_MASK) as usize).max(1);
+				}
+			}
+			_ => panic!("WorkRam: Unknown write at address {:#X}", addr)
+		}
+	}
+
+	fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+		Some(self)
+	}
+}
+
+impl Snapshotable for WorkRam {
+	fn save_state(&self) -> Vec<u8> {
+		let mut w = write_storage(b"DWRM", &self.storage);
+		w.write_u8(self.bank as u8);
+		w.finish()
+	}
+
+	fn load_state(&mut self, data:&[u8]) -> Result<(), StateError> {
+		let (mut r, storage) = read_storage(data, b"DWRM", self.storage.len())?;
+		let bank = r.read_u8()? as usize;
+		r.finish()?;
+		if bank == 0 || bank * WorkRam::BANK_SIZE >= self.storage.len() {
+			return Err(StateError::Invalid(format!("work RAM bank {} doesn't exist", bank)));
+		}
+		self.storage.copy_from_slice(storage);
+		self.bank = bank;
+		Ok(())
+	}
+}
+
+/// High RAM at 0xFF80-0xFFFE.
+///
+/// This is the only memory the CPU can reach while an OAM DMA transfer runs,
+/// so DMA routines are copied here and run from it. Its range is the one
+/// `PPU::dma_blocks_address` leaves unblocked.
+pub struct HighRam {
+	storage: [u8; HighRam::SIZE],
+}
+
+impl HighRam {
+	pub const START_ADDRESS:usize = 0xFF80;
+	pub const END_ADDRESS:usize = 0xFFFE;
+	pub const SIZE:usize = HighRam::END_ADDRESS - HighRam::START_ADDRESS + 1;
+
+	pub fn new() -> HighRam {
+		HighRam{
+			storage: [0u8; HighRam::SIZE],
+		}
+	}
+}
+
+impl Default for HighRam {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl BusRW for HighRam {
+	fn bus_read8(&mut self, addr:usize) -> u8
+	{
+		self.storage[addr - HighRam::START_ADDRESS]
+	}
+
+	fn bus_peek8(&self, addr:usize) -> u8
+	{
+		self.storage[addr - HighRam::START_ADDRESS]
+	}
+
 	fn bus_write8(&mut self, addr:usize, value:u8)
 	{
-		self.storage[addr - self.start] = value;
+		self.storage[addr - HighRam::START_ADDRESS] = value;
+	}
+
+	fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+		Some(self)
+	}
+}
+
+impl Snapshotable for HighRam {
+	fn save_state(&self) -> Vec<u8> {
+		write_storage(b"DHRM", &self.storage).finish()
+	}
+
+	fn load_state(&mut self, data:&[u8]) -> Result<(), StateError> {
+		let (r, storage) = read_storage(data, b"DHRM", HighRam::SIZE)?;
+		r.finish()?;
+		self.storage.copy_from_slice(storage);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_ram_from_bytes() {
+		let mut ram = Ram::from_bytes(&[0x10, 0x20, 0x30], 0xFF80);
+		assert_eq!(ram.bus_read8(0xFF80), 0x10);
+		assert_eq!(ram.bus_read8(0xFF81), 0x20);
+		assert_eq!(ram.bus_read8(0xFF82), 0x30);
+
+		ram.bus_write8(0xFF81, 0x55);
+		assert_eq!(ram.bus_read8(0xFF81), 0x55);
+	}
+
+	#[test]
+	fn test_ram_load_at() {
+		let mut ram = Ram::new(0x10, 0xC000);
+		ram.load_at(4, &[1, 2, 3]);
+		assert_eq!(ram.bus_read8(0xC003), 0);
+		assert_eq!(ram.bus_read8(0xC004), 1);
+		assert_eq!(ram.bus_read8(0xC005), 2);
+		assert_eq!(ram.bus_read8(0xC006), 3);
+		assert_eq!(ram.bus_read8(0xC007), 0);
+	}
+
+	#[test]
+	fn test_ram_read_only() {
+		let mut rom = Ram::read_only_from_bytes(&[0xAA, 0xBB], 0x0000);
+		assert!(rom.is_read_only());
+		rom.bus_write8(0x0000, 0x11);
+		assert_eq!(rom.bus_read8(0x0000), 0xAA);
+		assert_eq!(rom.bus_read8(0x0001), 0xBB);
+
+		rom.set_read_only(false);
+		rom.bus_write8(0x0000, 0x11);
+		assert_eq!(rom.bus_read8(0x0000), 0x11);
+
+		let mut ram = Ram::new(4, 0xC000);
+		ram.set_read_only(true);
+		ram.bus_write8(0xC000, 0x22);
+		assert_eq!(ram.bus_read8(0xC000), 0);
+	}
+
+	#[test]
+	fn test_ram_fill_and_clear() {
+		let mut ram = Ram::new(0x80, 0xFF80);
+		for addr in 0xFF80..0x10000 {
+			ram.bus_write8(addr, addr as u8);
+		}
+
+		ram.fill(0x5A);
+		for addr in 0xFF80..0x10000 {
+			assert_eq!(ram.bus_read8(addr), 0x5A);
+		}
+
+		ram.clear();
+		for addr in 0xFF80..0x10000 {
+			assert_eq!(ram.bus_read8(addr), 0);
+		}
+	}
+
+	#[test]
+	#[should_panic]
+	fn test_ram_load_at_out_of_range() {
+		let mut ram = Ram::new(0x10, 0xC000);
+		ram.load_at(0xE, &[1, 2, 3]);
+	}
+
+	#[test]
+	fn test_work_ram_dmg_is_flat() {
+		let mut wram = WorkRam::new(false);
+		wram.bus_write8(0xC000, 0x11);
+		wram.bus_write8(0xD000, 0x22);
+
+		// SVBK does nothing on DMG.
+		wram.bus_write8(WorkRam::SVBK_ADDRESS, 2);
+		assert_eq!(wram.bus_read8(WorkRam::SVBK_ADDRESS), 0xFF);
+		assert_eq!(wram.bus_read8(0xC000), 0x11);
+		assert_eq!(wram.bus_read8(0xD000), 0x22);
+	}
+
+	#[test]
+	fn test_work_ram_cgb_bank_switching() {
+		let mut wram = WorkRam::new(true);
+		wram.bus_write8(0xC010, 0xAA);
+		for bank in 1..8u8 {
+			wram.bus_write8(WorkRam::SVBK_ADDRESS, bank);
+			wram.bus_write8(0xD010, bank * 0x10);
+		}
+
+		for bank in 1..8u8 {
+			wram.bus_write8(WorkRam::SVBK_ADDRESS, bank);
+			assert_eq!(wram.bank(), bank as usize);
+			assert_eq!(wram.bus_read8(WorkRam::SVBK_ADDRESS), 0xF8 | bank);
+			assert_eq!(wram.bus_read8(0xD010), bank * 0x10);
+			// The fixed bank doesn't change.
+			assert_eq!(wram.bus_read8(0xC010), 0xAA);
+		}
+	}
+
+	#[test]
+	fn test_work_ram_bank_zero_selects_one() {
+		let mut wram = WorkRam::new(true);
+		wram.bus_write8(WorkRam::SVBK_ADDRESS, 1);
+		wram.bus_write8(0xDFFF, 0x5A);
+
+		wram.bus_write8(WorkRam::SVBK_ADDRESS, 3);
+		assert_eq!(wram.bus_read8(0xDFFF), 0);
+
+		wram.bus_write8(WorkRam::SVBK_ADDRESS, 0);
+		assert_eq!(wram.bank(), 1);
+		assert_eq!(wram.bus_read8(0xDFFF), 0x5A);
+
+		// Bank 0 is the fixed bank, it never shows up in the switchable area.
+		wram.bus_write8(0xD000, 0x77);
+		assert_eq!(wram.bus_read8(0xC000), 0);
+	}
+
+	#[test]
+	fn test_high_ram() {
+		let mut hram = HighRam::new();
+		assert_eq!(HighRam::SIZE, 127);
+		hram.bus_write8(HighRam::START_ADDRESS, 0x12);
+		hram.bus_write8(HighRam::END_ADDRESS, 0x34);
+		assert_eq!(hram.bus_read8(0xFF80), 0x12);
+		assert_eq!(hram.bus_read8(0xFFFE), 0x34);
+		assert_eq!(hram.bus_peek8(0xFFFE), 0x34);
 	}
 }
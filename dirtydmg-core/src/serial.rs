@@ -1,15 +1,23 @@
+use std::rc::Rc;
+use std::cell::RefCell;
 use crate::bus::BusRW;
 use crate::interrupt::InterruptStatus;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 
 const SB_REG_ADDR:usize = 0xFF01;
 const SC_REG_ADDR:usize = 0xFF02;
 
+/// Called with each byte shifted out of the serial port.
+pub type SerialCallback = Box<dyn FnMut(u8)>;
+
 pub struct SerialUnit{
     sb: u8,
     sc: u8,
     input: u8,
     output: Option<u8>,
-    cycle_count: u32
+    output_hook: Option<SerialCallback>,
+    cycle_count: u32,
+    interrupts: Option<Rc<RefCell<InterruptStatus>>>,
 }
 
 impl SerialUnit {
@@ -18,7 +26,12 @@ impl SerialUnit {
 
     const SC_ACTIVE_MASK:u8 = 0b1000_0000;
     const SC_SPEED_MASK:u8 = 0b0000_0010;
-    const SC_MASTER_MASK:u8 = 0b0000_0010;
+    const SC_MASTER_MASK:u8 = 0b0000_0001;
+
+    /// Magic bytes at the start of a saved serial state.
+    const STATE_MAGIC: &'static [u8;4] = b"DSER";
+    /// The current binary format version.
+    const STATE_VERSION: u16 = 1;
 
     fn is_transfer_active(&self) -> bool {
         (self.sc & SerialUnit::SC_ACTIVE_MASK) != 0
@@ -45,9 +58,24 @@ impl SerialUnit {
         ret
     }
 
-    pub fn update(&mut self, cycles: u32, is:&mut InterruptStatus) {
+    /// Sets a hook that is called with every byte transmitted over serial.
+    pub fn set_output_hook(&mut self, hook: Option<SerialCallback>) {
+        self.output_hook = hook;
+    }
+
+    /// Sets the interrupt status the serial port requests its interrupt from
+    /// when it is ticked through the bus.
+    pub fn set_interrupts(&mut self, interrupts: Option<Rc<RefCell<InterruptStatus>>>) {
+        self.interrupts = interrupts;
+    }
+
+    /// Advances the serial port by `cycles` m-cycles.
+    ///
+    /// Only transfers using the internal clock make progress; with an
+    /// external clock the transfer waits on a link partner that never comes.
+    pub fn execute_ticks(&mut self, cycles: u32, is:&mut InterruptStatus) {
         // If transfer is active
-        if self.is_transfer_active() {
+        if self.is_transfer_active() && self.is_master() {
             // Add ticks to cycle_count.
             self.cycle_count += cycles;
             // If enough ticks have elapsed
@@ -55,8 +83,11 @@ impl SerialUnit {
                 // Trigger a serial interrupt.
                 is.request_serial();
                 self.output = Some(self.sb);
-                // Clear the sb byte
-                self.sb = 0;
+                if let Some(hook) = self.output_hook.as_mut() {
+                    hook(self.sb);
+                }
+                // Shift in the byte from the (disconnected) link partner.
+                self.sb = self.input;
                 // Clear the transfer active flag
                 self.sc ^= SerialUnit::SC_ACTIVE_MASK;
                 // Clear the cycle count
@@ -69,9 +100,11 @@ impl SerialUnit {
         SerialUnit{
             sb: 0,
             sc: 0,
-            input: 0,
+            input: 0xFF,
             output: None,
+            output_hook: None,
             cycle_count: 0,
+            interrupts: None,
         }
     }
 }
@@ -93,17 +126,71 @@ impl BusRW for SerialUnit {
 
     fn bus_read8(&mut self, addr:usize) -> u8 {
         match addr {
-            SB_REG_ADDR => {
-                self.sb
-            }
-            SC_REG_ADDR => {
-                self.sc
+            SB_REG_ADDR | SC_REG_ADDR => {
+                self.bus_peek8(addr)
             }
             _ => {
                 panic!("SerialUnit unexpected read at address: {}", addr);
             }
         }
     }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
+    /// Same as execute_ticks, using the interrupt status given to
+    /// set_interrupts. The serial port counts m-cycles, so `ticks` should be
+    /// whole m-cycles of 4 ticks. Without an interrupt status transfers still
+    /// complete, but their interrupt requests are dropped.
+    fn tick(&mut self, ticks: u32) {
+        match self.interrupts.clone() {
+            Some(interrupts) => self.execute_ticks(ticks / 4, &mut interrupts.borrow_mut()),
+            None => self.execute_ticks(ticks / 4, &mut InterruptStatus::new()),
+        }
+    }
+
+    fn bus_peek8(&self, addr:usize) -> u8 {
+        match addr {
+            SB_REG_ADDR => self.sb,
+            SC_REG_ADDR => self.sc,
+            _ => 0xFF,
+        }
+    }
+}
+
+/// The output hook belongs to the host and is kept across a restore.
+impl Snapshotable for SerialUnit {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(SerialUnit::STATE_MAGIC, SerialUnit::STATE_VERSION);
+        w.write_u8(self.sb);
+        w.write_u8(self.sc);
+        w.write_u8(self.input);
+        w.write_bool(self.output.is_some());
+        w.write_u8(self.output.unwrap_or(0));
+        w.write_u32(self.cycle_count);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, SerialUnit::STATE_MAGIC)?;
+        if version != SerialUnit::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let sb = r.read_u8()?;
+        let sc = r.read_u8()?;
+        let input = r.read_u8()?;
+        let has_output = r.read_bool()?;
+        let output = r.read_u8()?;
+        let cycle_count = r.read_u32()?;
+        r.finish()?;
+        self.sb = sb;
+        self.sc = sc;
+        self.input = input;
+        self.output = if has_output {Some(output)} else {None};
+        self.cycle_count = cycle_count;
+        Ok(())
+    }
 }
 
 impl Default for SerialUnit {
@@ -115,6 +202,8 @@ impl Default for SerialUnit {
 #[cfg(test)]
 mod test{
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn get_test_pack() -> (SerialUnit, InterruptStatus){
         let unit = SerialUnit::new();
@@ -134,11 +223,11 @@ mod test{
         send_data(&mut stu, 0xA5);
 
         // No isr yet
-        stu.update(SerialUnit::CYCLES_PER_OPERATION-1, &mut is);
+        stu.execute_ticks(SerialUnit::CYCLES_PER_OPERATION-1, &mut is);
         assert_eq!(is.is_serial_active(), false);
 
         // Now isr should trigger
-        stu.update(1, &mut is);
+        stu.execute_ticks(1, &mut is);
         assert_eq!(is.is_serial_active(), true);
     }
 
@@ -148,10 +237,58 @@ mod test{
         send_data(&mut stu, 0xA5);
 
         assert_eq!(stu.get_output(), None);
-        stu.update(SerialUnit::CYCLES_PER_OPERATION, &mut is);
+        stu.execute_ticks(SerialUnit::CYCLES_PER_OPERATION, &mut is);
 
         assert_eq!(stu.get_output(), Some(0xA5));
         assert_eq!(stu.get_output(), None);
     }
 
+    #[test]
+    fn transfer_with_internal_clock(){
+        let (mut stu, mut is) = get_test_pack();
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let hook_sent = sent.clone();
+        stu.set_output_hook(Some(Box::new(move |b| hook_sent.borrow_mut().push(b))));
+
+        send_data(&mut stu, b'P');
+        for _ in 0..8 {
+            assert!(sent.borrow().is_empty());
+            stu.execute_ticks(SerialUnit::CYCLES_PER_TICK, &mut is);
+        }
+
+        assert_eq!(*sent.borrow(), vec![b'P']);
+        assert!(is.is_serial_active());
+        // The transfer completes and nothing was shifted in from a partner.
+        assert_eq!(stu.bus_read8(0xFF02) & 0x80, 0);
+        assert_eq!(stu.bus_read8(0xFF01), 0xFF);
+    }
+
+    #[test]
+    fn no_transfer_with_external_clock(){
+        let (mut stu, mut is) = get_test_pack();
+        stu.bus_write8(0xFF01, 0x42);
+        stu.bus_write8(0xFF02, 0x80);
+
+        stu.execute_ticks(SerialUnit::CYCLES_PER_OPERATION * 2, &mut is);
+        assert!(!is.is_serial_active());
+        assert_eq!(stu.get_output(), None);
+        assert_eq!(stu.bus_read8(0xFF01), 0x42);
+    }
+
+    #[test]
+    fn tick_requests_through_handle(){
+        let interrupts = Rc::new(RefCell::new(InterruptStatus::new()));
+        interrupts.borrow_mut().isrmask = 0xFF;
+        let mut stu = SerialUnit::new();
+        stu.set_interrupts(Some(interrupts.clone()));
+        send_data(&mut stu, b'T');
+
+        // Ticks are clock ticks, four to each m-cycle.
+        stu.tick(SerialUnit::CYCLES_PER_OPERATION * 4 - 4);
+        assert!(!interrupts.borrow().is_serial_active());
+        stu.tick(4);
+        assert!(interrupts.borrow().is_serial_active());
+        assert_eq!(stu.get_output(), Some(b'T'));
+    }
+
 }
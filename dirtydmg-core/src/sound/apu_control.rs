@@ -42,8 +42,8 @@ impl ApuControl {
         // Bit 2 - Output sound 3 to SO1 terminal
         // Bit 1 - Output sound 2 to SO1 terminal
         // Bit 0 - Output sound 1 to SO1 terminal
-        let mut shift = output as u8;         
-        shift += channel as u8 * 4;
+        let mut shift = channel as u8;
+        shift += output as u8 * 4;
         // We know what bit we want, sample it.
         (self.nr51 & (1 << shift)) != 0
     }
@@ -74,4 +74,31 @@ impl Default for ApuControl {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channel_output_bits() {
+        let mut ctrl = ApuControl::new();
+
+        // Sound 3 to SO1 is bit 2.
+        ctrl.nr51 = 0x04;
+        assert!(ctrl.is_channel_on_output(AudioOutput::Output1, AudioChannel::Channel3));
+        assert!(!ctrl.is_channel_on_output(AudioOutput::Output2, AudioChannel::Channel3));
+        assert!(!ctrl.is_channel_on_output(AudioOutput::Output1, AudioChannel::Channel1));
+
+        // Sound 2 to SO2 is bit 5.
+        ctrl.nr51 = 0x20;
+        assert!(ctrl.is_channel_on_output(AudioOutput::Output2, AudioChannel::Channel2));
+        assert!(!ctrl.is_channel_on_output(AudioOutput::Output1, AudioChannel::Channel2));
+        assert!(!ctrl.is_channel_on_output(AudioOutput::Output2, AudioChannel::Channel3));
+
+        // Sound 4 to both terminals.
+        ctrl.nr51 = 0x88;
+        assert!(ctrl.is_channel_on_output(AudioOutput::Output1, AudioChannel::Channel4));
+        assert!(ctrl.is_channel_on_output(AudioOutput::Output2, AudioChannel::Channel4));
+    }
+}
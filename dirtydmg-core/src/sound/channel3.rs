@@ -190,6 +190,10 @@ impl BusRW for Channel3{
     }
 
     fn bus_read8(&mut self, addr: usize) -> u8{
+        self.bus_peek8(addr)
+    }
+
+    fn bus_peek8(&self, addr: usize) -> u8{
         match addr {
             // Sample ram
             0xFF30..=0xFF3F => {
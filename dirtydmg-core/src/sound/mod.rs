@@ -351,6 +351,10 @@ impl BusRW for Apu {
     }
 
     fn bus_read8(&mut self, addr:usize) -> u8{
+        self.bus_peek8(addr)
+    }
+
+    fn bus_peek8(&self, addr:usize) -> u8{
         match addr {
             // Channel 1
             Apu::NR10_ADDRESS => {
@@ -424,7 +428,7 @@ impl BusRW for Apu {
             }
 
             0xFF30..=0xFF3F => {
-                self.ch3.bus_read8(addr)
+                self.ch3.bus_peek8(addr)
             }
 
             // TODO - turn this into a panic
@@ -0,0 +1,178 @@
+/// Errors from restoring a saved state.
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    /// The data doesn't start with the expected magic bytes.
+    BadMagic,
+    /// The data was saved with a format version this build can't read.
+    UnsupportedVersion(u16),
+    /// The data ended before the state was complete.
+    Truncated,
+    /// The data was complete but held an impossible value.
+    Invalid(String),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "state data has an unknown header"),
+            StateError::UnsupportedVersion(version) => write!(f,
+                "state data version {} is not supported", version),
+            StateError::Truncated => write!(f, "state data is truncated"),
+            StateError::Invalid(reason) => write!(f, "state data is invalid: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A device whose state can be saved and restored.
+///
+/// Bus members expose this through `BusRW::snapshot`, so a Bus can save 
+/// every device on it together.
+pub trait Snapshotable {
+    /// Saves the device state to a versioned binary blob.
+    fn save_state(&self) -> Vec<u8>;
+
+    /// Restores a state saved by `save_state`.
+    ///
+    /// The device is left untouched if the data can't be restored.
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError>;
+}
+
+/// Builds a versioned state blob.
+///
+/// Values are written little endian, with no padding or field tags, so the
+/// reader must read them back in the same order.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Starts a blob with a magic tag and format version.
+    pub fn new(magic: &[u8; 4], version: u16) -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.write_bytes(magic);
+        writer.write_u16(version);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Finishes the blob.
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back a blob built by a StateWriter.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the magic tag and returns a reader positioned after the header,
+    /// along with the format version.
+    pub fn new(data: &'a [u8], magic: &[u8; 4]) -> Result<(StateReader<'a>, u16), StateError> {
+        let mut reader = StateReader { data };
+        if reader.read_bytes(magic.len()).map_err(|_| StateError::BadMagic)? != magic {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u16()?;
+        Ok((reader, version))
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(StateError::Invalid(format!("{} is not a bool", value))),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Checks that the whole blob was consumed.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid(format!("{} unexpected trailing bytes", self.data.len())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = StateWriter::new(b"TEST", 3);
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0xBEEF);
+        writer.write_u32(0x1234_5678);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.finish();
+
+        let (mut reader, version) = StateReader::new(&data, b"TEST").unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0xBEEF));
+        assert_eq!(reader.read_u32(), Ok(0x1234_5678));
+        assert_eq!(reader.read_bytes(3), Ok(&[1u8, 2, 3][..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_data() {
+        assert_eq!(StateReader::new(b"TE", b"TEST").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"NOPE\x01\x00", b"TEST").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"TEST\x01", b"TEST").err(), Some(StateError::Truncated));
+
+        let (mut reader, _) = StateReader::new(b"TEST\x01\x00\x05\x09", b"TEST").unwrap();
+        assert_eq!(reader.read_u8(), Ok(5));
+        assert!(reader.read_bool().is_err());
+        assert_eq!(reader.read_u16(), Err(StateError::Truncated));
+    }
+}
@@ -0,0 +1,30 @@
+//! The whole system behind one type.
+//!
+//! `Dmg` owns the bus and every device mapped on it, and `Dmg::step` 
+//! advances the CPU, PPU, timer, serial unit and APU in lockstep, so it is 
+//! already the integration point. `GameBoy` is the same type under the name
+//! most users look for, rather than a second copy of the wiring.
+pub use crate::dmg::Dmg as GameBoy;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::FRAMEBUFFER_SIZE;
+
+    #[test]
+    fn test_game_boy_runs_a_frame() {
+        let mut gb = GameBoy::new();
+        // A ROM only cartrige that loops forever at 0x100, jr -2.
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        gb.load_rom(&rom).unwrap();
+
+        assert_eq!(gb.step(), 12);
+        assert_eq!(gb.cpu.reg.pc, 0x100);
+
+        let frame = gb.run_frame().to_vec();
+        assert_eq!(frame.len(), FRAMEBUFFER_SIZE);
+        assert_eq!(&frame[..], gb.ppu.borrow().framebuffer());
+    }
+}
@@ -1,5 +1,8 @@
+use std::rc::Rc;
+use std::cell::RefCell;
 use crate::bus::BusRW;
 use crate::interrupt::InterruptStatus;
+use crate::state::{Snapshotable, StateError, StateReader, StateWriter};
 
 const DIV_REG_ADDR:usize  = 0xFF04;
 const TIMA_REG_ADDR:usize = 0xFF05;
@@ -20,10 +23,21 @@ pub struct TimerUnit {
     // Timing functions.
     tima_inc_counter: u16,
     div_inc_counter: u16,
+
+    // Set when TIMA overflows outside of execute_ticks (e.g. on a DIV write),
+    // so the interrupt is requested on the next update.
+    overflow_pending: bool,
+
+    // Where overflows are requested when ticked through the bus.
+    interrupts: Option<Rc<RefCell<InterruptStatus>>>,
 }
 
 impl TimerUnit {
     const TICKS_PER_DIV:u16 = 256;
+    /// Magic bytes at the start of a saved timer state.
+    const STATE_MAGIC: &'static [u8;4] = b"DTMR";
+    /// The current binary format version.
+    const STATE_VERSION: u16 = 1;
 
     pub fn new() -> TimerUnit {
         TimerUnit {
@@ -34,11 +48,21 @@ impl TimerUnit {
             ticks_per_inc: 1024,
             enabled: false,
             tima_inc_counter: 0,
-            div_inc_counter: 0
+            div_inc_counter: 0,
+            overflow_pending: false,
+            interrupts: None,
         }
     }
 
-    pub fn update(&mut self, cpu_ticks:u16 , is:&mut InterruptStatus) {
+    /// Sets the interrupt status the timer requests its interrupt from when
+    /// it is ticked through the bus.
+    pub fn set_interrupts(&mut self, interrupts: Option<Rc<RefCell<InterruptStatus>>>) {
+        self.interrupts = interrupts;
+    }
+
+    /// Advances the timer by `cpu_ticks` clock ticks, incrementing DIV and
+    /// TIMA and requesting the timer interrupt whenever TIMA overflows.
+    pub fn execute_ticks(&mut self, cpu_ticks:u16 , is:&mut InterruptStatus) {
         // Update div counter 
         // TODO account for stop instruction.
         self.div_inc_counter += cpu_ticks;
@@ -56,15 +80,42 @@ impl TimerUnit {
                 let ticks = self.tima_inc_counter / self.ticks_per_inc;
                 self.tima_inc_counter %= self.ticks_per_inc;
 
-                // If we are wrapping
-                if ticks + self.tima as u16 >= 256 {
-                    self.tima = self.tima.wrapping_add(ticks as u8) + self.tma;
-                    is.request_timer();
-                } else {
-                    self.tima += ticks as u8;
+                for _ in 0..ticks {
+                    self.increment_tima();
                 }
             }
         }
+
+        if self.overflow_pending {
+            self.overflow_pending = false;
+            is.request_timer();
+        }
+    }
+
+    /// Sets TAC, along with the rate and enable derived from it.
+    fn tac_write(&mut self, value:u8) {
+        self.tac = value;
+        self.ticks_per_inc = 
+            match value & 0b11 {
+                0 => 1024,
+                1 => 16,
+                2 => 64,
+                3 => 256,
+                _ => {0}
+            };
+        self.enabled = value & 0b100 != 0;
+    }
+
+    /// Increments TIMA once, reloading it from TMA and flagging the timer
+    /// interrupt when it overflows.
+    fn increment_tima(&mut self) {
+        match self.tima.checked_add(1) {
+            Some(tima) => self.tima = tima,
+            None => {
+                self.tima = self.tma;
+                self.overflow_pending = true;
+            }
+        }
     }
 }
 
@@ -73,7 +124,16 @@ impl BusRW for TimerUnit {
     fn bus_write8(&mut self, addr: usize, value:u8){
        match addr {
             DIV_REG_ADDR => {
+                // DIV and TIMA are both driven from the same internal
+                // divider, so clearing it restarts both counters. If the bit
+                // selected by TAC was set, clearing it is seen as a falling
+                // edge and TIMA increments early.
+                if self.enabled && self.tima_inc_counter >= self.ticks_per_inc / 2 {
+                    self.increment_tima();
+                }
                 self.div = 0;
+                self.div_inc_counter = 0;
+                self.tima_inc_counter = 0;
             }
             TIMA_REG_ADDR => {
                 println!("TIMA write: {}", value);
@@ -83,16 +143,7 @@ impl BusRW for TimerUnit {
                 self.tma = value;
             }
             TAC_REG_ADDR => {
-                self.tac = value;
-                self.ticks_per_inc = 
-                    match value & 0b11 {
-                        0 => 1024,
-                        1 => 16,
-                        2 => 64,
-                        3 => 256,
-                        _ => {0}
-                    };
-                self.enabled = value & 0b100 != 0;
+                self.tac_write(value);
 
                 println!("Timer enabled: {}", self.enabled);
                 println!("Timer rate: {}", self.ticks_per_inc);
@@ -103,22 +154,73 @@ impl BusRW for TimerUnit {
 
     fn bus_read8(&mut self, addr: usize) -> u8 {
         match addr {
-            DIV_REG_ADDR => {
-                self.div
-            }
             TIMA_REG_ADDR => {
                 println!("tima read: {}", self.tima);
                 self.tima
             }
-            TMA_REG_ADDR => {
-                self.tma
-            }
-            TAC_REG_ADDR => {
-                self.tac
+            DIV_REG_ADDR | TMA_REG_ADDR | TAC_REG_ADDR => {
+                self.bus_peek8(addr)
             }
             _ => {panic!("TimerUnit: Unknown read at address {:#X}", addr);}
         }
     }
+
+    /// Same as execute_ticks, using the interrupt status given to
+    /// set_interrupts. Without one the timer still counts, but its
+    /// interrupt requests are dropped.
+    fn tick(&mut self, ticks: u32) {
+        match self.interrupts.clone() {
+            Some(interrupts) => self.execute_ticks(ticks as u16, &mut interrupts.borrow_mut()),
+            None => self.execute_ticks(ticks as u16, &mut InterruptStatus::new()),
+        }
+    }
+
+    fn bus_peek8(&self, addr: usize) -> u8 {
+        match addr {
+            DIV_REG_ADDR => self.div,
+            TIMA_REG_ADDR => self.tima,
+            TMA_REG_ADDR => self.tma,
+            TAC_REG_ADDR => self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+impl Snapshotable for TimerUnit {
+    fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(TimerUnit::STATE_MAGIC, TimerUnit::STATE_VERSION);
+        w.write_u8(self.div);
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+        w.write_u16(self.tima_inc_counter);
+        w.write_u16(self.div_inc_counter);
+        w.write_bool(self.overflow_pending);
+        w.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(data, TimerUnit::STATE_MAGIC)?;
+        if version != TimerUnit::STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut timer = TimerUnit::new();
+        timer.div = r.read_u8()?;
+        timer.tima = r.read_u8()?;
+        timer.tma = r.read_u8()?;
+        timer.tac_write(r.read_u8()?);
+        timer.tima_inc_counter = r.read_u16()?;
+        timer.div_inc_counter = r.read_u16()?;
+        timer.overflow_pending = r.read_bool()?;
+        r.finish()?;
+        timer.interrupts = self.interrupts.take();
+        *self = timer;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -138,11 +240,11 @@ mod test{
         let (mut tu, mut is) = get_test_pack();
 
         tu.bus_write8(super::TAC_REG_ADDR, 0x03);
-        tu.update(4, &mut is);
+        tu.execute_ticks(4, &mut is);
         assert_eq!(tu.tima_inc_counter, 0);
 
         tu.bus_write8(super::TAC_REG_ADDR, 0x5);
-        tu.update(4, &mut is);
+        tu.execute_ticks(4, &mut is);
         assert_eq!(tu.tima_inc_counter, 4);
     }
 
@@ -151,9 +253,9 @@ mod test{
         let (mut tu, mut is) = get_test_pack();
         tu.bus_write8(super::TAC_REG_ADDR, 0b101);
 
-        tu.update(15, &mut is);
+        tu.execute_ticks(15, &mut is);
         assert_eq!(tu.tima, 0);
-        tu.update(1, &mut is);
+        tu.execute_ticks(1, &mut is);
         assert_eq!(tu.tima, 1);
     }
 
@@ -165,18 +267,141 @@ mod test{
         tu.bus_write8(super::TMA_REG_ADDR, tma);
 
         for _ in 0..255{
-            tu.update(1024, &mut is);
+            tu.execute_ticks(1024, &mut is);
         }
         assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 255);
 
         // Enough here to tick twice, should wrap tick into tma.
-        tu.update(2049, &mut is);
+        tu.execute_ticks(2049, &mut is);
         assert_eq!(tu.tima, tma+1);
 
         // Partial tick of 1023 should cause update due to one extra tick.
-        tu.update(1023, &mut is);
+        tu.execute_ticks(1023, &mut is);
         assert_eq!(tu.tima, tma+2);
     }
+
+    #[test]
+    fn timer_tac_clock_selects(){
+        for &(tac, period) in [(0b100u8, 1024u16), (0b101, 16), (0b110, 64), (0b111, 256)].iter() {
+            let (mut tu, mut is) = get_test_pack();
+            tu.bus_write8(super::TAC_REG_ADDR, tac);
+
+            tu.execute_ticks(period - 1, &mut is);
+            assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 0, "TAC {:#b}", tac);
+            tu.execute_ticks(1, &mut is);
+            assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 1, "TAC {:#b}", tac);
+            tu.execute_ticks(period * 3, &mut is);
+            assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 4, "TAC {:#b}", tac);
+        }
+    }
+
+    #[test]
+    fn timer_overflow_reloads_tma_and_requests_interrupt(){
+        let (mut tu, mut is) = get_test_pack();
+        tu.bus_write8(super::TAC_REG_ADDR, 0b101);
+        tu.bus_write8(super::TMA_REG_ADDR, 0xF0);
+        tu.bus_write8(super::TIMA_REG_ADDR, 0xFE);
+
+        tu.execute_ticks(16, &mut is);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 0xFF);
+        assert!(!is.is_timer_active());
+
+        tu.execute_ticks(16, &mut is);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 0xF0);
+        assert!(is.is_timer_active());
+    }
+
+    #[test]
+    fn div_increments_every_256_ticks(){
+        let (mut tu, mut is) = get_test_pack();
+
+        tu.execute_ticks(255, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 0);
+        tu.execute_ticks(1, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 1);
+        tu.execute_ticks(256 * 4, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 5);
+    }
+
+    #[test]
+    fn div_write_resets_divider(){
+        let (mut tu, mut is) = get_test_pack();
+        tu.bus_write8(super::TAC_REG_ADDR, 0b111);
+
+        tu.execute_ticks(256 + 100, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 1);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 1);
+
+        // Any write clears DIV and restarts the divider from zero.
+        tu.bus_write8(super::DIV_REG_ADDR, 0x55);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 0);
+        tu.execute_ticks(255, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 0);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 1);
+        tu.execute_ticks(1, &mut is);
+        assert_eq!(tu.bus_read8(super::DIV_REG_ADDR), 1);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 2);
+    }
+
+    #[test]
+    fn div_write_falling_edge_increments_tima(){
+        let (mut tu, mut is) = get_test_pack();
+        tu.bus_write8(super::TAC_REG_ADDR, 0b101);
+        tu.bus_write8(super::TIMA_REG_ADDR, 0xFF);
+
+        // Past the halfway point of the 16 tick period the selected divider
+        // bit is high, so resetting DIV causes an early increment.
+        tu.execute_ticks(8, &mut is);
+        tu.bus_write8(super::DIV_REG_ADDR, 0);
+        assert_eq!(tu.bus_read8(super::TIMA_REG_ADDR), 0);
+
+        // The overflow interrupt is raised on the next update.
+        assert!(!is.is_timer_active());
+        tu.execute_ticks(1, &mut is);
+        assert!(is.is_timer_active());
+    }
+
+    #[test]
+    fn timer_save_state_round_trip(){
+        let (mut tu, mut is) = get_test_pack();
+        tu.bus_write8(super::TAC_REG_ADDR, 0b101);
+        tu.bus_write8(super::TMA_REG_ADDR, 0x40);
+        tu.execute_ticks(300, &mut is);
+        let state = tu.save_state();
+
+        let mut restored = TimerUnit::new();
+        restored.load_state(&state).unwrap();
+        for addr in super::DIV_REG_ADDR..=super::TAC_REG_ADDR {
+            assert_eq!(restored.bus_read8(addr), tu.bus_read8(addr));
+        }
+
+        // The rate derived from TAC and the counters carry on in step.
+        tu.execute_ticks(10, &mut is);
+        restored.execute_ticks(10, &mut is);
+        assert_eq!(restored.bus_read8(super::TIMA_REG_ADDR), tu.bus_read8(super::TIMA_REG_ADDR));
+        assert!(restored.load_state(&state[..4]).is_err());
+    }
+
+    #[test]
+    fn timer_tick_requests_through_handle(){
+        let interrupts = Rc::new(RefCell::new(InterruptStatus::new()));
+        interrupts.borrow_mut().isrmask = 0xFF;
+        let mut tu = TimerUnit::new();
+        tu.set_interrupts(Some(interrupts.clone()));
+        tu.bus_write8(super::TAC_REG_ADDR, 0b101);
+        tu.bus_write8(super::TIMA_REG_ADDR, 0xFF);
+
+        tu.tick(16);
+        assert!(interrupts.borrow().is_timer_active());
+
+        // The handle survives a restore.
+        interrupts.borrow_mut().isrreq = 0;
+        let state = tu.save_state();
+        tu.load_state(&state).unwrap();
+        tu.bus_write8(super::TIMA_REG_ADDR, 0xFF);
+        tu.tick(16);
+        assert!(interrupts.borrow().is_timer_active());
+    }
 }
 
 impl Default for TimerUnit{
@@ -20,6 +20,7 @@ use sdl2::joystick::*;
 use dirtydmg_core::dmg::Dmg;
 use dirtydmg_core::input::Button;
 use dirtydmg_core::interface::ScanlineBuffer;
+use dirtydmg_core::ppu::{LCD_WIDTH, LCD_HEIGHT};
 use dirtydmg_core::sound::AudioChannel;
 
 fn load_file(filepath: &str) -> Result<Vec<u8>, std::io::Error>
@@ -78,7 +79,7 @@ struct DmgSurfaceRenderer {
 
 impl DmgSurfaceRenderer{
     fn new(format:PixelFormatEnum) -> DmgSurfaceRenderer{
-        let surface = Surface::new(160, 144, format).unwrap();
+        let surface = Surface::new(LCD_WIDTH as u32, LCD_HEIGHT as u32, format).unwrap();
         let colors: [Color;4] = [
             Color{r: 255, g:255, b:255, a: 0},
             Color{r: 170, g:170, b:170, a: 0},
@@ -166,6 +167,11 @@ fn main() {
     println!("ROM PATH: {}", path);
 
     let mut dmg = Dmg::new();
+    // Test ROMs report their results over serial.
+    dmg.set_serial_output_hook(Some(Box::new(|byte| {
+        print!("{}", byte as char);
+        std::io::stdout().flush().unwrap();
+    })));
 
     // Load the rom into the DMG.
     let rom_data = load_file(path);
@@ -219,7 +225,7 @@ fn main() {
     println!("Buffer size = {}", audio_queue.spec().samples);
     audio_queue.resume();
 
-    let window = video.window("Dirty DMG", 160 * scale, 144 * scale)
+    let window = video.window("Dirty DMG", LCD_WIDTH as u32 * scale, LCD_HEIGHT as u32 * scale)
         .position_centered()
         .build()
         .unwrap();
@@ -241,7 +247,7 @@ fn main() {
         while audio_queue.size() > 512 {
            std::thread::sleep(Duration::from_millis(1));
         }
-        dmg.update();
+        dmg.step();
 
         if let Some((left, right)) = dmg.apu.as_ref().borrow_mut().get_sample() {
             buf.push(left);